@@ -2,7 +2,7 @@ extern crate dione_lib;
 
 use criterion::{Criterion, criterion_group, criterion_main, Throughput};
 
-use dione_lib::hashing::cryptographic::sha512_hash_bytes;
+use dione_lib::hashing::cryptographic::{sha512_hash_bytes, blake3_hash_bytes};
 
 fn adler_bench(c: &mut Criterion) {
 	{
@@ -71,5 +71,26 @@ fn sha512_bench(c: &mut Criterion) {
 	}
 }
 
-criterion_group!(benches, adler_bench, seahash_bench, sha512_bench);
+fn blake3_bench(c: &mut Criterion) {
+	{
+		const SIZE: usize = 1_000;
+
+		let mut group = c.benchmark_group("blake3-1000b");
+		group.throughput(Throughput::Bytes(SIZE as u64));
+		group.bench_function("blake3-1000b", |bencher| {
+			bencher.iter(|| blake3_hash_bytes(&[0; SIZE]));
+		});
+	}
+	{
+		const SIZE: usize = 1_000_000;
+
+		let mut group = c.benchmark_group("blake3-1MB");
+		group.throughput(Throughput::Bytes(SIZE as u64));
+		group.bench_function("blake3-1MB", |bencher| {
+			bencher.iter(|| blake3_hash_bytes(&[0; SIZE]));
+		});
+	}
+}
+
+criterion_group!(benches, adler_bench, seahash_bench, sha512_bench, blake3_bench);
 criterion_main!(benches);
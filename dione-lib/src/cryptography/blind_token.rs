@@ -0,0 +1,240 @@
+//! Anonymous tokens in the style of Privacy Pass, based on a verifiable oblivious PRF over P-256.
+//!
+//! The client blinds a random nonce ([TokenRequest]), the issuer signs the blinded element with its secret key
+//! ([IssuerKey::sign]) and proves that it used the key behind its public key. After unblinding, the client holds a
+//! [Token] the issuer can verify, but can't link to the issuance.
+
+use alloc::vec::Vec;
+use core::fmt;
+use p256::{CompressedPoint, FieldBytes, ProjectivePoint, Scalar};
+use p256::elliptic_curve::group::ff::{Field, PrimeField};
+use p256::elliptic_curve::group::GroupEncoding;
+use rand_core::{OsRng, RngCore};
+use serde::{Serialize, Deserialize};
+
+use crate::hashing::cryptographic::sha512_hash_bytes;
+
+pub const TOKEN_NONCE_LENGTH: usize = 32;
+
+/// Address the proof-of-work stamp of a token issuance is computed over.
+pub const ISSUE_TOKENS_ADDRESS: &[u8] = b"issue tokens";
+
+const HASH_TO_CURVE_CONTEXT: &[u8] = b"dione token hash to curve";
+const CHALLENGE_CONTEXT: &[u8] = b"dione token dleq";
+
+#[derive(Debug, PartialEq)]
+pub enum TokenError {
+	InvalidPoint,
+	InvalidScalar,
+	InvalidProof,
+}
+
+/// Token redeemable at the issuer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Token {
+	#[serde(with = "serde_bytes")]
+	pub nonce: Vec<u8>,
+	#[serde(with = "serde_bytes")]
+	pub signature: Vec<u8>,
+}
+
+/// Secret key of a node issuing tokens.
+#[derive(Clone)]
+pub struct IssuerKey {
+	secret: Scalar,
+}
+
+impl fmt::Debug for IssuerKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("IssuerKey")
+	}
+}
+
+impl IssuerKey {
+	pub fn generate() -> Self {
+		Self {
+			secret: Scalar::random(&mut OsRng),
+		}
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, TokenError> {
+		let secret = decode_scalar(bytes)?;
+		if secret == Scalar::zero() {
+			return Err(TokenError::InvalidScalar)
+		}
+		Ok(Self {
+			secret,
+		})
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self.secret.to_bytes().to_vec()
+	}
+
+	pub fn public_key(&self) -> Vec<u8> {
+		encode_point(&(ProjectivePoint::generator() * self.secret))
+	}
+
+	/// Signs the blinded element of a [TokenRequest]. Returns the signed element and the proof, that it was signed
+	/// with the key behind [IssuerKey::public_key].
+	pub fn sign(&self, blinded: &[u8]) -> Result<(Vec<u8>, Vec<u8>), TokenError> {
+		let blinded = decode_point(blinded)?;
+		let public = ProjectivePoint::generator() * self.secret;
+		let evaluated = blinded * self.secret;
+
+		let nonce = Scalar::random(&mut OsRng);
+		let a = ProjectivePoint::generator() * nonce;
+		let b = blinded * nonce;
+		let c = challenge(&public, &blinded, &evaluated, &a, &b);
+		let u = nonce - c * self.secret;
+
+		let mut proof = c.to_bytes().to_vec();
+		proof.extend_from_slice(&u.to_bytes());
+		Ok((encode_point(&evaluated), proof))
+	}
+
+	/// Checks that `token` was issued with this key. Preventing double spending is up to the caller.
+	pub fn verify_token(&self, token: &Token) -> bool {
+		match decode_point(&token.signature) {
+			Ok(d) => d == hash_to_curve(&token.nonce) * self.secret,
+			Err(_) => false,
+		}
+	}
+}
+
+/// Client side state of a token, while it is issued.
+pub struct TokenRequest {
+	nonce: Vec<u8>,
+	blind: Scalar,
+	blinded: ProjectivePoint,
+}
+
+impl Default for TokenRequest {
+	fn default() -> Self {
+		let mut nonce = alloc::vec![0u8; TOKEN_NONCE_LENGTH];
+		OsRng.fill_bytes(&mut nonce);
+		let blind = Scalar::random(&mut OsRng);
+		let blinded = hash_to_curve(&nonce) * blind;
+		Self {
+			nonce,
+			blind,
+			blinded,
+		}
+	}
+}
+
+impl TokenRequest {
+	/// Blinded element, that is sent to the issuer.
+	pub fn blinded(&self) -> Vec<u8> {
+		encode_point(&self.blinded)
+	}
+
+	/// Checks the proof of the issuer and unblinds the signed element.
+	pub fn finalize(self, public_key: &[u8], evaluated: &[u8], proof: &[u8]) -> Result<Token, TokenError> {
+		let public = decode_point(public_key)?;
+		let evaluated = decode_point(evaluated)?;
+		if proof.len() != 64 {
+			return Err(TokenError::InvalidProof)
+		}
+		let c = decode_scalar(&proof[..32])?;
+		let u = decode_scalar(&proof[32..])?;
+
+		let a = ProjectivePoint::generator() * u + public * c;
+		let b = self.blinded * u + evaluated * c;
+		if challenge(&public, &self.blinded, &evaluated, &a, &b) != c {
+			return Err(TokenError::InvalidProof)
+		}
+
+		let inverse: Option<Scalar> = self.blind.invert().into();
+		let signature = evaluated * inverse.ok_or(TokenError::InvalidScalar)?;
+		Ok(Token {
+			nonce: self.nonce,
+			signature: encode_point(&signature),
+		})
+	}
+}
+
+fn encode_point(point: &ProjectivePoint) -> Vec<u8> {
+	point.to_bytes().to_vec()
+}
+
+fn decode_point(bytes: &[u8]) -> Result<ProjectivePoint, TokenError> {
+	if bytes.len() != 33 {
+		return Err(TokenError::InvalidPoint)
+	}
+	let mut encoded = CompressedPoint::default();
+	encoded.copy_from_slice(bytes);
+	let point: Option<ProjectivePoint> = ProjectivePoint::from_bytes(&encoded).into();
+	point.ok_or(TokenError::InvalidPoint)
+}
+
+fn decode_scalar(bytes: &[u8]) -> Result<Scalar, TokenError> {
+	if bytes.len() != 32 {
+		return Err(TokenError::InvalidScalar)
+	}
+	let mut repr = FieldBytes::default();
+	repr.copy_from_slice(bytes);
+	Scalar::from_repr(repr).ok_or(TokenError::InvalidScalar)
+}
+
+/// Maps `nonce` to a point of unknown discrete logarithm by try-and-increment.
+fn hash_to_curve(nonce: &[u8]) -> ProjectivePoint {
+	let mut counter: u32 = 0;
+	loop {
+		let mut input = HASH_TO_CURVE_CONTEXT.to_vec();
+		input.extend_from_slice(nonce);
+		input.extend_from_slice(&counter.to_be_bytes());
+		let hash = sha512_hash_bytes(&input);
+		let mut candidate = CompressedPoint::default();
+		candidate[0] = 0x02;
+		candidate[1..].copy_from_slice(&hash[..32]);
+		let point: Option<ProjectivePoint> = ProjectivePoint::from_bytes(&candidate).into();
+		if let Some(d) = point {
+			return d
+		}
+		counter += 1;
+	}
+}
+
+/// Fiat-Shamir challenge of the proof, that `evaluated` and `public` share the same discrete logarithm.
+fn challenge(public: &ProjectivePoint, blinded: &ProjectivePoint, evaluated: &ProjectivePoint, a: &ProjectivePoint, b: &ProjectivePoint) -> Scalar {
+	let mut input = CHALLENGE_CONTEXT.to_vec();
+	for point in [&ProjectivePoint::generator(), public, blinded, evaluated, a, b].iter() {
+		input.extend_from_slice(&point.to_bytes());
+	}
+	let hash = sha512_hash_bytes(&input);
+	let mut repr = FieldBytes::default();
+	repr.copy_from_slice(&hash[..32]);
+	Scalar::from_bytes_reduced(&repr)
+}
+
+#[cfg(test)]
+mod blind_token_test {
+	use crate::cryptography::blind_token::{IssuerKey, TokenRequest, TokenError};
+
+	#[test]
+	fn issue_and_verify_test() {
+		let key = IssuerKey::generate();
+		let request = TokenRequest::default();
+		let (evaluated, proof) = key.sign(&request.blinded()).unwrap();
+		let token = request.finalize(&key.public_key(), &evaluated, &proof).unwrap();
+		assert!(key.verify_token(&token));
+		assert!(!IssuerKey::generate().verify_token(&token));
+	}
+
+	#[test]
+	fn reject_foreign_key_test() {
+		let key = IssuerKey::generate();
+		let request = TokenRequest::default();
+		let (evaluated, proof) = key.sign(&request.blinded()).unwrap();
+		let res = request.finalize(&IssuerKey::generate().public_key(), &evaluated, &proof);
+		assert_eq!(res.unwrap_err(), TokenError::InvalidProof);
+	}
+
+	#[test]
+	fn key_bytes_test() {
+		let key = IssuerKey::generate();
+		let restored = IssuerKey::from_bytes(&key.to_bytes()).unwrap();
+		assert_eq!(key.public_key(), restored.public_key());
+	}
+}
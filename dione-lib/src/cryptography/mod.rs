@@ -2,3 +2,4 @@ pub mod sharing;
 pub mod symetric;
 pub mod key_exchange;
 pub mod ratchet;
+pub mod blind_token;
\ No newline at end of file
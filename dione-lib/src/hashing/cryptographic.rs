@@ -1,6 +1,9 @@
 use alloc::vec::Vec;
 
 use digest::Digest;
+use hmac::{Hmac, Mac, NewMac};
+
+type HmacSha512 = Hmac<ring_compat::digest::Sha512>;
 
 pub fn sha512_hash_bytes(data: &[u8]) -> Vec<u8> {
 	let mut hasher = ring_compat::digest::Sha512::new();
@@ -8,6 +11,18 @@ pub fn sha512_hash_bytes(data: &[u8]) -> Vec<u8> {
 	Digest::finalize_reset(&mut hasher).to_vec()
 }
 
+pub fn blake3_hash_bytes(data: &[u8]) -> Vec<u8> {
+	blake3::hash(data).as_bytes().to_vec()
+}
+
+/// Keyed hash (HMAC-SHA512) of `data`. Keys of any length are accepted.
+pub fn hmac_sha512_hash_bytes(key: &[u8], data: &[u8]) -> Vec<u8> {
+	let mut mac = HmacSha512::new_from_slice(key)
+		.expect("HMAC accepts keys of any length");
+	mac.update(data);
+	mac.finalize().into_bytes().to_vec()
+}
+
 #[cfg(test)]
 mod sha512_test {
 	use crate::hashing::cryptographic::sha512_hash_bytes;
@@ -30,3 +45,44 @@ mod sha512_test {
 		assert_ne!(number1, number2)
 	}
 }
+
+#[cfg(test)]
+mod blake3_test {
+	use crate::hashing::cryptographic::blake3_hash_bytes;
+
+	#[test]
+	fn blake3_hash_test_vector() {
+		let expected = [
+			0xaf, 0x13, 0x49, 0xb9, 0xf5, 0xf9, 0xa1, 0xa6, 0xa0, 0x40, 0x4d, 0xea, 0x36, 0xdc, 0xc9, 0x49,
+			0x9b, 0xcb, 0x25, 0xc9, 0xad, 0xc1, 0x12, 0xb7, 0xcc, 0x9a, 0x93, 0xca, 0xe4, 0x1f, 0x32, 0x62,
+		];
+		assert_eq!(blake3_hash_bytes(b""), expected.to_vec())
+	}
+
+	#[test]
+	fn blake3_hash_test_nq() {
+		let number1 = blake3_hash_bytes(b"Hello World");
+		let number2 = blake3_hash_bytes(b"Hallo World2");
+		assert_ne!(number1, number2)
+	}
+}
+
+#[cfg(test)]
+mod hmac_sha512_test {
+	use crate::hashing::cryptographic::hmac_sha512_hash_bytes;
+
+	#[test]
+	fn hmac_sha512_hash_test_eq() {
+		let key = b"an example very very secret key.";
+		let number1 = hmac_sha512_hash_bytes(key, b"Hello World");
+		let number2 = hmac_sha512_hash_bytes(key, b"Hello World");
+		assert_eq!(number1, number2)
+	}
+
+	#[test]
+	fn hmac_sha512_hash_test_key_nq() {
+		let number1 = hmac_sha512_hash_bytes(b"an example very very secret key.", b"Hello World");
+		let number2 = hmac_sha512_hash_bytes(b"An example very very secret key.", b"Hello World");
+		assert_ne!(number1, number2)
+	}
+}
@@ -1,2 +1,95 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::hash::Hasher;
+
+use digest::Digest;
+
 pub mod cryptographic;
 pub mod non_cryptographic;
+pub mod pow;
+
+/// Algorithms shares can be hashed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+	Sha512,
+	Adler32,
+	Seahash,
+	Blake3,
+}
+
+/// Hashes `data` with `algorithm`.
+pub fn hash_bytes(algorithm: HashAlgorithm, data: &[u8]) -> Vec<u8> {
+	match algorithm {
+		HashAlgorithm::Sha512 => cryptographic::sha512_hash_bytes(data),
+		HashAlgorithm::Adler32 => non_cryptographic::adler_hash_bytes(data),
+		HashAlgorithm::Seahash => non_cryptographic::seahash_hash_bytes(data),
+		HashAlgorithm::Blake3 => cryptographic::blake3_hash_bytes(data),
+	}
+}
+
+/// Hashes data fed piece by piece, e.g. a share arriving in chunks.
+///
+/// The result is the same as hashing all pieces at once with the `*_hash_bytes` functions.
+pub enum StreamHasher {
+	Sha512(ring_compat::digest::Sha512),
+	Adler32(adler::Adler32),
+	Seahash(seahash::SeaHasher),
+	Blake3(Box<blake3::Hasher>),
+}
+
+impl StreamHasher {
+	pub fn new(algorithm: HashAlgorithm) -> Self {
+		match algorithm {
+			HashAlgorithm::Sha512 => Self::Sha512(ring_compat::digest::Sha512::new()),
+			HashAlgorithm::Adler32 => Self::Adler32(adler::Adler32::new()),
+			HashAlgorithm::Seahash => Self::Seahash(seahash::SeaHasher::new()),
+			HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+		}
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		match self {
+			Self::Sha512(d) => d.update(data),
+			Self::Adler32(d) => d.write_slice(data),
+			Self::Seahash(d) => d.write(data),
+			Self::Blake3(d) => {
+				d.update(data);
+			}
+		}
+	}
+
+	pub fn finalize(self) -> Vec<u8> {
+		match self {
+			Self::Sha512(d) => d.finalize().to_vec(),
+			Self::Adler32(d) => d.checksum().to_be_bytes().to_vec(),
+			Self::Seahash(d) => d.finish().to_be_bytes().to_vec(),
+			Self::Blake3(d) => d.finalize().as_bytes().to_vec(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod stream_hasher_test {
+	use crate::hashing::{hash_bytes, HashAlgorithm, StreamHasher};
+	use crate::hashing::cryptographic::{sha512_hash_bytes, blake3_hash_bytes};
+	use crate::hashing::non_cryptographic::{adler_hash_bytes, seahash_hash_bytes};
+
+	#[test]
+	fn stream_hasher_matches_hash_bytes() {
+		let data: alloc::vec::Vec<u8> = (0..1000u32).map(|e| (e * 7) as u8).collect();
+		let hashes = [
+			(HashAlgorithm::Sha512, sha512_hash_bytes(&data)),
+			(HashAlgorithm::Adler32, adler_hash_bytes(&data)),
+			(HashAlgorithm::Seahash, seahash_hash_bytes(&data)),
+			(HashAlgorithm::Blake3, blake3_hash_bytes(&data)),
+		];
+		for (algorithm, hash) in hashes {
+			let mut hasher = StreamHasher::new(algorithm);
+			for chunk in data.chunks(77) {
+				hasher.update(chunk);
+			}
+			assert_eq!(hash_bytes(algorithm, &data), hash, "{:?}", algorithm);
+			assert_eq!(hasher.finalize(), hash, "{:?}", algorithm);
+		}
+	}
+}
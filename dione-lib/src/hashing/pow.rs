@@ -0,0 +1,67 @@
+//! Hashcash-style proof of work bound to a share.
+//!
+//! A stamp is a nonce for which the SHA-512 hash of the address, the digest of the content and the nonce
+//! starts with at least `difficulty` zero bits. Minting takes about 2^`difficulty` hashes, verifying a single one.
+
+use alloc::vec::Vec;
+
+use crate::hashing::cryptographic::sha512_hash_bytes;
+
+const POW_CONTEXT: &[u8] = b"dione pow";
+
+fn stamp_hash(address: &[u8], content_digest: &[u8], nonce: u64) -> Vec<u8> {
+	let mut input = Vec::with_capacity(POW_CONTEXT.len() + address.len() + content_digest.len() + 8);
+	input.extend_from_slice(POW_CONTEXT);
+	input.extend_from_slice(address);
+	input.extend_from_slice(content_digest);
+	input.extend_from_slice(&nonce.to_be_bytes());
+	sha512_hash_bytes(&input)
+}
+
+/// Number of leading zero bits of `hash`.
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+	let mut zeros = 0;
+	for byte in hash {
+		zeros += byte.leading_zeros();
+		if *byte != 0 {
+			break;
+		}
+	}
+	zeros
+}
+
+/// Checks that `nonce` is a stamp of at least `difficulty` bits for the share.
+pub fn verify(address: &[u8], content_digest: &[u8], nonce: u64, difficulty: u32) -> bool {
+	leading_zero_bits(&stamp_hash(address, content_digest, nonce)) >= difficulty
+}
+
+/// Searches a stamp of at least `difficulty` bits for the share.
+pub fn mint(address: &[u8], content_digest: &[u8], difficulty: u32) -> u64 {
+	let mut nonce = 0;
+	while !verify(address, content_digest, nonce, difficulty) {
+		nonce += 1;
+	}
+	nonce
+}
+
+#[cfg(test)]
+mod pow_test {
+	use crate::hashing::cryptographic::sha512_hash_bytes;
+	use crate::hashing::pow::{leading_zero_bits, mint, verify};
+
+	#[test]
+	fn leading_zero_bits_test() {
+		assert_eq!(leading_zero_bits(&[0, 0b0001_0000, 0xff]), 11);
+		assert_eq!(leading_zero_bits(&[0, 0]), 16);
+		assert_eq!(leading_zero_bits(&[0x80]), 0);
+	}
+
+	#[test]
+	fn mint_verify_test() {
+		let digest = sha512_hash_bytes(b"Hello World");
+		let nonce = mint(b"address", &digest, 8);
+		assert_eq!(nonce, 510);
+		assert!(verify(b"address", &digest, nonce, 8));
+		assert!(!verify(b"other address", &digest, nonce, 8));
+	}
+}
@@ -1,10 +1,12 @@
-use crate::message_storage::ServerAddressType;
+use crate::message_storage::{ServerAddressType, HashType, InfoResponse};
 use sled::Db;
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
 use crate::KNOWN_HOSTS_KEY;
 use serde::{Serialize, Deserialize};
-use tokio::runtime::Runtime;
-use crate::net::{get_server_for_address, NetError, get_server_for_message};
+use tonic::Code;
+use crate::net::{get_server_for_address, NetError, get_server_for_message, get_info, Connector};
 use rand::rngs::OsRng;
 use rand::prelude::*;
 use thiserror::Error;
@@ -23,6 +25,64 @@ pub enum HostError {
 	},
 	#[error("No Server for message found")]
 	NoServerForMessage,
+	#[error("Server {address} speaks an incompatible protocol")]
+	Incompatible {
+		address: String,
+	},
+}
+
+/// Protocol versions of nodes this library can talk to.
+///
+/// Nodes before version 2 ignore the requested hash type and would answer with receipts the client can't verify, nodes
+/// before version 3 answer WatchAddresses with a different message.
+const SUPPORTED_PROTOCOL_VERSIONS: RangeInclusive<u32> = 3..=3;
+
+/// Age after which the info of a node is requested again, as limits and capabilities change while it runs.
+const INFO_REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Version, limits and capabilities a node reported.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NodeInfo {
+	pub protocol_version: u32,
+	pub server_version: String,
+	pub peer_id: String,
+	pub listen_addresses: Vec<String>,
+	pub hash_types: Vec<i32>,
+	pub max_share_size: u64,
+	pub default_ttl: u64,
+	pub max_ttl: u64,
+	pub free_capacity: Option<u64>,
+	pub pow_difficulty: u32,
+	pub issues_tokens: bool,
+	pub requires_tokens: bool,
+	pub proxies: bool,
+}
+
+impl From<InfoResponse> for NodeInfo {
+	fn from(info: InfoResponse) -> Self {
+		Self {
+			protocol_version: info.protocol_version,
+			server_version: info.server_version,
+			peer_id: info.peer_id,
+			listen_addresses: info.listen_addresses,
+			hash_types: info.hash_types,
+			max_share_size: info.max_share_size,
+			default_ttl: info.default_ttl,
+			max_ttl: info.max_ttl,
+			free_capacity: info.free_capacity,
+			pow_difficulty: info.pow_difficulty,
+			issues_tokens: info.issues_tokens,
+			requires_tokens: info.requires_tokens,
+			proxies: info.proxies,
+		}
+	}
+}
+
+impl NodeInfo {
+	/// Whether the node speaks a supported protocol version and hashes with SHA-512.
+	pub fn is_compatible(&self) -> bool {
+		SUPPORTED_PROTOCOL_VERSIONS.contains(&self.protocol_version) && self.hash_types.contains(&(HashType::Sha512 as i32))
+	}
 }
 
 #[derive(Serialize, Deserialize, Hash, Eq, PartialEq, Clone)]
@@ -42,7 +102,10 @@ impl Host {
 
 pub struct KnownHosts {
 	db: Db,
-	hosts: HashSet<Host>
+	hosts: HashSet<Host>,
+	/// Info per node address, with the time it was requested.
+	infos: HashMap<String, (Instant, NodeInfo)>,
+	info_refresh: Duration,
 }
 
 impl KnownHosts {
@@ -53,9 +116,71 @@ impl KnownHosts {
 		Self {
 			db,
 			hosts,
+			infos: Default::default(),
+			info_refresh: INFO_REFRESH_INTERVAL,
+		}
+	}
+
+	/// Info of the node at `address`. It is requested again once the recorded info is older than the refresh interval.
+	pub fn info(&mut self, connector: &Connector, address: &str) -> Result<NodeInfo, HostError> {
+		if let Some(d) = self.recorded_info(address) {
+			return Ok(d.clone())
+		}
+		let info = NodeInfo::from(get_info(connector, address.to_owned())?);
+		self.infos.insert(address.to_owned(), (Instant::now(), info.clone()));
+		Ok(info)
+	}
+
+	/// Recorded info of the node at `address`, unless it is due for a refresh.
+	fn recorded_info(&self, address: &str) -> Option<&NodeInfo> {
+		match self.infos.get(address) {
+			Some((requested_at, d)) if requested_at.elapsed() < self.info_refresh => Some(d),
+			_ => None,
+		}
+	}
+
+	/// Nodes predating the Info request are incompatible as well.
+	fn is_compatible(&mut self, connector: &Connector, address: &str) -> Result<bool, HostError> {
+		match self.info(connector, address) {
+			Ok(d) => Ok(d.is_compatible()),
+			Err(HostError::NetError { source }) if source.code() == Some(Code::Unimplemented) => Ok(false),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Chooses a random compatible host. Unreachable and incompatible hosts are removed.
+	fn choose_host(&mut self, connector: &Connector) -> Result<Host, HostError> {
+		loop {
+			let server = match self.hosts.iter().choose(&mut OsRng) {
+				Some(d) => d.to_owned(),
+				None => {
+					return Err(HostError::NoHosts);
+				}
+			};
+			match self.is_compatible(connector, &server.address) {
+				Ok(true) => return Ok(server),
+				Ok(false) | Err(HostError::NetError { source: NetError::TonicError { .. } }) => self.remove(server).unwrap(),
+				Err(e) => return Err(e),
+			}
 		}
 	}
 
+	/// Fails if the server responsible for a share is incompatible, as no other server can take its place.
+	fn check_target(&mut self, connector: &Connector, address: &str) -> Result<(), HostError> {
+		if !self.is_compatible(connector, address)? {
+			return Err(HostError::Incompatible {
+				address: address.to_owned(),
+			})
+		}
+		Ok(())
+	}
+
+	/// A compatible known host serving shares it doesn't hold itself, if there is one.
+	pub fn proxy_host(&mut self, connector: &Connector) -> Option<Host> {
+		let hosts: Vec<Host> = self.hosts.iter().cloned().collect();
+		hosts.into_iter().find(|e| matches!(self.info(connector, &e.address), Ok(d) if d.proxies && d.is_compatible()))
+	}
+
 	pub fn add(&mut self, host: Host) -> anyhow::Result<()> {
 		let current: Vec<u8> = self.db.get(KNOWN_HOSTS_KEY)?.unwrap().to_vec();
 		let mut current: HashSet<Host> = bincode::deserialize(&current)?;
@@ -72,28 +197,27 @@ impl KnownHosts {
 		current.remove(&host);
 		let current_bytes = bincode::serialize(&current)?;
 		self.hosts = current;
+		self.infos.remove(&host.address);
 		let _ = self.db.insert(KNOWN_HOSTS_KEY, current_bytes)?;
 		Ok(())
 	}
 
-	pub fn get_server_for_address(&mut self, rt: &Runtime, message_address: &[u8]) -> Result<(ServerAddressType, String), HostError> {
+	pub fn get_server_for_address(&mut self, connector: &Connector, message_address: &[u8]) -> Result<(ServerAddressType, String), HostError> {
 		loop {
-			let server = match self.hosts.iter().choose(&mut OsRng) {
-				Some(d) => d.to_owned(),
-				None => {
-					return Err(HostError::NoHosts);
+			let server = self.choose_host(connector)?;
+			break match get_server_for_address(connector, server.address.clone(), message_address) {
+				Ok(d) => {
+					self.check_target(connector, &d.1)?;
+					Ok(d)
 				}
-			};
-			break match get_server_for_address(rt, server.address.clone(), message_address) {
-				Ok(d) => Ok(d),
 				Err(e) => {
 					match e {
 						NetError::TonicError { .. } => {
 							self.remove(server.clone()).unwrap();
 							continue;
 						}
-						NetError::ServerResponseErr(e) => {
-							let err = HostError::NetError {source: NetError::ServerResponseErr(e)};
+						e => {
+							let err = HostError::NetError {source: e};
 							return Err(err)
 						}
 					}
@@ -102,29 +226,26 @@ impl KnownHosts {
 		}
 	}
 
-	pub fn get_server_for_message(&mut self, rt: &Runtime, message_address: &[u8]) -> Result<(ServerAddressType, String), HostError> {
+	pub fn get_server_for_message(&mut self, connector: &Connector, message_address: &[u8]) -> Result<(ServerAddressType, String), HostError> {
+		let mut servers = self.get_servers_for_message(connector, message_address)?;
+		Ok(servers.remove(0))
+	}
+
+	/// Every server holding a replica of the share. Never empty.
+	pub fn get_servers_for_message(&mut self, connector: &Connector, message_address: &[u8]) -> Result<Vec<(ServerAddressType, String)>, HostError> {
 		loop {
-			let server = match self.hosts.iter().choose(&mut OsRng) {
-				Some(d) => d.to_owned(),
-				None => {
-					return Err(HostError::NoHosts);
-				}
-			};
-			break match get_server_for_message(rt, server.address.clone(), message_address) {
-				Ok(d) => Ok(match d.get(0) {
-					Some(d) => d.to_owned(),
-					None => {
-						return Err(HostError::NoServerForMessage)
-					}
-				}),
+			let server = self.choose_host(connector)?;
+			break match get_server_for_message(connector, server.address.clone(), message_address) {
+				Ok(d) if d.is_empty() => Err(HostError::NoServerForMessage),
+				Ok(d) => Ok(d),
 				Err(e) => {
 					match e {
 						NetError::TonicError { .. } => {
 							self.remove(server.clone()).unwrap();
 							continue;
 						}
-						NetError::ServerResponseErr(e) => {
-							let err = HostError::NetError {source: NetError::ServerResponseErr(e)};
+						e => {
+							let err = HostError::NetError {source: e};
 							return Err(err)
 						}
 					}
@@ -146,12 +267,80 @@ fn get_server_for_address_test() {
 	known_hosts.add(host_1).unwrap();
 	known_hosts.add(host_2).unwrap();
 
-	let rt = tokio::runtime::Builder::new_multi_thread()
+	let connector = Connector::new(tokio::runtime::Builder::new_multi_thread()
 		.enable_all()
 		.build()
-		.unwrap();
+		.unwrap());
 
 	let message_address = b"binarydata";
 
-	let _ = known_hosts.get_server_for_address(&rt, message_address).unwrap();
-}
\ No newline at end of file
+	let _ = known_hosts.get_server_for_address(&connector, message_address).unwrap();
+}
+#[test]
+fn node_info_compatibility() {
+	let info = NodeInfo {
+		protocol_version: 3,
+		server_version: String::from("0.1.0"),
+		peer_id: String::new(),
+		listen_addresses: Vec::new(),
+		hash_types: vec![HashType::Sha512 as i32, HashType::Adler32 as i32],
+		max_share_size: 4_194_304,
+		default_ttl: 604_800,
+		max_ttl: 2_592_000,
+		free_capacity: None,
+		pow_difficulty: 0,
+		issues_tokens: false,
+		requires_tokens: false,
+		proxies: false,
+	};
+	assert!(info.is_compatible());
+
+	let newer = NodeInfo {
+		protocol_version: 4,
+		..info.clone()
+	};
+	assert!(!newer.is_compatible());
+
+	let without_sha512 = NodeInfo {
+		hash_types: vec![HashType::Adler32 as i32],
+		..info
+	};
+	assert!(!without_sha512.is_compatible());
+}
+
+#[test]
+fn refresh_and_forget_host_infos() {
+	let db = sled::Config::new().temporary(true).open().unwrap();
+	let host = Host::from_net_prop(&ServerAddressType::Clear, "http://127.0.0.1:8010");
+	let info = NodeInfo {
+		protocol_version: 3,
+		server_version: String::from("0.1.0"),
+		peer_id: String::new(),
+		listen_addresses: Vec::new(),
+		hash_types: vec![HashType::Sha512 as i32],
+		max_share_size: 4_194_304,
+		default_ttl: 604_800,
+		max_ttl: 2_592_000,
+		free_capacity: None,
+		pow_difficulty: 0,
+		issues_tokens: false,
+		requires_tokens: false,
+		proxies: false,
+	};
+
+	let mut known_hosts = KnownHosts::new(db.clone());
+	known_hosts.add(host.clone()).unwrap();
+	known_hosts.infos.insert(host.address.clone(), (Instant::now(), info.clone()));
+	let fresh = known_hosts.recorded_info(&host.address).cloned();
+	known_hosts.remove(host.clone()).unwrap();
+	let removed = known_hosts.recorded_info(&host.address).cloned();
+
+	let mut stale_hosts = KnownHosts::new(db);
+	stale_hosts.info_refresh = Duration::from_secs(0);
+	stale_hosts.infos.insert(host.address.clone(), (Instant::now(), info.clone()));
+	let stale = stale_hosts.recorded_info(&host.address).cloned();
+
+	assert_eq!(fresh, Some(info));
+	assert_eq!(removed, None);
+	assert_eq!(stale, None);
+}
@@ -9,20 +9,28 @@ use std::path::Path;
 use crate::user::User;
 use dione_lib::cryptography::key_exchange::{IdentityKey, Key};
 use crate::host::{Host, KnownHosts};
-use tokio::runtime::Runtime;
-use crate::net::{get_server_for_address, save_message, get_message};
+use crate::net::{get_server_for_address, save_message, get_message, delete_message, save_messages, get_messages, watch_addresses, get_pow_difficulty, pow_stamp, issue_tokens, rate_token, Connector, NetError, CHUNKED_THRESHOLD};
+use crate::message_storage::SaveMessageRequest;
 use std::collections::HashMap;
 use uuid::Uuid;
 use crate::session::Session;
 use crate::bundle::{BundleBuilder, AliceBob, PartnerBundle, BobBundle, AliceBundle, HostBundle};
 use dione_lib::cryptography::ratchet::AddressShare;
+use dione_lib::hashing::cryptographic::{hmac_sha512_hash_bytes, sha512_hash_bytes};
 use serde::{Deserialize, Serialize};
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use crate::wallet::Wallet;
 
-mod net;
+pub mod net;
 pub mod session;
 mod bundle;
 mod user;
 mod host;
+mod wallet;
+
+pub use crate::host::NodeInfo;
+pub use crate::message_storage::HashType;
 
 const HOST_USER_KEY: &[u8] = b"host_user";
 const HOST_IDENTITY_KEY_KEY: &[u8] = b"host_identity_key";
@@ -30,6 +38,15 @@ const KNOWN_HOSTS_KEY: &[u8] = b"known_hosts";
 const NUMBER_SHARES_KEY: &[u8] = b"number_shares";
 const HOST_BUNDLE_KEY: &[u8] = b"host_bundle";
 
+/// Highest proof-of-work difficulty the client is willing to compute stamps for.
+const MAX_POW_DIFFICULTY: u32 = 28;
+
+/// Number of tokens requested once a server demands tokens and the wallet holds none of it.
+const TOKENS_PER_ISSUE: usize = 32;
+
+/// Stream of incoming shares, as returned by [Client::watch_message].
+pub type ShareStream = Pin<Box<dyn Stream<Item = anyhow::Result<AddressShare>> + Send>>;
+
 pub(crate) mod message_storage {
     tonic::include_proto!("messagestorage");
 }
@@ -37,13 +54,18 @@ pub(crate) mod message_storage {
 /// Client for external usage
 pub struct Client {
     db: Db,
-    runtime: Runtime,
+    connector: Connector,
     host_user: User,
     host_identity_key: IdentityKey,
     host_bundle: Option<HostBundle>,
     number_shares: usize,
     known_hosts: KnownHosts,
     sessions: HashMap<Uuid, Session>,
+    /// Last own share of the session setup per peer, with its server. Deleted once the peer answered to it.
+    setup_shares: HashMap<Uuid, (String, Vec<u8>)>,
+    pow_difficulties: HashMap<String, u32>,
+    wallet: Wallet,
+    hash_type: HashType,
 }
 
 impl Client {
@@ -61,7 +83,9 @@ impl Client {
 
         let known_hosts = KnownHosts::new(db.clone());
 
-        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        let wallet = Wallet::new(&db)?;
+
+        let connector = Connector::new(tokio::runtime::Builder::new_multi_thread().enable_all().build()?);
 
         let number_shares_bytes = number_shares.to_be_bytes().to_vec();
         let _ = db.insert(NUMBER_SHARES_KEY, number_shares_bytes)?;
@@ -73,15 +97,19 @@ impl Client {
             host_bundle: None,
             number_shares,
             known_hosts,
-            runtime,
+            connector,
             sessions: Default::default(),
+            setup_shares: Default::default(),
+            pow_difficulties: Default::default(),
+            wallet,
+            hash_type: HashType::Sha512,
         };
         Ok(client)
     }
 
     /// Initial connection to a server. This is necessary to perform all other functionalities and tests the network connection.
     pub fn connect(&mut self, server_address: String) -> anyhow::Result<()> {
-        match get_server_for_address(&self.runtime, server_address.clone(), b"") {
+        match get_server_for_address(&self.connector, server_address.clone(), b"") {
             Ok(d) => {
                 let addressed_host = Host::from_net_prop(&message_storage::ServerAddressType::Clear, server_address.as_str());
                 self.known_hosts.add(addressed_host)?;
@@ -95,6 +123,17 @@ impl Client {
         Ok(())
     }
 
+    /// Version, limits and capabilities of the server.
+    pub fn node_info(&mut self, server_address: &str) -> anyhow::Result<NodeInfo> {
+        Ok(self.known_hosts.info(&self.connector, server_address)?)
+    }
+
+    /// Trusts the PEM encoded CA certificate at `path` for servers with an `https://` address, e.g. in test networks.
+    pub fn trust_ca<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        self.connector.set_custom_ca(Some(std::fs::read(path)?));
+        Ok(())
+    }
+
     /// Initial necessary step for establishing a connection to other [Client]. Provides the own message bundle to servers.
     pub fn provide_bundle(&mut self) -> anyhow::Result<()> {
         let host_uuid = self.host_user
@@ -122,8 +161,12 @@ impl Client {
         self.host_bundle = Some(host_bundle);
 
 
-        let (_, server_address) = self.known_hosts.get_server_for_address(&self.runtime, &host_uuid)?;
-        let _ = save_message(&self.runtime, server_address, &host_uuid, &bundle_bytes)?;
+        let secret = self.setup_delete_secret(&host_uuid);
+
+        let (_, server_address) = self.known_hosts.get_server_for_address(&self.connector, &host_uuid)?;
+        // A previously provided bundle would block the address.
+        let _ = delete_message(&self.connector, server_address.clone(), &host_uuid, &secret);
+        self.save_share(server_address, &host_uuid, &bundle_bytes, Some(sha512_hash_bytes(&secret)))?;
         Ok(())
     }
 
@@ -131,8 +174,7 @@ impl Client {
     pub fn init_one_session(&mut self, id: Uuid) -> anyhow::Result<()> {
         let peer_uuid = id.as_bytes().to_vec();
 
-        let (_, server_address) = self.known_hosts.get_server_for_message(&self.runtime, &peer_uuid)?;
-        let peer_bundle_bytes = get_message(&self.runtime, server_address, &peer_uuid)?.content;
+        let (_, peer_bundle_bytes) = self.fetch_share(&peer_uuid)?;
 
         let bundle = BundleBuilder::default()
             .identity_key(self.host_identity_key.clone())
@@ -163,8 +205,8 @@ impl Client {
         host_peer_key.append(&mut seperator);
         host_peer_key.append(&mut peer_uuid.clone());
 
-        let (_, server_address) = self.known_hosts.get_server_for_address(&self.runtime, &host_peer_key)?;
-        let _ = save_message(&self.runtime, server_address, &host_peer_key, &host_bundle_bytes).unwrap();
+        let (_, server_address) = self.known_hosts.get_server_for_address(&self.connector, &host_peer_key)?;
+        self.save_setup_share(id, server_address, host_peer_key, &host_bundle_bytes)?;
         Ok(())
     }
 
@@ -176,16 +218,18 @@ impl Client {
         host_peer_key.append(&mut seperator);
         host_peer_key.append(&mut self.host_user.id.as_bytes().to_vec());
 
-        let (_, server_address) = self.known_hosts.get_server_for_message(&self.runtime, &host_peer_key)?;
-
-        let host_bundle = &self.host_bundle.as_ref().unwrap().bundle;
+        let (server_address, peer_bundle_bytes) = self.fetch_share(&host_peer_key)?;
 
+        let host_bundle = match &self.host_bundle {
+            Some(d) => &d.bundle,
+            None => return Err(anyhow::Error::msg("Bundle has to be provided before initializing a session")),
+        };
 
-        let peer_bundle_bytes = get_message(&self.runtime, server_address.clone(), &host_peer_key)?.content;
         let peer_bundle: BobBundle = BobBundle::from_bytes(&peer_bundle_bytes)?;
 
         let (kind, magic_ratchet) = host_bundle.init(&peer_bundle)?;
 
+
         let mut session = Session::new(kind, magic_ratchet);
 
 
@@ -197,7 +241,7 @@ impl Client {
         let mut ender = b"!".to_vec();
         host_peer_key.append(&mut ender);
 
-        let _ = save_message(&self.runtime, server_address, &host_peer_key, &init_message_bytes)?;
+        self.save_setup_share(id, server_address, host_peer_key, &init_message_bytes)?;
 
         Ok(())
     }
@@ -213,21 +257,24 @@ impl Client {
         let mut ender = b"!".to_vec();
         host_peer_key.append(&mut ender);
 
-        let (_, server_address) = self.known_hosts.get_server_for_message(&self.runtime, &host_peer_key)?;
-
-        let init_message_bytes = get_message(&self.runtime, server_address, &host_peer_key)?.content;
+        let (_, init_message_bytes) = self.fetch_share(&host_peer_key)?;
         let init_message: Vec<AddressShare> = bincode::deserialize(&init_message_bytes)?;
 
-        let session = self.sessions.get_mut(&id).unwrap();
+        let session = self.session(id)?;
         session.process_init_message(init_message);
 
+        // The init message answers the bundle, so the peer is done with it.
+        self.delete_setup_share(id);
+
+        let session = self.session(id)?;
+
         let init_message = session.make_init_message()?;
         let init_message_bytes = bincode::serialize(&init_message)?;
 
         host_peer_key.append(&mut ender);
 
-        let (_, server_address) = self.known_hosts.get_server_for_address(&self.runtime, &host_peer_key)?;
-        let _ = save_message(&self.runtime, server_address, &host_peer_key, &init_message_bytes).unwrap();
+        let (_, server_address) = self.known_hosts.get_server_for_address(&self.connector, &host_peer_key)?;
+        self.save_setup_share(id, server_address, host_peer_key, &init_message_bytes)?;
 
         self.provide_bundle()?;
 
@@ -245,13 +292,14 @@ impl Client {
         host_peer_key.append(&mut ender);
         host_peer_key.append(&mut ender);
 
-        let (_, server_address) = self.known_hosts.get_server_for_message(&self.runtime, &host_peer_key)?;
-        let init_message_bytes = get_message(&self.runtime, server_address, &host_peer_key)?.content;
+        let (_, init_message_bytes) = self.fetch_share(&host_peer_key)?;
         let init_message = bincode::deserialize(&init_message_bytes)?;
 
-        let session = self.sessions.get_mut(&id).unwrap();
+        let session = self.session(id)?;
         session.process_init_message(init_message);
 
+        self.delete_setup_share(id);
+
         self.provide_bundle()?;
 
         Ok(())
@@ -259,32 +307,290 @@ impl Client {
 
     /// Send message to Uuid. Established connection is necessary.
     pub fn send_message(&mut self, id: Uuid, content: &[u8]) -> anyhow::Result<()> {
-        let session = self.sessions.get_mut(&id).unwrap();
+        let session = self.session(id)?;
         let address_shares = session.send_message(content)?;
+        let mut by_server: HashMap<String, Vec<SaveMessageRequest>> = HashMap::new();
         for address_share in address_shares {
             let address = address_share.0;
             let share = address_share.1;
-            let (_, server_address) = self.known_hosts.get_server_for_address(&self.runtime, &address)?;
-            let _ = save_message(&self.runtime, server_address, &address, &share)?;
+            let delete_commitment = sha512_hash_bytes(&delete_secret(content, &address));
+            let (_, server_address) = self.known_hosts.get_server_for_address(&self.connector, &address)?;
+            by_server.entry(server_address).or_default().push(self.share_request(&address, &share, Some(delete_commitment)));
+        }
+        for (server_address, messages) in by_server {
+            // Large shares are uploaded in chunks, so they can't be part of a batch.
+            let (mut single, mut batch): (Vec<_>, Vec<_>) = messages.into_iter()
+                .partition(|e| e.content.len() > CHUNKED_THRESHOLD);
+            if batch.len() == 1 {
+                single.append(&mut batch);
+            }
+            for message in single {
+                self.save_share(server_address.clone(), &message.addr, &message.content, message.delete_commitment)?;
+            }
+            if batch.is_empty() {
+                continue;
+            }
+            for message in batch.iter_mut() {
+                self.admit(&server_address, message)?;
+            }
+            let responses = save_messages(&self.connector, server_address.clone(), batch.clone())?;
+            for (message, response) in batch.into_iter().zip(responses) {
+                match response.code {
+                    200 => {}
+                    // Admission failed, saving the share on its own refills tokens or recomputes the stamp.
+                    401 | 402 => {
+                        self.pow_difficulties.remove(&server_address);
+                        self.save_share(server_address.clone(), &message.addr, &message.content, message.delete_commitment)?;
+                    }
+                    d => return Err(NetError::from_item_code(d).into()),
+                }
+            }
         }
         Ok(())
     }
 
     /// Receiving message from Uuid. Established connection and send message necessary.
     pub fn recv_message(&mut self, id: Uuid) -> anyhow::Result<Vec<u8>> {
-        let session = self.sessions.get_mut(&id).unwrap();
+        let session = self.session(id)?;
         let addresses = session.next_address()?;
+        let mut by_server: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        for address in &addresses {
+            let (_, server_address) = self.known_hosts.get_server_for_message(&self.connector, address)?;
+            by_server.entry(server_address).or_default().push(address.to_vec());
+        }
+        // Shares by address, together with the server they were fetched from.
+        let mut contents: HashMap<Vec<u8>, (String, Vec<u8>)> = HashMap::new();
+        for (server_address, mut message_addresses) in by_server {
+            if message_addresses.len() == 1 {
+                let address = message_addresses.remove(0);
+                let d = self.fetch_share(&address)?;
+                contents.insert(address, d);
+                continue;
+            }
+            let items = match get_messages(&self.connector, server_address.clone(), message_addresses.clone()) {
+                Ok(d) => d,
+                Err(e) if e.should_fail_over() => {
+                    for address in message_addresses {
+                        let d = self.fetch_share(&address)?;
+                        contents.insert(address, d);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            for item in items {
+                match item.content {
+                    Some(d) if item.code == 200 => {
+                        contents.insert(item.addr, (server_address.clone(), d));
+                    }
+                    // Too large for the batch, has to be downloaded in chunks.
+                    None if item.code == 416 => {
+                        let d = get_message(&self.connector, server_address.clone(), &item.addr)?.content;
+                        contents.insert(item.addr, (server_address.clone(), d));
+                    }
+                    // Another replica might have it.
+                    None if item.code == 404 => {
+                        let d = self.fetch_share(&item.addr)?;
+                        contents.insert(item.addr, d);
+                    }
+                    _ => return Err(NetError::from_item_code(item.code).into()),
+                }
+            }
+        }
         let mut parts = Vec::new();
+        let mut servers = Vec::new();
         for address in addresses {
-            let (_, server_address) = self.known_hosts.get_server_for_message(&self.runtime, &address)?;
-            let d = get_message(&self.runtime, server_address, &address)?.content;
+            let (server_address, d) = contents.remove(&address[..]).ok_or_else(|| NetError::from_item_code(404))?;
             let address_share = (address, d);
             parts.push(address_share);
+            servers.push(server_address);
+        }
+        self.decrypt_and_delete(id, &parts, servers)
+    }
+
+    /// Watches the addresses of the next message from Uuid, so the message doesn't have to be polled.
+    ///
+    /// The stream yields each share as soon as a server stores it. Once every share arrived, pass them to [Client::recv_shares].
+    pub fn watch_message(&mut self, id: Uuid) -> anyhow::Result<ShareStream> {
+        let session = self.session(id)?;
+        let addresses = session.next_address()?;
+        let mut by_server: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        for address in &addresses {
+            let (_, server_address) = self.known_hosts.get_server_for_message(&self.connector, address)?;
+            by_server.entry(server_address).or_default().push(address.to_vec());
+        }
+        let mut streams = Vec::new();
+        for (server_address, message_addresses) in by_server {
+            streams.push(Box::pin(watch_addresses(&self.connector, server_address, message_addresses)?));
         }
-        let d = session.recv_message(&parts)?;
+        let shares = futures::stream::select_all(streams).map(|e| {
+            let d = e?;
+            if d.addr.len() != 32 {
+                return Err(anyhow::Error::msg("Server returned share for invalid address"))
+            }
+            let mut address = [0u8; 32];
+            address.copy_from_slice(&d.addr);
+            Ok((address, d.content))
+        });
+        Ok(Box::pin(shares))
+    }
+
+    /// Receiving message from Uuid out of the shares collected from [Client::watch_message].
+    pub fn recv_shares(&mut self, id: Uuid, parts: Vec<AddressShare>) -> anyhow::Result<Vec<u8>> {
+        let mut servers = Vec::new();
+        for (address, _) in &parts {
+            let (_, server_address) = self.known_hosts.get_server_for_message(&self.connector, address)?;
+            servers.push(server_address);
+        }
+        self.decrypt_and_delete(id, &parts, servers)
+    }
+
+    fn decrypt_and_delete(&mut self, id: Uuid, parts: &[AddressShare], servers: Vec<String>) -> anyhow::Result<Vec<u8>> {
+        let session = self.session(id)?;
+        let d = session.recv_message(parts)?;
+        // Shares are only deleted once the message was decrypted.
+        for ((address, _), server_address) in parts.iter().zip(servers) {
+            self.delete_processed(server_address, address, &d);
+        }
+        // A message of the peer shows that it finished the session setup.
+        self.delete_setup_share(id);
         Ok(d)
     }
 
+    /// Fetches the share from the first of its replicas that delivers it. Returns the share with the server it came from.
+    ///
+    /// A known host proxying shares is asked first, which spares looking up and contacting the replicas.
+    fn fetch_share(&mut self, address: &[u8]) -> anyhow::Result<(String, Vec<u8>)> {
+        if let Some(proxy) = self.known_hosts.proxy_host(&self.connector) {
+            match get_message(&self.connector, proxy.address.clone(), address) {
+                Ok(d) => return Ok((proxy.address, d.content)),
+                Err(e) if e.should_fail_over() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let servers = self.known_hosts.get_servers_for_message(&self.connector, address)?;
+        let mut last_error = NetError::from_item_code(404);
+        for (_, server_address) in servers {
+            match get_message(&self.connector, server_address.clone(), address) {
+                Ok(d) => return Ok((server_address, d.content)),
+                Err(e) if e.should_fail_over() => last_error = e,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(last_error.into())
+    }
+
+    /// Proof-of-work difficulty demanded by the server, cached after the first request.
+    fn pow_difficulty(&mut self, server_address: &str) -> anyhow::Result<u32> {
+        if let Some(d) = self.pow_difficulties.get(server_address) {
+            return Ok(*d)
+        }
+        let difficulty = get_pow_difficulty(&self.connector, server_address.to_owned())?;
+        if difficulty > MAX_POW_DIFFICULTY {
+            return Err(anyhow::Error::msg(format!("Server {} demands proof of work of {} bits, more than the maximum of {}", server_address, difficulty, MAX_POW_DIFFICULTY)))
+        }
+        self.pow_difficulties.insert(server_address.to_owned(), difficulty);
+        Ok(difficulty)
+    }
+
+    /// Requests `count` tokens from the server and keeps them in the wallet. Returns the number of tokens held for the server.
+    ///
+    /// The key the server signs with is pinned on first use, tokens signed under another key are refused.
+    pub fn issue_tokens(&mut self, server_address: String, count: usize) -> anyhow::Result<usize> {
+        let pow_difficulty = self.pow_difficulty(&server_address)?;
+        let issuer_key = self.wallet.issuer_key(&server_address)?;
+        let (public_key, tokens) = issue_tokens(&self.connector, server_address.clone(), count, pow_difficulty, issuer_key.as_deref())?;
+        self.wallet.pin_issuer_key(&server_address, &public_key)?;
+        self.wallet.add(&server_address, tokens)?;
+        Ok(self.wallet.count(&server_address))
+    }
+
+    /// Hash type the servers are asked to use for the receipts of saved shares. SHA-512 by default.
+    pub fn set_hash_type(&mut self, hash_type: HashType) {
+        self.hash_type = hash_type;
+    }
+
+    fn share_request(&self, address: &[u8], content: &[u8], delete_commitment: Option<Vec<u8>>) -> SaveMessageRequest {
+        SaveMessageRequest {
+            addr: address.to_vec(),
+            content: content.to_vec(),
+            ttl: None,
+            delete_commitment,
+            pow_nonce: None,
+            token: None,
+            hash_type: Some(self.hash_type as i32),
+        }
+    }
+
+    /// Redeems a token of the server for the share, or stamps it with proof of work if the wallet holds none.
+    fn admit(&mut self, server_address: &str, request: &mut SaveMessageRequest) -> anyhow::Result<()> {
+        match self.wallet.take(server_address)? {
+            Some(d) => request.token = Some(rate_token(d)),
+            None => {
+                let pow_difficulty = self.pow_difficulty(server_address)?;
+                request.pow_nonce = pow_stamp(&request.addr, &request.content, pow_difficulty);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves a single share, redeeming a token of the server or with a proof-of-work stamp for the server.
+    fn save_share(&mut self, server_address: String, address: &[u8], content: &[u8], delete_commitment: Option<Vec<u8>>) -> anyhow::Result<()> {
+        let mut request = self.share_request(address, content, delete_commitment);
+        self.admit(&server_address, &mut request)?;
+        match save_message(&self.connector, server_address.clone(), request.clone()) {
+            // The server raised its difficulty since it was cached.
+            Err(NetError::PowRequired(_)) => {
+                self.pow_difficulties.remove(&server_address);
+                let pow_difficulty = self.pow_difficulty(&server_address)?;
+                request.token = None;
+                request.pow_nonce = pow_stamp(address, content, pow_difficulty);
+                let _ = save_message(&self.connector, server_address, request)?;
+            }
+            // The server demands tokens or refused ours, e.g. because they were spent already.
+            Err(NetError::TokenRequired(_)) => {
+                self.issue_tokens(server_address.clone(), TOKENS_PER_ISSUE)?;
+                request.pow_nonce = None;
+                request.token = self.wallet.take(&server_address)?.map(rate_token);
+                let _ = save_message(&self.connector, server_address, request)?;
+            }
+            d => {
+                let _ = d?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Secret authorizing the deletion of an own share of the session setup. It's derived from the identity key, as
+    /// the stored share is readable by anyone who knows its address.
+    fn setup_delete_secret(&self, address: &[u8]) -> Vec<u8> {
+        hmac_sha512_hash_bytes(&self.host_identity_key.to_bytes(), address)
+    }
+
+    /// Saves an own share of the session setup with `id`, replacing the previous one as the one to delete next.
+    fn save_setup_share(&mut self, id: Uuid, server_address: String, address: Vec<u8>, content: &[u8]) -> anyhow::Result<()> {
+        let delete_commitment = sha512_hash_bytes(&self.setup_delete_secret(&address));
+        self.save_share(server_address.clone(), &address, content, Some(delete_commitment))?;
+        self.setup_shares.insert(id, (server_address, address));
+        Ok(())
+    }
+
+    /// Deletes the last own share of the session setup with `id`. Failed deletions are left to expire.
+    fn delete_setup_share(&mut self, id: Uuid) {
+        if let Some((server_address, address)) = self.setup_shares.remove(&id) {
+            let _ = delete_message(&self.connector, server_address, &address, &self.setup_delete_secret(&address));
+        }
+    }
+
+    /// Deletes the share at `address` once `content` was processed. Failed deletions are left to expire.
+    fn delete_processed(&self, server_address: String, address: &[u8], content: &[u8]) {
+        let _ = delete_message(&self.connector, server_address, address, &delete_secret(content, address));
+    }
+
+    fn session(&mut self, id: Uuid) -> anyhow::Result<&mut Session> {
+        self.sessions.get_mut(&id).ok_or_else(|| anyhow::Error::msg(format!("No session established with {}", id)))
+    }
+
     /// Get clients Uuid.
     pub fn get_uuid(&self) -> Uuid {
         self.host_user.id
@@ -297,6 +603,12 @@ impl Client {
 
 }
 
+/// Secret authorizing the deletion of the message share at `address`. Only sender and receiver of the message can
+/// derive it, as `content` is the plaintext of the message and the stored shares are encrypted.
+fn delete_secret(content: &[u8], address: &[u8]) -> Vec<u8> {
+    hmac_sha512_hash_bytes(content, address)
+}
+
 #[derive(Deserialize, Serialize)]
 struct InitMessageBundle {
     host_bundle: Vec<u8>,
@@ -305,7 +617,23 @@ struct InitMessageBundle {
 
 #[cfg(test)]
 mod tests {
-    use crate::Client;
+    use crate::{Client, delete_secret};
+    use dione_lib::hashing::cryptographic::sha512_hash_bytes;
+
+    #[test]
+    fn reading_setup_share_does_not_allow_deletion() {
+        let client = Client::new("setup_share_test_client", 3).unwrap();
+        let address = b"host-peer!".to_vec();
+        let content = b"Init message as stored on the server";
+        let commitment = sha512_hash_bytes(&client.setup_delete_secret(&address));
+        drop(client);
+        std::fs::remove_dir_all("setup_share_test_client").unwrap();
+
+        // Everything a reader of the share knows.
+        assert_ne!(sha512_hash_bytes(&delete_secret(content, &address)), commitment);
+        assert_ne!(sha512_hash_bytes(&delete_secret(&address, content)), commitment);
+        assert_ne!(sha512_hash_bytes(content), commitment);
+    }
 
     #[test]
     #[ignore]
@@ -1,10 +1,24 @@
 use crate::message_storage::location_client::LocationClient;
 use crate::message_storage::message_storage_client::MessageStorageClient;
 use crate::message_storage::ServerLocRequest;
-use crate::message_storage::{SaveMessageRequest, SaveMessageResponse, GetMessageResponse, GetMessageRequest};
-use crate::message_storage::ServerAddressType;
-use tonic::{Request, Status};
+use crate::message_storage::{SaveMessageRequest, SaveMessageResponse, GetMessageResponse, GetMessageRequest, DeleteMessageRequest, DeleteMessageResponse};
+use crate::message_storage::{SaveMessagesRequest, GetMessagesRequest, GetMessagesItem, WatchAddressesRequest, SaveChunk, PowParamsRequest, IssueTokensRequest, IssueTokensResponse, RateToken, InfoRequest, InfoResponse};
+use crate::message_storage::{ServerAddressType, HashType};
+use tonic::{Request, Status, Code};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
 use tokio::runtime::Runtime;
+use prost::Message;
+use futures::{Stream, StreamExt};
+use dione_lib::hashing::{pow, hash_bytes, HashAlgorithm};
+use dione_lib::hashing::cryptographic::sha512_hash_bytes;
+use dione_lib::cryptography::blind_token::{Token, TokenRequest, ISSUE_TOKENS_ADDRESS};
+use std::future::Future;
+
+/// Shares larger than this are uploaded in chunks.
+pub const CHUNKED_THRESHOLD: usize = 1024 * 1024;
+
+/// Size of the chunks of an upload.
+const CHUNK_SIZE: usize = 64 * 1024;
 
 #[derive(Debug, thiserror::Error)]
 pub enum NetError {
@@ -13,10 +27,134 @@ pub enum NetError {
 		#[from]
 		source: tonic::transport::Error,
 	},
+	#[error("No share stored at the requested address (not delivered yet?) => {0:?}")]
+	NotFound(Status),
+	#[error("Server refused share, limits of server exhausted => {0:?}")]
+	ResourceExhausted(Status),
+	#[error("Different share already stored at the requested address => {0:?}")]
+	AlreadyExists(Status),
+	#[error("Server refused deletion, secret doesn't match => {0:?}")]
+	PermissionDenied(Status),
+	#[error("Server demands a valid proof-of-work stamp => {0:?}")]
+	PowRequired(Status),
+	#[error("Server demands a token issued by itself => {0:?}")]
+	TokenRequired(Status),
+	#[error("Server issued tokens with an invalid proof")]
+	InvalidTokenProof,
+	#[error("Server signed tokens with a different key than before")]
+	IssuerKeyChanged,
+	#[error("Receipt of the server doesn't match the saved share")]
+	ReceiptMismatch,
+	#[error("Received share doesn't match its digest")]
+	DigestMismatch,
 	#[error("Server threw error with responding status => {0:?}")]
 	ServerResponseErr(Status)
 }
 
+impl From<Status> for NetError {
+	fn from(status: Status) -> Self {
+		match status.code() {
+			Code::NotFound => Self::NotFound(status),
+			Code::ResourceExhausted => Self::ResourceExhausted(status),
+			Code::AlreadyExists => Self::AlreadyExists(status),
+			Code::PermissionDenied => Self::PermissionDenied(status),
+			Code::FailedPrecondition => Self::PowRequired(status),
+			Code::Unauthenticated => Self::TokenRequired(status),
+			_ => Self::ServerResponseErr(status),
+		}
+	}
+}
+
+impl NetError {
+	/// gRPC status code the server responded with, if the server was reached.
+	pub fn code(&self) -> Option<Code> {
+		self.status().map(|e| e.code())
+	}
+
+	/// Code of the [SaveMessageResponse] the server attached to a refused share, e.g. 413 for a share that is too large.
+	pub fn response_code(&self) -> Option<i32> {
+		let status = self.status()?;
+		SaveMessageResponse::decode(status.details()).ok().map(|e| e.code)
+	}
+
+	/// Whether another replica of the share might still serve the request, because the server didn't have it or couldn't be reached.
+	pub fn should_fail_over(&self) -> bool {
+		matches!(self, NetError::TonicError { .. } | NetError::NotFound(_))
+			|| self.code() == Some(Code::Unavailable)
+	}
+
+	/// Error for an item of a batch response that carries a code other than 200.
+	pub fn from_item_code(code: i32) -> Self {
+		let status_code = match code {
+			404 => Code::NotFound,
+			401 => Code::Unauthenticated,
+			402 => Code::FailedPrecondition,
+			403 => Code::PermissionDenied,
+			409 => Code::AlreadyExists,
+			413 | 429 | 507 => Code::ResourceExhausted,
+			416 => Code::OutOfRange,
+			_ => Code::Internal,
+		};
+		let details = SaveMessageResponse {
+			code,
+			hash: None,
+			hash_type: None,
+		};
+		let status = Status::with_details(status_code, format!("Server responded with code {}", code), details.encode_to_vec().into());
+		Self::from(status)
+	}
+
+	/// True if the server is fine, but holds no share at the address yet.
+	pub fn is_not_found(&self) -> bool {
+		matches!(self, Self::NotFound(_))
+	}
+
+	fn status(&self) -> Option<&Status> {
+		match self {
+			Self::TonicError { .. } | Self::InvalidTokenProof | Self::IssuerKeyChanged | Self::ReceiptMismatch | Self::DigestMismatch => None,
+			Self::NotFound(d) | Self::ResourceExhausted(d) | Self::AlreadyExists(d) | Self::PermissionDenied(d) | Self::PowRequired(d) | Self::TokenRequired(d) | Self::ServerResponseErr(d) => Some(d),
+		}
+	}
+}
+
+/// Runtime the requests to servers are run on, together with the TLS settings for `https://` hosts.
+pub struct Connector {
+	runtime: Runtime,
+	/// PEM encoded certificate of the CA `https://` hosts are verified against, instead of the system roots.
+	custom_ca: Option<Vec<u8>>,
+}
+
+impl Connector {
+	pub fn new(runtime: Runtime) -> Self {
+		Self {
+			runtime,
+			custom_ca: None,
+		}
+	}
+
+	/// Trusts the CA with the PEM encoded `certificate` for `https://` hosts, e.g. in test networks. `None` restores the system roots.
+	pub fn set_custom_ca(&mut self, certificate: Option<Vec<u8>>) {
+		self.custom_ca = certificate;
+	}
+
+	pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+		self.runtime.block_on(future)
+	}
+
+	/// Opens a channel to the server. Hosts with an `https://` address are connected via TLS.
+	async fn connect(&self, server_address: String) -> Result<Channel, tonic::transport::Error> {
+		let mut endpoint = Endpoint::new(server_address)?;
+		if endpoint.uri().scheme_str() == Some("https") {
+			let mut tls = ClientTlsConfig::new();
+			if let Some(d) = &self.custom_ca {
+				tls = tls.ca_certificate(Certificate::from_pem(d));
+			}
+			endpoint = endpoint.tls_config(tls)?;
+		}
+		endpoint.connect().await
+	}
+}
+
 impl From<i32> for ServerAddressType {
 	fn from(number: i32) -> Self {
 		match number {
@@ -32,8 +170,8 @@ impl From<i32> for ServerAddressType {
 }
 
 
-pub fn get_server_for_address(rt: &Runtime, server_address: String, message_address: &[u8]) -> Result<(ServerAddressType, String), NetError> {
-	let mut client = match rt.block_on(LocationClient::connect(server_address)) {
+pub fn get_server_for_address(connector: &Connector, server_address: String, message_address: &[u8]) -> Result<(ServerAddressType, String), NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(LocationClient::new) {
 		Ok(d) => d,
 		Err(e) => {
 			let err = NetError::TonicError {
@@ -47,11 +185,10 @@ pub fn get_server_for_address(rt: &Runtime, server_address: String, message_addr
 		addr: message_address.to_vec(),
 	});
 
-	let response = match rt.block_on(client.look_up(request)) {
+	let response = match connector.block_on(client.look_up(request)) {
 		Ok(d) => d,
 		Err(e) => {
-			let err = NetError::ServerResponseErr(e);
-			return Err(err)
+			return Err(NetError::from(e))
 		}
 	};
 	let response = response.into_inner();
@@ -60,8 +197,8 @@ pub fn get_server_for_address(rt: &Runtime, server_address: String, message_addr
 	Ok((addr_type, addr))
 }
 
-pub fn get_server_for_message(rt: &Runtime, server_address: String, message_address: &[u8]) -> Result<Vec<(ServerAddressType, String)>, NetError> {
-	let mut client = match rt.block_on(LocationClient::connect(server_address)) {
+pub fn get_server_for_message(connector: &Connector, server_address: String, message_address: &[u8]) -> Result<Vec<(ServerAddressType, String)>, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(LocationClient::new) {
 		Ok(d) => d,
 		Err(e) => {
 			let err = NetError::TonicError {
@@ -75,11 +212,10 @@ pub fn get_server_for_message(rt: &Runtime, server_address: String, message_addr
 		addr: message_address.to_vec(),
 	});
 
-	let response = match rt.block_on(client.message_look_up(request)) {
+	let response = match connector.block_on(client.message_look_up(request)) {
 		Ok(d) => d,
 		Err(e) => {
-			let err = NetError::ServerResponseErr(e);
-			return Err(err)
+			return Err(NetError::from(e))
 		}
 	};
 	let response = response.into_inner();
@@ -88,8 +224,47 @@ pub fn get_server_for_message(rt: &Runtime, server_address: String, message_addr
 	Ok(addresses)
 }
 
-pub fn save_message(rt: &Runtime, server_address: String, message_address: &[u8], content: &[u8]) -> Result<SaveMessageResponse, NetError> {
-	let mut client = match rt.block_on(MessageStorageClient::connect(server_address)) {
+impl From<HashType> for HashAlgorithm {
+	fn from(hash_type: HashType) -> Self {
+		match hash_type {
+			HashType::Sha512 => HashAlgorithm::Sha512,
+			HashType::Adler32 => HashAlgorithm::Adler32,
+			HashType::Seahash => HashAlgorithm::Seahash,
+			HashType::Blake3 => HashAlgorithm::Blake3,
+		}
+	}
+}
+
+/// Hash type and hash the server has to return for a saved share.
+type Receipt = (HashType, Vec<u8>);
+
+fn expected_receipt(request: &SaveMessageRequest) -> Receipt {
+	let hash_type = request.hash_type
+		.and_then(HashType::from_i32)
+		.unwrap_or(HashType::Sha512);
+	(hash_type, hash_bytes(hash_type.into(), &request.content))
+}
+
+/// Checks the receipt of the server against the locally computed hash of the share.
+fn verify_receipt(response: &SaveMessageResponse, receipt: &Receipt) -> Result<(), NetError> {
+	let (hash_type, hash) = receipt;
+	if response.hash_type != Some(*hash_type as i32) || response.hash.as_ref() != Some(hash) {
+		return Err(NetError::ReceiptMismatch)
+	}
+	Ok(())
+}
+
+/// Proof-of-work stamp for a share, or none if the server demands no proof of work.
+pub fn pow_stamp(message_address: &[u8], content: &[u8], pow_difficulty: u32) -> Option<u64> {
+	if pow_difficulty == 0 {
+		return None
+	}
+	Some(pow::mint(message_address, &sha512_hash_bytes(content), pow_difficulty))
+}
+
+/// Proof-of-work difficulty the server demands for saving shares.
+pub fn get_pow_difficulty(connector: &Connector, server_address: String) -> Result<u32, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
 		Ok(d) => d,
 		Err(e) => {
 			let err = NetError::TonicError {
@@ -99,25 +274,138 @@ pub fn save_message(rt: &Runtime, server_address: String, message_address: &[u8]
 		}
 	};
 
-	let request = Request::new(SaveMessageRequest {
-		addr: message_address.to_vec(),
-		content: content.to_vec(),
+	let request = Request::new(PowParamsRequest {});
+
+	let response = match connector.block_on(client.pow_params(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+
+	Ok(response.difficulty)
+}
+
+/// Signs `count` tokens at the server, which can later be redeemed there instead of proof-of-work stamps.
+///
+/// The server has to sign with `issuer_key` if it is given, as a server signing with a key per client could link the
+/// tokens to the client. Returns the public key the tokens were signed under, together with the tokens.
+pub fn issue_tokens(connector: &Connector, server_address: String, count: usize, pow_difficulty: u32, issuer_key: Option<&[u8]>) -> Result<(Vec<u8>, Vec<Token>), NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	let token_requests: Vec<TokenRequest> = (0..count).map(|_| TokenRequest::default()).collect();
+	let blinded: Vec<Vec<u8>> = token_requests.iter().map(|e| e.blinded()).collect();
+	let pow_nonce = pow_stamp(ISSUE_TOKENS_ADDRESS, &blinded.concat(), pow_difficulty);
+
+	let request = Request::new(IssueTokensRequest {
+		blinded,
+		pow_nonce,
 	});
 
-	let response = match rt.block_on(client.save_message(request)) {
+	let response = match connector.block_on(client.issue_tokens(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+	let tokens = finalize_tokens(token_requests, &response, issuer_key)?;
+
+	Ok((response.public_key, tokens))
+}
+
+/// Unblinds the tokens signed in `response`. Tokens signed under another key than `issuer_key` are refused.
+fn finalize_tokens(token_requests: Vec<TokenRequest>, response: &IssueTokensResponse, issuer_key: Option<&[u8]>) -> Result<Vec<Token>, NetError> {
+	if issuer_key.is_some_and(|e| e != response.public_key) {
+		return Err(NetError::IssuerKeyChanged)
+	}
+	if response.evaluated.len() != token_requests.len() || response.proofs.len() != token_requests.len() {
+		return Err(NetError::InvalidTokenProof)
+	}
+
+	token_requests.into_iter()
+		.zip(response.evaluated.iter().zip(&response.proofs))
+		.map(|(request, (evaluated, proof))| {
+			request.finalize(&response.public_key, evaluated, proof).map_err(|_| NetError::InvalidTokenProof)
+		})
+		.collect()
+}
+
+/// Converts a token into its wire format.
+pub fn rate_token(token: Token) -> RateToken {
+	RateToken {
+		nonce: token.nonce,
+		signature: token.signature,
+	}
+}
+
+/// Version, limits and capabilities of the server.
+pub fn get_info(connector: &Connector, server_address: String) -> Result<InfoResponse, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	let request = Request::new(InfoRequest {});
+
+	let response = match connector.block_on(client.info(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+
+	Ok(response)
+}
+
+/// Saves a share on the server and verifies the receipt. Shares above [CHUNKED_THRESHOLD] are uploaded in chunks.
+pub fn save_message(connector: &Connector, server_address: String, request: SaveMessageRequest) -> Result<SaveMessageResponse, NetError> {
+	if request.content.len() > CHUNKED_THRESHOLD {
+		return save_message_chunked(connector, server_address, request)
+	}
+
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
 		Ok(d) => d,
 		Err(e) => {
-			let err = NetError::ServerResponseErr(e);
+			let err = NetError::TonicError {
+				source: e,
+			};
 			return Err(err)
 		}
 	};
+
+	let receipt = expected_receipt(&request);
+
+	let response = match connector.block_on(client.save_message(Request::new(request))) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
 	let response = response.into_inner();
+	verify_receipt(&response, &receipt)?;
 
 	Ok(response)
 }
 
-pub fn get_message(rt: &Runtime, server_address: String, message_address: &[u8]) -> Result<GetMessageResponse, NetError> {
-	let mut client = match rt.block_on(MessageStorageClient::connect(server_address)) {
+/// Gets a share from the server. Falls back to downloading it in chunks if it is too large for a single message.
+pub fn get_message(connector: &Connector, server_address: String, message_address: &[u8]) -> Result<GetMessageResponse, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address.clone())).map(MessageStorageClient::new) {
 		Ok(d) => d,
 		Err(e) => {
 			let err = NetError::TonicError {
@@ -131,14 +419,279 @@ pub fn get_message(rt: &Runtime, server_address: String, message_address: &[u8])
 		addr: message_address.to_vec(),
 	});
 
-	let response = match rt.block_on(client.get_message(request)) {
+	let response = match connector.block_on(client.get_message(request)) {
+		Ok(d) => d,
+		Err(e) if e.code() == Code::OutOfRange => {
+			return get_message_chunked(connector, server_address, message_address)
+		}
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+
+	Ok(response)
+}
+
+/// Uploads a share in chunks and verifies the receipt. The first chunk carries the digest of the whole content, so
+/// the server can check the proof-of-work stamp before accepting the rest.
+pub fn save_message_chunked(connector: &Connector, server_address: String, request: SaveMessageRequest) -> Result<SaveMessageResponse, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	let receipt = expected_receipt(&request);
+
+	let mut chunks: Vec<SaveChunk> = request.content.chunks(CHUNK_SIZE)
+		.map(|e| SaveChunk {
+			addr: None,
+			ttl: None,
+			delete_commitment: None,
+			data: e.to_vec(),
+			digest: None,
+			pow_nonce: None,
+			token: None,
+			hash_type: None,
+		})
+		.collect();
+	if chunks.is_empty() {
+		chunks.push(SaveChunk {
+			addr: None,
+			ttl: None,
+			delete_commitment: None,
+			data: Vec::new(),
+			digest: None,
+			pow_nonce: None,
+			token: None,
+			hash_type: None,
+		});
+	}
+	chunks[0].addr = Some(request.addr);
+	chunks[0].ttl = request.ttl;
+	chunks[0].delete_commitment = request.delete_commitment;
+	chunks[0].pow_nonce = request.pow_nonce;
+	chunks[0].token = request.token;
+	chunks[0].hash_type = request.hash_type;
+	chunks[0].digest = Some(sha512_hash_bytes(&request.content));
+
+	let response = match connector.block_on(client.save_message_chunked(futures::stream::iter(chunks))) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+	verify_receipt(&response, &receipt)?;
+
+	Ok(response)
+}
+
+/// Downloads a share in chunks and checks it against the digest sent with the last chunk.
+pub fn get_message_chunked(connector: &Connector, server_address: String, message_address: &[u8]) -> Result<GetMessageResponse, NetError> {
+	let client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	connector.block_on(receive_chunked(client, message_address.to_vec()))
+}
+
+/// Receives the share at `message_address` in chunks from `client`.
+async fn receive_chunked(mut client: MessageStorageClient<Channel>, message_address: Vec<u8>) -> Result<GetMessageResponse, NetError> {
+	let request = Request::new(GetMessageRequest {
+		addr: message_address.clone(),
+	});
+
+	let mut stream = match client.get_message_chunked(request).await {
+		Ok(d) => d.into_inner(),
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+
+	let mut content = Vec::new();
+	let mut digest = None;
+	while let Some(chunk) = stream.message().await? {
+		content.extend_from_slice(&chunk.data);
+		digest = chunk.digest;
+	}
+	if digest != Some(sha512_hash_bytes(&content)) {
+		return Err(NetError::DigestMismatch)
+	}
+
+	Ok(GetMessageResponse {
+		addr: message_address,
+		content,
+	})
+}
+
+pub fn delete_message(connector: &Connector, server_address: String, message_address: &[u8], secret: &[u8]) -> Result<DeleteMessageResponse, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
 		Ok(d) => d,
 		Err(e) => {
-			let err = NetError::ServerResponseErr(e);
+			let err = NetError::TonicError {
+				source: e,
+			};
 			return Err(err)
 		}
 	};
+
+	let request = Request::new(DeleteMessageRequest {
+		addr: message_address.to_vec(),
+		secret: secret.to_vec(),
+	});
+
+	let response = match connector.block_on(client.delete_message(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
 	let response = response.into_inner();
 
 	Ok(response)
-}
\ No newline at end of file
+}
+
+/// Saves several shares on the same server with one request. Returns one response per share.
+pub fn save_messages(connector: &Connector, server_address: String, messages: Vec<SaveMessageRequest>) -> Result<Vec<SaveMessageResponse>, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	let receipts: Vec<Receipt> = messages.iter().map(expected_receipt).collect();
+
+	let request = Request::new(SaveMessagesRequest {
+		messages,
+	});
+
+	let response = match connector.block_on(client.save_messages(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+	for (response, receipt) in response.responses.iter().zip(&receipts) {
+		if response.code == 200 {
+			verify_receipt(response, receipt)?;
+		}
+	}
+
+	Ok(response.responses)
+}
+
+/// Gets several shares from the same server with one request. Returns one item per address.
+pub fn get_messages(connector: &Connector, server_address: String, message_addresses: Vec<Vec<u8>>) -> Result<Vec<GetMessagesItem>, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	let request = Request::new(GetMessagesRequest {
+		addrs: message_addresses,
+	});
+
+	let response = match connector.block_on(client.get_messages(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+	let response = response.into_inner();
+
+	Ok(response.items)
+}
+
+/// Watches the addresses on the server. The stream yields every share as soon as it is saved and ends once all were delivered.
+///
+/// Shares too large for a single message are downloaded in chunks.
+pub fn watch_addresses(connector: &Connector, server_address: String, message_addresses: Vec<Vec<u8>>) -> Result<impl Stream<Item = Result<GetMessageResponse, NetError>>, NetError> {
+	let mut client = match connector.block_on(connector.connect(server_address)).map(MessageStorageClient::new) {
+		Ok(d) => d,
+		Err(e) => {
+			let err = NetError::TonicError {
+				source: e,
+			};
+			return Err(err)
+		}
+	};
+
+	let request = Request::new(WatchAddressesRequest {
+		addrs: message_addresses,
+	});
+
+	let response = match connector.block_on(client.watch_addresses(request)) {
+		Ok(d) => d,
+		Err(e) => {
+			return Err(NetError::from(e))
+		}
+	};
+
+	let shares = response.into_inner().then(move |e| {
+		let client = client.clone();
+		async move {
+			let item = e?;
+			match item.content {
+				Some(d) if item.code == 200 => Ok(GetMessageResponse {
+					addr: item.addr,
+					content: d,
+				}),
+				None if item.code == 416 => receive_chunked(client, item.addr).await,
+				_ => Err(NetError::from_item_code(item.code)),
+			}
+		}
+	});
+
+	Ok(shares)
+}
+
+#[test]
+fn refuse_changed_issuer_key() {
+	use dione_lib::cryptography::blind_token::IssuerKey;
+
+	let pinned = IssuerKey::generate();
+	let other = IssuerKey::generate();
+	let sign = |key: &IssuerKey| {
+		let token_request = TokenRequest::default();
+		let (evaluated, proof) = key.sign(&token_request.blinded()).unwrap();
+		let response = IssueTokensResponse {
+			public_key: key.public_key(),
+			evaluated: vec![evaluated],
+			proofs: vec![proof],
+		};
+		(token_request, response)
+	};
+
+	let (token_request, response) = sign(&pinned);
+	let accepted = finalize_tokens(vec![token_request], &response, Some(&pinned.public_key()));
+	let (token_request, response) = sign(&other);
+	let refused = finalize_tokens(vec![token_request], &response, Some(&pinned.public_key()));
+	let (token_request, response) = sign(&other);
+	let first_use = finalize_tokens(vec![token_request], &response, None);
+
+	assert_eq!(accepted.unwrap().len(), 1);
+	assert!(matches!(refused, Err(NetError::IssuerKeyChanged)));
+	assert_eq!(first_use.unwrap().len(), 1);
+}
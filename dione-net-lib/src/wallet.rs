@@ -0,0 +1,89 @@
+use sled::{Db, Tree};
+use dione_lib::cryptography::blind_token::Token;
+
+const TOKENS_TREE: &str = "tokens";
+const ISSUER_KEYS_TREE: &str = "issuer_keys";
+
+/// Tokens issued by servers, kept until they are redeemed at the server that issued them.
+pub struct Wallet {
+	tokens: Tree,
+	/// Public key of each server, pinned when it issued tokens for the first time.
+	issuer_keys: Tree,
+}
+
+impl Wallet {
+	pub fn new(db: &Db) -> anyhow::Result<Self> {
+		let tokens = db.open_tree(TOKENS_TREE)?;
+		let issuer_keys = db.open_tree(ISSUER_KEYS_TREE)?;
+		Ok(Self {
+			tokens,
+			issuer_keys,
+		})
+	}
+
+	/// Public key the server has to sign tokens with, if it issued tokens before.
+	pub fn issuer_key(&self, server_address: &str) -> anyhow::Result<Option<Vec<u8>>> {
+		Ok(self.issuer_keys.get(server_address)?.map(|e| e.to_vec()))
+	}
+
+	/// Pins the public key of the server, unless a key is pinned already.
+	pub fn pin_issuer_key(&self, server_address: &str, public_key: &[u8]) -> anyhow::Result<()> {
+		let _ = self.issuer_keys.compare_and_swap(server_address, None as Option<&[u8]>, Some(public_key))?;
+		Ok(())
+	}
+
+	pub fn add(&self, server_address: &str, tokens: Vec<Token>) -> anyhow::Result<()> {
+		for token in tokens {
+			let key = token_key(server_address, &token.nonce);
+			let token_bytes = bincode::serialize(&token)?;
+			let _ = self.tokens.insert(key, token_bytes)?;
+		}
+		Ok(())
+	}
+
+	/// Removes a token of the server from the wallet, so it is only redeemed once.
+	pub fn take(&self, server_address: &str) -> anyhow::Result<Option<Token>> {
+		let prefix = server_prefix(server_address);
+		while let Some(e) = self.tokens.scan_prefix(&prefix).next() {
+			let (key, token_bytes) = e?;
+			// Another caller might have taken the token in the meantime.
+			if self.tokens.remove(key)?.is_some() {
+				return Ok(Some(bincode::deserialize(&token_bytes)?))
+			}
+		}
+		Ok(None)
+	}
+
+	pub fn count(&self, server_address: &str) -> usize {
+		self.tokens.scan_prefix(server_prefix(server_address)).count()
+	}
+}
+
+fn server_prefix(server_address: &str) -> Vec<u8> {
+	let mut prefix = server_address.as_bytes().to_vec();
+	prefix.push(0);
+	prefix
+}
+
+fn token_key(server_address: &str, nonce: &[u8]) -> Vec<u8> {
+	let mut key = server_prefix(server_address);
+	key.extend_from_slice(nonce);
+	key
+}
+
+#[test]
+fn take_tokens_per_server() {
+	let db = sled::Config::new().temporary(true).open().unwrap();
+	let wallet = Wallet::new(&db).unwrap();
+
+	let token = Token {
+		nonce: b"tokennonce".to_vec(),
+		signature: b"tokensignature".to_vec(),
+	};
+	wallet.add("http://127.0.0.1:8010", vec![token.clone()]).unwrap();
+
+	assert_eq!(wallet.count("http://127.0.0.1:8010"), 1);
+	assert_eq!(wallet.take("http://127.0.0.1:8000").unwrap(), None);
+	assert_eq!(wallet.take("http://127.0.0.1:8010").unwrap(), Some(token));
+	assert_eq!(wallet.take("http://127.0.0.1:8010").unwrap(), None);
+}
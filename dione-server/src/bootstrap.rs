@@ -0,0 +1,84 @@
+use tracing::*;
+
+use std::time::Duration;
+use libp2p::{Multiaddr, PeerId};
+use libp2p::futures::future::join_all;
+use libp2p::multiaddr::Protocol;
+use crate::network::Client;
+
+/// Time until a dial of a bootstrap peer is given up.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Wait before dialing an unreachable bootstrap peer again, doubled after every failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+/// Longest wait between two dials of an unreachable bootstrap peer.
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Splits a bootstrap multiaddr into the PeerId it ends with and the address to dial.
+pub fn parse_peer(addr: Multiaddr) -> anyhow::Result<(PeerId, Multiaddr)> {
+	let mut dial_addr = addr.clone();
+	match dial_addr.pop() {
+		Some(Protocol::P2p(hash)) => {
+			let peer_id = PeerId::from_multihash(hash)
+				.map_err(|_| anyhow::Error::msg(format!("Invalid PeerId in bootstrap address {}", addr)))?;
+			Ok((peer_id, dial_addr))
+		}
+		_ => anyhow::bail!("Bootstrap address {} doesn't end with a PeerId", addr),
+	}
+}
+
+/// Dials all bootstrap `peers` concurrently, then bootstraps Kademlia every `interval` to keep the routing table fresh.
+///
+/// Unreachable peers are dialed again with exponential backoff until they answer.
+pub async fn run(client: Client, peers: Vec<(PeerId, Multiaddr)>, interval: Duration) {
+	let dials = peers.into_iter().map(|(peer_id, addr)| {
+		let client = client.clone();
+		async move {
+			if let Err(e) = dial(&client, peer_id, addr.clone()).await {
+				event!(Level::WARN, "Bootstrap peer {} unreachable: {:?}", peer_id, e);
+				tokio::spawn(redial(client, peer_id, addr));
+			}
+		}
+	});
+	join_all(dials).await;
+
+	let mut interval = tokio::time::interval(interval);
+	loop {
+		interval.tick().await;
+		if let Err(e) = client.bootstrap().await {
+			event!(Level::INFO, "Skipping Kademlia bootstrap: {:?}", e);
+		}
+	}
+}
+
+async fn dial(client: &Client, peer_id: PeerId, addr: Multiaddr) -> anyhow::Result<()> {
+	tokio::time::timeout(DIAL_TIMEOUT, client.clone().dial(peer_id, addr)).await?
+}
+
+async fn redial(client: Client, peer_id: PeerId, addr: Multiaddr) {
+	let mut backoff = INITIAL_BACKOFF;
+	loop {
+		tokio::time::sleep(backoff).await;
+		match dial(&client, peer_id, addr.clone()).await {
+			Ok(()) => {
+				event!(Level::INFO, "Reached bootstrap peer {}", peer_id);
+				// The routing table is filled from the new peer right away instead of at the next interval.
+				let _ = client.bootstrap().await;
+				return
+			}
+			Err(e) => event!(Level::DEBUG, "Bootstrap peer {} still unreachable: {:?}", peer_id, e),
+		}
+		backoff = (backoff * 2).min(MAX_BACKOFF);
+	}
+}
+
+#[test]
+fn parse_bootstrap_address() {
+	let peer_id = PeerId::random();
+	let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+
+	let (parsed_id, parsed_addr) = parse_peer(addr.clone().with(Protocol::P2p(peer_id.into()))).unwrap();
+	assert_eq!(parsed_id, peer_id);
+	assert_eq!(parsed_addr, addr);
+
+	assert!(parse_peer(addr).is_err());
+}
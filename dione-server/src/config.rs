@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use serde::Deserialize;
+use toml::Value;
+
+/// Config with every setting at its default, printed by the `default-config` subcommand.
+pub const DEFAULT_CONFIG: &str = r#"# Configuration of a Dione server node.
+#
+# Pass this file with `--config <path>`. Flags on the command line override values set here, lists given there
+# replace the lists set here. Switches set here are turned off with `--no-<flag>`, e.g. `--no-proxy`.
+# Every key is named like the corresponding flag, with underscores instead of dashes.
+
+# Address where the gRPC server listens for clients. Required.
+# ex = "0.0.0.0:8010"
+
+# Address where the gRPC server can be contacted, including scheme and port.
+# Defaults to the environment variable CLEARADDRESS on port 8010.
+# clear_address = "http://localhost:8010"
+
+# Multiaddress where the libp2p part listens for other peers.
+# listen_address = "/ip4/0.0.0.0/tcp/0"
+
+# Multiaddresses of bootstrap peers, each ending with the PeerId of the peer.
+# bootstrap = ["/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA"]
+
+# Discover peers in the local network via mDNS.
+# mdns = false
+
+# Interval of the Kademlia bootstrap in seconds.
+# bootstrap_interval = 300
+
+# Directory of the database. Required.
+# db_path = "dione-db"
+
+# Storage backend for shares: "sled", "memory" or, with the sqlite feature, "sqlite".
+# storage = "sled"
+
+# Key file for encryption at rest. Shares are stored in clear if not set.
+# at_rest_key_file = "at-rest.key"
+
+# Key file of the node identity. Defaults to `identity.key` in the database directory, with memory storage
+# to a new identity on every start.
+# identity_key = "dione-db/identity.key"
+
+# Ports of the web server.
+# web_http_port = 8080
+# web_https_port = 8443
+
+# Default and maximum time to live of shares in seconds.
+# default_ttl = 604800
+# max_ttl = 2592000
+
+# Interval of the garbage collection in seconds.
+# gc_interval = 60
+
+# Number of peers every new share is copied to.
+# replicas = 2
+
+# Fetch shares missing on this node from their providers.
+# proxy = false
+
+# Maximum size of a share in bytes.
+# max_share_size = 4194304
+
+# Maximum size of a share uploaded in chunks in bytes.
+# max_chunked_share_size = 67108864
+
+# Maximum capacity of the node in bytes. Unlimited if not set.
+# max_capacity = 1073741824
+
+# Maximum number of shares per rate window of `rate_window` seconds. Unlimited if not set.
+# max_saves_per_window = 1000
+# rate_window = 60
+
+# Proof-of-work difficulty in bits, 0 disables proof of work.
+# pow_difficulty = 0
+
+# Key file for issuing rate-limit tokens, and whether shares without a token are refused.
+# token_key_file = "token.key"
+# require_tokens = false
+
+# TLS for the web server and the gRPC endpoint. Ignored if a subcommand is passed on the command line.
+# [tls]
+# public_key = "cert.pem"
+# private_key = "key.pem"
+# grpc_public_key = "grpc-cert.pem"
+# grpc_private_key = "grpc-key.pem"
+"#;
+
+/// TLS section of the config file, mirroring the `tls` subcommand.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+	pub public_key: PathBuf,
+	pub private_key: PathBuf,
+	pub grpc_public_key: Option<PathBuf>,
+	pub grpc_private_key: Option<PathBuf>,
+}
+
+/// Command line arguments with the values of the config file passed with `--config` put in front of them.
+///
+/// Arguments override themselves, so flags on the command line take precedence over the file. Keys whose flag is
+/// given on the command line are left out entirely, so lists like `--bootstrap` are replaced instead of extended.
+pub fn merge_args(mut args: Vec<OsString>) -> anyhow::Result<(Vec<OsString>, Option<TlsConfig>)> {
+	let path = match config_path(&args) {
+		Some(d) => d,
+		None => return Ok((args, None)),
+	};
+	let config = std::fs::read_to_string(&path)
+		.map_err(|e| anyhow::Error::msg(format!("Reading config file {:?} failed: {}", path, e)))?;
+	let (file_args, tls) = file_args(&config, &command_line_flags(&args))?;
+	let at = args.len().min(1);
+	args.splice(at..at, file_args);
+	Ok((args, tls))
+}
+
+/// Path passed with `--config`. It's looked up before parsing, as the file supplies arguments to the parser.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+	let mut args = args.iter().map(|e| e.to_string_lossy());
+	while let Some(arg) = args.next() {
+		if arg == "--config" {
+			return args.next().map(|e| PathBuf::from(e.into_owned()))
+		}
+		if let Some(d) = arg.strip_prefix("--config=") {
+			return Some(PathBuf::from(d))
+		}
+	}
+	None
+}
+
+/// Long flags on the command line, without their values. `--no-<flag>` counts for `--<flag>` as well.
+fn command_line_flags(args: &[OsString]) -> HashSet<String> {
+	args.iter()
+		.filter_map(|e| e.to_str()?.strip_prefix("--"))
+		.map(|e| e.split('=').next().unwrap_or(e))
+		.map(|e| format!("--{}", e.strip_prefix("no-").unwrap_or(e)))
+		.collect()
+}
+
+/// Translates every key of `config` into the flag of the same name, except for the flags in `overridden`.
+fn file_args(config: &str, overridden: &HashSet<String>) -> anyhow::Result<(Vec<OsString>, Option<TlsConfig>)> {
+	let mut table: toml::value::Table = toml::from_str(config)?;
+	let tls = table.remove("tls").map(|e| e.try_into()).transpose()?;
+	let mut args = Vec::new();
+	for (key, value) in table {
+		let flag = format!("--{}", key.replace('_', "-"));
+		if overridden.contains(&flag) {
+			continue
+		}
+		let values = match value {
+			Value::Array(d) => d,
+			d => vec![d],
+		};
+		for value in values {
+			match value {
+				Value::Boolean(true) => args.push(OsString::from(&flag)),
+				Value::Boolean(false) => {}
+				Value::String(d) => args.extend([OsString::from(&flag), OsString::from(d)]),
+				Value::Integer(d) => args.extend([OsString::from(&flag), OsString::from(d.to_string())]),
+				d => anyhow::bail!("Unsupported value {} for {} in config file", d, key),
+			}
+		}
+	}
+	Ok((args, tls))
+}
+
+#[test]
+fn config_file_to_args() {
+	let (args, tls) = file_args(DEFAULT_CONFIG, &HashSet::new()).unwrap();
+	assert!(args.is_empty());
+	assert_eq!(tls, None);
+
+	let config = r#"
+		ex = "0.0.0.0:8010"
+		db_path = "node1"
+		bootstrap = ["/ip4/127.0.0.1/tcp/4001/p2p/a", "/ip4/127.0.0.1/tcp/4002/p2p/b"]
+		replicas = 3
+		proxy = true
+		require_tokens = false
+
+		[tls]
+		public_key = "cert.pem"
+		private_key = "key.pem"
+	"#;
+	let (args, tls) = file_args(config, &HashSet::new()).unwrap();
+	let args: Vec<_> = args.iter().map(|e| e.to_str().unwrap()).collect();
+	assert_eq!(args, vec![
+		"--bootstrap", "/ip4/127.0.0.1/tcp/4001/p2p/a",
+		"--bootstrap", "/ip4/127.0.0.1/tcp/4002/p2p/b",
+		"--db-path", "node1",
+		"--ex", "0.0.0.0:8010",
+		"--proxy",
+		"--replicas", "3",
+	]);
+	assert_eq!(tls.unwrap().public_key, PathBuf::from("cert.pem"));
+
+	let args: Vec<OsString> = vec!["dione-server".into(), "--config=node1.toml".into()];
+	assert_eq!(config_path(&args), Some(PathBuf::from("node1.toml")));
+}
+
+#[test]
+fn command_line_replaces_file_values() {
+	let config = r#"
+		bootstrap = ["/ip4/127.0.0.1/tcp/4001/p2p/a", "/ip4/127.0.0.1/tcp/4002/p2p/b"]
+		replicas = 3
+		proxy = true
+	"#;
+	let command_line: Vec<OsString> = vec!["dione-server".into(), "--bootstrap=/ip4/127.0.0.1/tcp/4003/p2p/c".into(), "--no-proxy".into()];
+
+	let (args, _) = file_args(config, &command_line_flags(&command_line)).unwrap();
+	let args: Vec<_> = args.iter().map(|e| e.to_str().unwrap()).collect();
+
+	assert_eq!(args, vec!["--replicas", "3"]);
+}
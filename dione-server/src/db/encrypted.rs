@@ -0,0 +1,280 @@
+use std::path::Path;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use tracing::{event, Level};
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use dione_lib::cryptography::symetric::AeadCipher;
+use dione_lib::cryptography::symetric::aes_aead::AesGcmSiv;
+use dione_lib::hashing::cryptographic::hmac_sha512_hash_bytes;
+
+use crate::db::{MessageStoreDb, MessageEntry, DbError, SaveOutcome};
+
+#[cfg(test)]
+use crate::db::{MemoryDb, unix_now};
+
+/// Minimum length of the key material for encryption at rest.
+pub const MIN_AT_REST_KEY_LENGTH: usize = 32;
+
+const NONCE_LENGTH: usize = 12;
+
+/// Keys for encryption at rest, derived from the key material of the node operator.
+#[derive(Clone)]
+pub struct AtRestKey {
+	address_key: Vec<u8>,
+	content_key: Vec<u8>,
+}
+
+impl AtRestKey {
+	pub fn from_material(material: &[u8]) -> anyhow::Result<Self> {
+		if material.len() < MIN_AT_REST_KEY_LENGTH {
+			return Err(anyhow::Error::msg(format!("Key for encryption at rest has to be at least {} bytes long", MIN_AT_REST_KEY_LENGTH)))
+		}
+		let address_key = hmac_sha512_hash_bytes(material, b"dione at rest address key");
+		let mut content_key = hmac_sha512_hash_bytes(material, b"dione at rest content key");
+		content_key.truncate(32);
+		Ok(Self {
+			address_key,
+			content_key,
+		})
+	}
+}
+
+impl std::fmt::Debug for AtRestKey {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("AtRestKey")
+	}
+}
+
+/// Wraps any [MessageStoreDb] and encrypts shares before they reach it.
+///
+/// Addresses are replaced by a keyed hash. The content is sealed together with the original address, so the
+/// address can be recovered when entries expire.
+#[derive(Debug)]
+pub struct EncryptedDb<T: MessageStoreDb> {
+	inner: T,
+	key: AtRestKey,
+	/// Plaintext bytes staged per upload, as the inner database only sees the larger ciphertexts.
+	staged: DashMap<Vec<u8>, u64>,
+}
+
+impl<T: MessageStoreDb> EncryptedDb<T> {
+	pub fn with_key(inner: T, key: AtRestKey) -> Self {
+		Self {
+			inner,
+			key,
+			staged: DashMap::new(),
+		}
+	}
+
+	fn hashed_address(&self, address: &[u8]) -> Vec<u8> {
+		let mut hashed = hmac_sha512_hash_bytes(&self.key.address_key, address);
+		hashed.truncate(32);
+		hashed
+	}
+
+	/// Encrypts `plaintext` with a fresh nonce, which is prefixed to the ciphertext.
+	fn encrypt(&self, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+		let mut nonce = [0u8; NONCE_LENGTH];
+		OsRng.fill_bytes(&mut nonce);
+		let ciphertext = AesGcmSiv::encrypt(plaintext, &self.key.content_key, &nonce)
+			.map_err(|e| anyhow::Error::msg(format!("Error encrypting share: {:?}", e)))?;
+		let mut sealed = nonce.to_vec();
+		sealed.extend_from_slice(&ciphertext);
+		Ok(sealed)
+	}
+
+	fn decrypt(&self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+		if sealed.len() < NONCE_LENGTH {
+			return Err(anyhow::Error::msg("Encrypted share is too short"))
+		}
+		let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+		AesGcmSiv::decrypt(ciphertext, &self.key.content_key, nonce)
+			.map_err(|e| anyhow::Error::msg(format!("Error decrypting share: {:?}", e)))
+	}
+
+	fn seal(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<MessageEntry> {
+		let plaintext = bincode::serialize(&(address, &entry.content))?;
+		Ok(MessageEntry {
+			content: self.encrypt(&plaintext)?,
+			..entry
+		})
+	}
+
+	fn open(&self, entry: MessageEntry) -> anyhow::Result<(Vec<u8>, MessageEntry)> {
+		let plaintext = self.decrypt(&entry.content)?;
+		let (address, content): (Vec<u8>, Vec<u8>) = bincode::deserialize(&plaintext)?;
+		Ok((address, MessageEntry {
+			content,
+			..entry
+		}))
+	}
+
+	/// Decrypts `entry` and checks that it was sealed for `address`.
+	fn open_for(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<MessageEntry> {
+		let (sealed_address, entry) = self.open(entry)?;
+		if sealed_address != address {
+			return Err(anyhow::Error::msg("Encrypted share belongs to a different address"))
+		}
+		Ok(entry)
+	}
+}
+
+#[async_trait]
+impl<T: MessageStoreDb + Send + Sync> MessageStoreDb for EncryptedDb<T> {
+	fn new<P: AsRef<Path>>(_path: P) -> anyhow::Result<Self> {
+		Err(anyhow::Error::msg("Encryption at rest needs a key, use EncryptedDb::with_key"))
+	}
+
+	async fn save_message(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<SaveOutcome> {
+		let hashed_address = self.hashed_address(address);
+		let content = entry.content.clone();
+		let sealed = self.seal(address, entry)?;
+		let err = match self.inner.save_message(&hashed_address, sealed).await {
+			Ok(d) => return Ok(d),
+			Err(e) => e,
+		};
+		// Sealing uses a fresh nonce, so the inner database can't recognize retries of the same share.
+		if err.downcast_ref::<DbError>() != Some(&DbError::AlreadyExists) {
+			return Err(err)
+		}
+		// Like the inner databases, a share uploaded in chunks is never the duplicate of a single message.
+		let chunked = self.inner.get_chunk(&hashed_address, 0).await?.is_some();
+		match self.get_entry(address).await? {
+			Some(d) if d.content == content && !chunked => Ok(SaveOutcome::Duplicate),
+			_ => Err(err),
+		}
+	}
+
+	/// The entry is sealed like any other, the chunks were encrypted one by one when they were staged.
+	async fn save_staged(&self, address: &[u8], upload_id: &[u8], entry: MessageEntry) -> anyhow::Result<()> {
+		let sealed = self.seal(address, entry)?;
+		self.inner.save_staged(&self.hashed_address(address), upload_id, sealed).await?;
+		self.staged.remove(upload_id);
+		Ok(())
+	}
+
+	async fn get_entry(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let message = self.inner.get_entry(&self.hashed_address(address)).await?;
+		message.map(|e| self.open_for(address, e)).transpose()
+	}
+
+	async fn get_chunk(&self, address: &[u8], index: u32) -> anyhow::Result<Option<Vec<u8>>> {
+		let chunk = self.inner.get_chunk(&self.hashed_address(address), index).await?;
+		chunk.map(|e| self.decrypt(&e)).transpose()
+	}
+
+	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let prev = self.inner.remove_message(&self.hashed_address(address)).await?;
+		prev.map(|e| self.open_for(address, e)).transpose()
+	}
+
+	async fn remove_expired(&self, now: u64) -> anyhow::Result<Vec<(Vec<u8>, MessageEntry)>> {
+		let removed = self.inner.remove_expired(now).await?;
+		// The inner database already deleted the whole batch, so one broken entry mustn't hide the others.
+		let removed = removed.into_iter()
+			.filter_map(|(hashed_address, e)| match self.open(e) {
+				Ok(d) => Some(d),
+				Err(e) => {
+					event!(Level::ERROR, "Error opening expired share at {:?}: {:?}", hashed_address, e);
+					None
+				}
+			})
+			.collect();
+		Ok(removed)
+	}
+
+	/// The original addresses are recovered from the sealed entries.
+	async fn addresses(&self, now: u64) -> anyhow::Result<Vec<Vec<u8>>> {
+		let mut addresses = Vec::new();
+		for hashed_address in self.inner.addresses(now).await? {
+			if let Some(entry) = self.inner.get_entry(&hashed_address).await? {
+				addresses.push(self.open(entry)?.0);
+			}
+		}
+		Ok(addresses)
+	}
+
+	fn stored_bytes(&self) -> u64 {
+		self.inner.stored_bytes()
+	}
+
+	async fn append_staged(&self, upload_id: &[u8], chunk: &[u8]) -> anyhow::Result<u64> {
+		self.inner.append_staged(upload_id, &self.encrypt(chunk)?).await?;
+		let mut staged = self.staged.entry(upload_id.to_vec()).or_default();
+		*staged += chunk.len() as u64;
+		Ok(*staged)
+	}
+
+	async fn remove_staged(&self, upload_id: &[u8]) -> anyhow::Result<()> {
+		self.staged.remove(upload_id);
+		self.inner.remove_staged(upload_id).await
+	}
+
+	async fn spend_token(&self, nonce: &[u8]) -> anyhow::Result<bool> {
+		self.inner.spend_token(nonce).await
+	}
+
+	async fn restore_token(&self, nonce: &[u8]) -> anyhow::Result<()> {
+		self.inner.restore_token(nonce).await
+	}
+
+	#[cfg(test)]
+	fn test_connection<P: AsRef<Path>>(path: P) -> Self {
+		let mut material = [0u8; MIN_AT_REST_KEY_LENGTH];
+		OsRng.fill_bytes(&mut material);
+		Self::with_key(T::test_connection(path), AtRestKey::from_material(&material).unwrap())
+	}
+
+	#[cfg(test)]
+	async fn destroy_test_connection<P: AsRef<Path> + Send>(path: P) -> anyhow::Result<()> {
+		T::destroy_test_connection(path).await
+	}
+
+	fn flush(&self) {
+		self.inner.flush()
+	}
+}
+
+#[tokio::test]
+async fn hides_addresses_and_content() {
+	let test_db = EncryptedDb::<MemoryDb>::test_connection("");
+	let entry = MessageEntry::new(b"This is just testcontent".to_vec(), 1_000);
+	test_db.save_message(b"thisisatestaddress", entry.clone()).await.unwrap();
+
+	let raw = test_db.inner.get_entry(&test_db.hashed_address(b"thisisatestaddress")).await.unwrap().unwrap();
+
+	assert_eq!(test_db.inner.get_entry(b"thisisatestaddress").await.unwrap(), None);
+	assert_ne!(raw.content, entry.content);
+	assert_eq!(test_db.get_message(b"thisisatestaddress").await.unwrap(), Some(entry));
+}
+
+#[tokio::test]
+async fn skip_undecryptable_expired_shares() {
+	let test_db = EncryptedDb::<MemoryDb>::test_connection("");
+	let mut expired = MessageEntry::new(b"expired content".to_vec(), 10);
+	expired.inserted_at -= 20;
+	test_db.save_message(b"expiredaddress", expired.clone()).await.unwrap();
+	let mut broken = MessageEntry::new(b"not encrypted".to_vec(), 10);
+	broken.inserted_at -= 20;
+	test_db.inner.save_message(b"brokenaddress", broken).await.unwrap();
+
+	let removed = test_db.remove_expired(unix_now()).await.unwrap();
+
+	assert_eq!(removed, vec![(b"expiredaddress".to_vec(), expired)]);
+	assert!(test_db.inner.addresses(0).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn chunked_shares_are_no_duplicates() {
+	let test_db = EncryptedDb::<MemoryDb>::test_connection("");
+	test_db.append_staged(b"uploadid", b"first chunk").await.unwrap();
+	test_db.save_staged(b"chunkedaddress", b"uploadid", MessageEntry::new(Vec::new(), 1_000)).await.unwrap();
+
+	let err = test_db.save_message(b"chunkedaddress", MessageEntry::new(b"first chunk".to_vec(), 1_000)).await.unwrap_err();
+	let also_err = test_db.save_message(b"chunkedaddress", MessageEntry::new(Vec::new(), 1_000)).await.unwrap_err();
+
+	assert_eq!(err.downcast_ref::<DbError>(), Some(&DbError::AlreadyExists));
+	assert_eq!(also_err.downcast_ref::<DbError>(), Some(&DbError::AlreadyExists));
+}
@@ -0,0 +1,333 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use dashmap::DashMap;
+
+use crate::db::{MessageStoreDb, MessageEntry, DbError, SaveOutcome};
+
+#[cfg(test)]
+use crate::db::{MessageDb, MemoryDb};
+
+/// Storage limits of a node.
+#[derive(Debug, Clone)]
+pub struct Limits {
+	/// Maximum size of a single share in bytes.
+	pub max_share_size: usize,
+	/// Maximum size of a share uploaded in chunks, which never has to fit into a single message.
+	pub max_chunked_share_size: usize,
+	/// Maximum number of bytes all stored entries may occupy together.
+	pub max_capacity: Option<u64>,
+	/// Maximum number of shares accepted per `window`.
+	pub max_saves_per_window: Option<u64>,
+	pub window: Duration,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			max_share_size: 4_194_304,
+			max_chunked_share_size: 67_108_864,
+			max_capacity: None,
+			max_saves_per_window: None,
+			window: Duration::from_secs(60),
+		}
+	}
+}
+
+#[derive(Debug)]
+struct RateWindow {
+	start: Instant,
+	saves: u64,
+}
+
+/// Capacity and rate slot held by a save in progress. Dropping it returns both, the rate slot only if the share
+/// wasn't stored.
+struct Reservation<'a, T: MessageStoreDb> {
+	db: &'a LimitedDb<T>,
+	bytes: u64,
+	window_start: Option<Instant>,
+	stored: bool,
+}
+
+impl<T: MessageStoreDb> Drop for Reservation<'_, T> {
+	fn drop(&mut self) {
+		self.db.pending_bytes.fetch_sub(self.bytes, Ordering::SeqCst);
+		if let (Some(start), false) = (self.window_start, self.stored) {
+			let mut rate_window = self.db.rate_window.lock().unwrap();
+			if rate_window.start == start {
+				rate_window.saves -= 1;
+			}
+		}
+	}
+}
+
+/// Wraps any [MessageStoreDb] and rejects shares that would exceed the configured [Limits].
+#[derive(Debug)]
+pub struct LimitedDb<T: MessageStoreDb> {
+	inner: T,
+	limits: Limits,
+	rate_window: Mutex<RateWindow>,
+	/// Bytes of saves in progress and of staged uploads, counted against the capacity until they are done.
+	pending_bytes: AtomicU64,
+	/// Bytes staged per upload, which are part of `pending_bytes`.
+	staged: DashMap<Vec<u8>, u64>,
+}
+
+impl<T: MessageStoreDb> LimitedDb<T> {
+	pub fn with_limits(inner: T, limits: Limits) -> Self {
+		Self {
+			inner,
+			limits,
+			rate_window: Mutex::new(RateWindow {
+				start: Instant::now(),
+				saves: 0,
+			}),
+			pending_bytes: AtomicU64::new(0),
+			staged: DashMap::new(),
+		}
+	}
+
+	/// Counts `bytes` against the capacity, unless they don't fit into it anymore.
+	fn reserve_bytes(&self, bytes: u64) -> Result<(), DbError> {
+		let max_capacity = self.limits.max_capacity;
+		self.pending_bytes
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| match max_capacity {
+				Some(max) if self.inner.stored_bytes() + pending + bytes > max => None,
+				_ => Some(pending + bytes),
+			})
+			.map(|_| ())
+			.map_err(|_| DbError::CapacityExceeded { max: max_capacity.unwrap_or_default() })
+	}
+
+	/// Reserves capacity and a rate slot for a share of `size` bytes, which may be at most `max_size` bytes large.
+	/// `reserved` bytes of the share are already counted against the capacity, as they were staged.
+	fn reserve(&self, size: usize, max_size: usize, reserved: u64) -> Result<Reservation<'_, T>, DbError> {
+		if size > max_size {
+			return Err(DbError::ShareTooLarge { size, max: max_size })
+		}
+		let bytes = size as u64 - reserved;
+		self.reserve_bytes(bytes)?;
+		let mut reservation = Reservation {
+			db: self,
+			bytes,
+			window_start: None,
+			stored: false,
+		};
+		if let Some(max) = self.limits.max_saves_per_window {
+			let mut rate_window = self.rate_window.lock().unwrap();
+			if rate_window.start.elapsed() >= self.limits.window {
+				rate_window.start = Instant::now();
+				rate_window.saves = 0;
+			}
+			if rate_window.saves >= max {
+				return Err(DbError::RateLimited { max, window_secs: self.limits.window.as_secs() })
+			}
+			rate_window.saves += 1;
+			reservation.window_start = Some(rate_window.start);
+		}
+		Ok(reservation)
+	}
+
+	/// Forgets the upload `upload_id` and returns its staged bytes to the capacity.
+	fn release_staged(&self, upload_id: &[u8]) {
+		if let Some((_, bytes)) = self.staged.remove(upload_id) {
+			self.pending_bytes.fetch_sub(bytes, Ordering::SeqCst);
+		}
+	}
+}
+
+#[async_trait]
+impl<T: MessageStoreDb + Send + Sync> MessageStoreDb for LimitedDb<T> {
+	fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+		Ok(Self::with_limits(T::new(path)?, Limits::default()))
+	}
+
+	async fn save_message(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<SaveOutcome> {
+		let mut reservation = self.reserve(entry.content.len(), self.limits.max_share_size, 0)?;
+		let outcome = self.inner.save_message(address, entry).await;
+		// Only stored shares count against the rate limit, retries and collisions give their slot back.
+		reservation.stored = matches!(outcome, Ok(SaveOutcome::Stored));
+		outcome
+	}
+
+	async fn save_staged(&self, address: &[u8], upload_id: &[u8], entry: MessageEntry) -> anyhow::Result<()> {
+		let staged = self.staged.get(upload_id).map(|e| *e).unwrap_or_default();
+		let size = entry.content.len() + staged as usize;
+		let mut reservation = self.reserve(size, self.limits.max_chunked_share_size, staged)?;
+		let outcome = self.inner.save_staged(address, upload_id, entry).await;
+		if outcome.is_ok() {
+			reservation.stored = true;
+			// The staged bytes are stored now.
+			self.release_staged(upload_id);
+		}
+		outcome
+	}
+
+	async fn get_entry(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		self.inner.get_entry(address).await
+	}
+
+	async fn get_chunk(&self, address: &[u8], index: u32) -> anyhow::Result<Option<Vec<u8>>> {
+		self.inner.get_chunk(address, index).await
+	}
+
+	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		self.inner.remove_message(address).await
+	}
+
+	async fn remove_expired(&self, now: u64) -> anyhow::Result<Vec<(Vec<u8>, MessageEntry)>> {
+		self.inner.remove_expired(now).await
+	}
+
+	async fn addresses(&self, now: u64) -> anyhow::Result<Vec<Vec<u8>>> {
+		self.inner.addresses(now).await
+	}
+
+	fn stored_bytes(&self) -> u64 {
+		self.inner.stored_bytes()
+	}
+
+	/// Uploads are refused as soon as they grow too large for a share or for the free capacity. Staged chunks count
+	/// against the capacity until the upload is saved or removed.
+	async fn append_staged(&self, upload_id: &[u8], chunk: &[u8]) -> anyhow::Result<u64> {
+		let staged = self.staged.get(upload_id).map(|e| *e).unwrap_or_default() + chunk.len() as u64;
+		let max = self.limits.max_chunked_share_size;
+		let reserved = match staged > max as u64 {
+			true => Err(DbError::ShareTooLarge { size: staged as usize, max }),
+			false => self.reserve_bytes(chunk.len() as u64),
+		};
+		if let Err(e) = reserved {
+			self.remove_staged(upload_id).await?;
+			return Err(e.into())
+		}
+		*self.staged.entry(upload_id.to_vec()).or_default() += chunk.len() as u64;
+		match self.inner.append_staged(upload_id, chunk).await {
+			Ok(d) => Ok(d),
+			Err(e) => {
+				self.remove_staged(upload_id).await?;
+				Err(e)
+			}
+		}
+	}
+
+	async fn remove_staged(&self, upload_id: &[u8]) -> anyhow::Result<()> {
+		self.release_staged(upload_id);
+		self.inner.remove_staged(upload_id).await
+	}
+
+	async fn spend_token(&self, nonce: &[u8]) -> anyhow::Result<bool> {
+		self.inner.spend_token(nonce).await
+	}
+
+	async fn restore_token(&self, nonce: &[u8]) -> anyhow::Result<()> {
+		self.inner.restore_token(nonce).await
+	}
+
+	#[cfg(test)]
+	fn test_connection<P: AsRef<Path>>(path: P) -> Self {
+		Self::with_limits(T::test_connection(path), Limits::default())
+	}
+
+	#[cfg(test)]
+	async fn destroy_test_connection<P: AsRef<Path> + Send>(path: P) -> anyhow::Result<()> {
+		T::destroy_test_connection(path).await
+	}
+
+	fn flush(&self) {
+		self.inner.flush()
+	}
+}
+
+#[tokio::test]
+async fn reject_over_limits() {
+	let test_db_path = String::from("test_reject_over_limits.sled");
+	let limits = Limits {
+		max_share_size: 16,
+		max_chunked_share_size: 32,
+		max_capacity: None,
+		max_saves_per_window: Some(1),
+		window: Duration::from_secs(60),
+	};
+	let test_db = LimitedDb::with_limits(MessageDb::test_connection(&test_db_path), limits);
+
+	let too_large = test_db.save_message(b"address1", MessageEntry::new(vec![0; 17], 60)).await.unwrap_err();
+	let first = test_db.save_message(b"address2", MessageEntry::new(vec![0; 16], 60)).await;
+	let second = test_db.save_message(b"address3", MessageEntry::new(vec![0; 16], 60)).await.unwrap_err();
+	LimitedDb::<MessageDb>::destroy_test_connection(&test_db_path).await.unwrap();
+
+	assert_eq!(too_large.downcast_ref::<DbError>(), Some(&DbError::ShareTooLarge { size: 17, max: 16 }));
+	assert!(first.is_ok());
+	assert_eq!(second.downcast_ref::<DbError>(), Some(&DbError::RateLimited { max: 1, window_secs: 60 }));
+}
+
+#[tokio::test]
+async fn count_only_stored_shares() {
+	let limits = Limits {
+		max_share_size: 16,
+		max_chunked_share_size: 32,
+		max_capacity: Some(24),
+		max_saves_per_window: Some(2),
+		window: Duration::from_secs(60),
+	};
+	let test_db = LimitedDb::with_limits(MemoryDb::test_connection(""), limits);
+
+	let first = test_db.save_message(b"address1", MessageEntry::new(vec![0; 8], 60)).await.unwrap();
+	let duplicate = test_db.save_message(b"address1", MessageEntry::new(vec![0; 8], 60)).await.unwrap();
+	let collision = test_db.save_message(b"address1", MessageEntry::new(vec![1; 8], 60)).await.unwrap_err();
+	let second = test_db.save_message(b"address2", MessageEntry::new(vec![0; 8], 60)).await.unwrap();
+	let too_full = test_db.save_message(b"address3", MessageEntry::new(vec![0; 16], 60)).await.unwrap_err();
+	let pending = test_db.pending_bytes.load(Ordering::SeqCst);
+
+	assert_eq!(first, SaveOutcome::Stored);
+	assert_eq!(duplicate, SaveOutcome::Duplicate);
+	assert_eq!(collision.downcast_ref::<DbError>(), Some(&DbError::AlreadyExists));
+	assert_eq!(second, SaveOutcome::Stored);
+	assert_eq!(too_full.downcast_ref::<DbError>(), Some(&DbError::CapacityExceeded { max: 24 }));
+	assert_eq!(pending, 0);
+}
+
+#[tokio::test]
+async fn limit_chunked_uploads() {
+	let limits = Limits {
+		max_share_size: 16,
+		max_chunked_share_size: 32,
+		max_capacity: None,
+		max_saves_per_window: None,
+		window: Duration::from_secs(60),
+	};
+	let test_db = LimitedDb::with_limits(MemoryDb::test_connection(""), limits);
+
+	test_db.append_staged(b"uploadid", &[0; 24]).await.unwrap();
+	let saved = test_db.save_staged(b"address1", b"uploadid", MessageEntry::new(Vec::new(), 60)).await;
+	test_db.append_staged(b"otheruploadid", &[0; 24]).await.unwrap();
+	let too_large = test_db.append_staged(b"otheruploadid", &[0; 16]).await.unwrap_err();
+
+	assert!(saved.is_ok());
+	assert_eq!(test_db.get_message(b"address1").await.unwrap().unwrap().content, vec![0; 24]);
+	assert_eq!(too_large.downcast_ref::<DbError>(), Some(&DbError::ShareTooLarge { size: 40, max: 32 }));
+	assert!(test_db.staged.is_empty());
+}
+
+#[tokio::test]
+async fn reserve_staged_chunks() {
+	let limits = Limits {
+		max_share_size: 16,
+		max_chunked_share_size: 32,
+		max_capacity: Some(32),
+		max_saves_per_window: None,
+		window: Duration::from_secs(60),
+	};
+	let test_db = LimitedDb::with_limits(MemoryDb::test_connection(""), limits);
+
+	test_db.append_staged(b"uploadid", &[0; 24]).await.unwrap();
+	let too_full = test_db.append_staged(b"otheruploadid", &[0; 16]).await.unwrap_err();
+	let pending = test_db.pending_bytes.load(Ordering::SeqCst);
+	test_db.save_staged(b"address1", b"uploadid", MessageEntry::new(Vec::new(), 60)).await.unwrap();
+
+	assert_eq!(too_full.downcast_ref::<DbError>(), Some(&DbError::CapacityExceeded { max: 32 }));
+	assert_eq!(pending, 24);
+	assert_eq!(test_db.pending_bytes.load(Ordering::SeqCst), 0);
+	assert_eq!(test_db.stored_bytes(), 24);
+}
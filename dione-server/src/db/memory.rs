@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
+use dashmap::mapref::entry::Entry;
+
+use crate::db::{MessageStoreDb, MessageEntry, DbError, SaveOutcome, unix_now};
+
+/// [MessageStoreDb] keeping all shares in memory. Nothing is written to disk, so all shares are lost on shutdown.
+#[derive(Debug, Default)]
+pub struct MemoryDb {
+	messages: DashMap<Vec<u8>, MessageEntry>,
+	/// Chunks of shares saved with [MessageStoreDb::save_staged], by address.
+	chunks: DashMap<Vec<u8>, Vec<Vec<u8>>>,
+	staged: DashMap<Vec<u8>, Vec<Vec<u8>>>,
+	spent_tokens: DashSet<Vec<u8>>,
+	stored_bytes: AtomicU64,
+}
+
+impl MemoryDb {
+	/// Removes the chunks of the share at `address`.
+	fn remove_chunks(&self, address: &[u8]) {
+		if let Some((_, chunks)) = self.chunks.remove(address) {
+			let chunks_len: u64 = chunks.iter().map(|e| e.len() as u64).sum();
+			self.stored_bytes.fetch_sub(chunks_len, Ordering::SeqCst);
+		}
+	}
+}
+
+#[async_trait]
+impl MessageStoreDb for MemoryDb {
+	fn new<P: AsRef<Path>>(_path: P) -> anyhow::Result<Self> {
+		Ok(Self::default())
+	}
+
+	async fn save_message(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<SaveOutcome> {
+		let entry_len = entry.content.len() as u64;
+		match self.messages.entry(address.to_vec()) {
+			Entry::Occupied(mut o) => {
+				if !o.get().is_expired(unix_now()) {
+					return if o.get().content == entry.content && !self.chunks.contains_key(address) {
+						Ok(SaveOutcome::Duplicate)
+					} else {
+						Err(DbError::AlreadyExists.into())
+					}
+				}
+				let prev = o.insert(entry);
+				self.stored_bytes.fetch_add(entry_len, Ordering::SeqCst);
+				self.stored_bytes.fetch_sub(prev.content.len() as u64, Ordering::SeqCst);
+				self.remove_chunks(address);
+			}
+			Entry::Vacant(v) => {
+				v.insert(entry);
+				self.stored_bytes.fetch_add(entry_len, Ordering::SeqCst);
+			}
+		}
+		Ok(SaveOutcome::Stored)
+	}
+
+	async fn save_staged(&self, address: &[u8], upload_id: &[u8], entry: MessageEntry) -> anyhow::Result<()> {
+		let entry_len = entry.content.len() as u64;
+		match self.messages.entry(address.to_vec()) {
+			Entry::Occupied(mut o) => {
+				if !o.get().is_expired(unix_now()) {
+					return Err(DbError::AlreadyExists.into())
+				}
+				let prev = o.insert(entry);
+				self.stored_bytes.fetch_sub(prev.content.len() as u64, Ordering::SeqCst);
+				self.remove_chunks(address);
+			}
+			Entry::Vacant(v) => {
+				v.insert(entry);
+			}
+		}
+		let chunks = self.staged.remove(upload_id).map(|(_, e)| e).unwrap_or_default();
+		let chunks_len: u64 = chunks.iter().map(|e| e.len() as u64).sum();
+		self.chunks.insert(address.to_vec(), chunks);
+		self.stored_bytes.fetch_add(entry_len + chunks_len, Ordering::SeqCst);
+		Ok(())
+	}
+
+	async fn get_entry(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let message = self.messages
+			.get(address)
+			.map(|e| e.value().clone())
+			.filter(|e| !e.is_expired(unix_now()));
+		Ok(message)
+	}
+
+	async fn get_chunk(&self, address: &[u8], index: u32) -> anyhow::Result<Option<Vec<u8>>> {
+		let chunk = self.chunks
+			.get(address)
+			.and_then(|e| e.value().get(index as usize).cloned());
+		Ok(chunk)
+	}
+
+	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let prev = self.messages.remove(address).map(|(_, e)| e);
+		if let Some(prev) = &prev {
+			self.stored_bytes.fetch_sub(prev.content.len() as u64, Ordering::SeqCst);
+			self.remove_chunks(address);
+		}
+		Ok(prev)
+	}
+
+	async fn remove_expired(&self, now: u64) -> anyhow::Result<Vec<(Vec<u8>, MessageEntry)>> {
+		let expired: Vec<Vec<u8>> = self.messages
+			.iter()
+			.filter(|e| e.value().is_expired(now))
+			.map(|e| e.key().clone())
+			.collect();
+		let mut removed = Vec::with_capacity(expired.len());
+		for address in expired {
+			// Only remove the entry if it wasn't replaced in the meantime.
+			if let Some(e) = self.messages.remove_if(&address, |_, e| e.is_expired(now)) {
+				self.stored_bytes.fetch_sub(e.1.content.len() as u64, Ordering::SeqCst);
+				self.remove_chunks(&address);
+				removed.push(e);
+			}
+		}
+		Ok(removed)
+	}
+
+	async fn addresses(&self, now: u64) -> anyhow::Result<Vec<Vec<u8>>> {
+		let addresses = self.messages
+			.iter()
+			.filter(|e| !e.value().is_expired(now))
+			.map(|e| e.key().clone())
+			.collect();
+		Ok(addresses)
+	}
+
+	fn stored_bytes(&self) -> u64 {
+		self.stored_bytes.load(Ordering::SeqCst)
+	}
+
+	async fn append_staged(&self, upload_id: &[u8], chunk: &[u8]) -> anyhow::Result<u64> {
+		let mut chunks = self.staged.entry(upload_id.to_vec()).or_default();
+		chunks.push(chunk.to_vec());
+		Ok(chunks.iter().map(|e| e.len() as u64).sum())
+	}
+
+	async fn remove_staged(&self, upload_id: &[u8]) -> anyhow::Result<()> {
+		self.staged.remove(upload_id);
+		Ok(())
+	}
+
+	async fn spend_token(&self, nonce: &[u8]) -> anyhow::Result<bool> {
+		Ok(self.spent_tokens.insert(nonce.to_vec()))
+	}
+
+	async fn restore_token(&self, nonce: &[u8]) -> anyhow::Result<()> {
+		self.spent_tokens.remove(nonce);
+		Ok(())
+	}
+
+	#[cfg(test)]
+	fn test_connection<P: AsRef<Path>>(path: P) -> Self {
+		Self::new(path).expect("Error creating db")
+	}
+
+	#[cfg(test)]
+	async fn destroy_test_connection<P: AsRef<Path> + Send>(_path: P) -> anyhow::Result<()> {
+		Ok(())
+	}
+
+	fn flush(&self) {}
+}
@@ -1,12 +1,218 @@
 use std::path::Path;
+use std::convert::TryInto;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use async_trait::async_trait;
+use bincode::Options;
+use sled::Transactional;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use dione_lib::hashing::cryptographic::sha512_hash_bytes;
+
+mod encrypted;
+mod limits;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use encrypted::{AtRestKey, EncryptedDb};
+pub use limits::{Limits, LimitedDb};
+pub use memory::MemoryDb;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{SqliteDb, migrate_from_sled};
+
+/// Time to live of shares stored before entries carried one, the default of `--default-ttl`.
+pub const LEGACY_TTL: u64 = 604800;
+
+/// Storage backends a node can keep its shares in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageKind {
+	/// Persistent storage in a sled database
+	Sled,
+	/// Volatile storage in memory
+	Memory,
+	/// Persistent storage in an SQLite database
+	#[cfg(feature = "sqlite")]
+	Sqlite,
+}
+
+impl FromStr for StorageKind {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"sled" => Ok(Self::Sled),
+			"memory" => Ok(Self::Memory),
+			#[cfg(feature = "sqlite")]
+			"sqlite" => Ok(Self::Sqlite),
+			d => Err(anyhow::Error::msg(format!("Unknown storage backend: {}", d))),
+		}
+	}
+}
+
+/// Reasons for a [MessageStoreDb] to refuse storing a share.
+#[derive(Debug, Error, PartialEq)]
+pub enum DbError {
+	#[error("Share size {size} exceeds the maximum of {max} bytes")]
+	ShareTooLarge { size: usize, max: usize },
+	#[error("Node capacity of {max} bytes is exhausted")]
+	CapacityExceeded { max: u64 },
+	#[error("Node accepts at most {max} shares per {window_secs} seconds")]
+	RateLimited { max: u64, window_secs: u64 },
+	#[error("A different share is already stored at this address")]
+	AlreadyExists,
+}
+
+impl DbError {
+	/// Code returned to the client in `SaveMessageResponse.code`.
+	pub fn response_code(&self) -> i32 {
+		match self {
+			DbError::ShareTooLarge { .. } => 413,
+			DbError::CapacityExceeded { .. } => 507,
+			DbError::RateLimited { .. } => 429,
+			DbError::AlreadyExists => 409,
+		}
+	}
+}
+
+/// Outcome of a successful [MessageStoreDb::save_message].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveOutcome {
+	/// The share was stored.
+	Stored,
+	/// The identical share was already stored at the address, e.g. because a client retried.
+	Duplicate,
+}
+
+/// Share as it is kept in a [MessageStoreDb], together with the data needed to expire it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageEntry {
+	pub content: Vec<u8>,
+	/// Unix timestamp (seconds) of the moment the share was saved.
+	pub inserted_at: u64,
+	/// Time to live in seconds, counted from `inserted_at`.
+	pub ttl: u64,
+	/// SHA-512 hash of the secret that authorizes deleting the share. Shares without it can only expire.
+	pub delete_commitment: Option<Vec<u8>>,
+}
+
+impl MessageEntry {
+	pub fn new(content: Vec<u8>, ttl: u64) -> Self {
+		Self {
+			content,
+			inserted_at: unix_now(),
+			ttl,
+			delete_commitment: None,
+		}
+	}
+
+	pub fn with_delete_commitment(mut self, delete_commitment: Option<Vec<u8>>) -> Self {
+		self.delete_commitment = delete_commitment;
+		self
+	}
+
+	/// Checks whether `secret` opens the delete commitment of the share.
+	pub fn may_delete(&self, secret: &[u8]) -> bool {
+		match &self.delete_commitment {
+			Some(d) => {
+				let hash = sha512_hash_bytes(secret);
+				hash.len() == d.len() && hash.iter().zip(d).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+			}
+			None => false,
+		}
+	}
+
+	pub fn expires_at(&self) -> u64 {
+		self.inserted_at.saturating_add(self.ttl)
+	}
+
+	pub fn is_expired(&self, now: u64) -> bool {
+		self.expires_at() <= now
+	}
+
+	fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+		Ok(bincode::serialize(self)?)
+	}
+
+	/// Trailing bytes are rejected, so the raw content of a share stored before entries existed isn't taken for an entry.
+	fn from_bytes(inp: &[u8]) -> anyhow::Result<Self> {
+		let options = bincode::DefaultOptions::new()
+			.with_fixint_encoding()
+			.reject_trailing_bytes();
+		Ok(options.deserialize(inp)?)
+	}
+
+	/// Decodes a stored value. Values that aren't an entry are the raw content of a share stored before entries
+	/// existed, which counts as saved at `now` with [LEGACY_TTL].
+	fn from_stored(inp: &[u8], now: u64) -> Self {
+		Self::from_bytes(inp).unwrap_or_else(|_| Self {
+			content: inp.to_vec(),
+			inserted_at: now,
+			ttl: LEGACY_TTL,
+			delete_commitment: None,
+		})
+	}
+}
+
+/// Current time as unix timestamp in seconds.
+pub fn unix_now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default()
+}
 
 #[async_trait]
 pub trait MessageStoreDb {
 	fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> where Self: Sized;
-	async fn save_message(&self, address: &[u8], content: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
-	async fn get_message(&self, address: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
-	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
+	/// Stores `entry` at `address` unless a live entry already occupies it (first writer wins).
+	///
+	/// Fails with [DbError::AlreadyExists] if the occupying entry has a different content.
+	async fn save_message(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<SaveOutcome>;
+	/// Stores `entry` at `address` with the upload staged under `upload_id` as chunks following its content.
+	///
+	/// The chunks are kept as they were staged, so the share is never assembled in memory. Fails with
+	/// [DbError::AlreadyExists] if a live entry occupies `address`, even an identical one, as telling retries apart
+	/// would mean reading both shares back.
+	async fn save_staged(&self, address: &[u8], upload_id: &[u8], entry: MessageEntry) -> anyhow::Result<()>;
+	/// Returns the entry stored at `address` without the chunks saved with [MessageStoreDb::save_staged].
+	/// Entries whose TTL ran out are treated as absent.
+	async fn get_entry(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>>;
+	/// Returns chunk `index` of the share at `address`, see [MessageStoreDb::save_staged]. Doesn't check the TTL.
+	async fn get_chunk(&self, address: &[u8], index: u32) -> anyhow::Result<Option<Vec<u8>>>;
+	/// Returns the entry stored at `address` with its chunks appended to the content. Entries whose TTL ran out are
+	/// treated as absent.
+	async fn get_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let mut entry = match self.get_entry(address).await? {
+			Some(d) => d,
+			None => return Ok(None),
+		};
+		let mut index = 0;
+		while let Some(chunk) = self.get_chunk(address, index).await? {
+			entry.content.extend_from_slice(&chunk);
+			index += 1;
+		}
+		Ok(Some(entry))
+	}
+	/// Removes the entry at `address` together with its chunks and returns the entry like [MessageStoreDb::get_entry].
+	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>>;
+	/// Removes every entry that is expired at `now` together with its chunks and returns the removed entries with
+	/// their addresses, like [MessageStoreDb::get_entry].
+	async fn remove_expired(&self, now: u64) -> anyhow::Result<Vec<(Vec<u8>, MessageEntry)>>;
+	/// Addresses of every entry that is still alive at `now`.
+	async fn addresses(&self, now: u64) -> anyhow::Result<Vec<Vec<u8>>>;
+	/// Number of content bytes currently held by stored shares, counting the chunks of shares saved in chunks.
+	fn stored_bytes(&self) -> u64;
+	/// Appends `chunk` to the upload staged under `upload_id` and returns the number of staged bytes.
+	async fn append_staged(&self, upload_id: &[u8], chunk: &[u8]) -> anyhow::Result<u64>;
+	/// Drops the upload staged under `upload_id`, e.g. because it failed.
+	async fn remove_staged(&self, upload_id: &[u8]) -> anyhow::Result<()>;
+	/// Marks the token with `nonce` as spent. Returns false if it was spent before.
+	async fn spend_token(&self, nonce: &[u8]) -> anyhow::Result<bool>;
+	/// Marks the token with `nonce` as unspent again, because the share it was spent on wasn't stored.
+	async fn restore_token(&self, nonce: &[u8]) -> anyhow::Result<()>;
 	#[cfg(test)]
 	fn test_connection<P: AsRef<Path>>(path: P) -> Self;
 	#[cfg(test)]
@@ -18,6 +224,31 @@ pub trait MessageStoreDb {
 pub struct MessageDb {
 	db: sled::Db,
 	message_db: sled::Tree,
+	/// Chunks of shares saved with [MessageStoreDb::save_staged], see [chunk_key].
+	chunks_db: sled::Tree,
+	staged_db: sled::Tree,
+	spent_tokens_db: sled::Tree,
+	stored_bytes: AtomicU64,
+}
+
+/// Key of chunk `index` of the share at `address`. Keys of different addresses can't collide, as the index has a
+/// fixed length.
+fn chunk_key(address: &[u8], index: u32) -> Vec<u8> {
+	let mut key = address.to_vec();
+	key.extend_from_slice(&index.to_be_bytes());
+	key
+}
+
+impl MessageDb {
+	/// Removes the chunks of the share at `address`.
+	fn remove_chunks(&self, address: &[u8]) -> anyhow::Result<()> {
+		let mut index = 0;
+		while let Some(chunk) = self.chunks_db.remove(chunk_key(address, index))? {
+			self.stored_bytes.fetch_sub(chunk.len() as u64, Ordering::SeqCst);
+			index += 1;
+		}
+		Ok(())
+	}
 }
 
 #[async_trait]
@@ -25,27 +256,207 @@ impl MessageStoreDb for MessageDb {
 	fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
 		let db = sled::open(path)?;
 		let message_db = db.open_tree("messages")?;
+		let chunks_db = db.open_tree("chunks")?;
+		// Uploads interrupted by a shutdown can't be resumed.
+		let staged_db = db.open_tree("staged")?;
+		staged_db.clear()?;
+		let spent_tokens_db = db.open_tree("spent_tokens")?;
+		let now = unix_now();
+		let mut stored_bytes = 0;
+		for e in message_db.iter() {
+			let (address, mut value) = e?;
+			// Shares stored before entries existed are migrated once, so they expire like any other share.
+			if MessageEntry::from_bytes(&value).is_err() {
+				value = MessageEntry::from_stored(&value, now).to_bytes()?.into();
+				message_db.insert(address, value.clone())?;
+			}
+			stored_bytes += MessageEntry::from_bytes(&value)?.content.len() as u64;
+		}
+		for e in chunks_db.iter() {
+			stored_bytes += e?.1.len() as u64;
+		}
 		Ok(MessageDb {
 			db,
-			message_db
+			message_db,
+			chunks_db,
+			staged_db,
+			spent_tokens_db,
+			stored_bytes: AtomicU64::new(stored_bytes),
 		})
 	}
 
-	async fn save_message(&self, address: &[u8], content: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
-		match self.message_db.insert(address, content) {
-			Ok(d) => Ok(d.map(|d| d.to_vec())),
-			Err(e) => Err(anyhow::Error::from(e)),
+	async fn save_message(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<SaveOutcome> {
+		let entry_bytes = entry.to_bytes()?;
+		let entry_len = entry.content.len() as u64;
+		let mut expected: Option<sled::IVec> = None;
+		loop {
+			let current = match self.message_db.compare_and_swap(address, expected.as_ref(), Some(entry_bytes.clone()))? {
+				Ok(()) => {
+					self.stored_bytes.fetch_add(entry_len, Ordering::SeqCst);
+					if let Some(prev) = &expected {
+						let prev_len = MessageEntry::from_bytes(prev)?.content.len() as u64;
+						self.stored_bytes.fetch_sub(prev_len, Ordering::SeqCst);
+						self.remove_chunks(address)?;
+					}
+					return Ok(SaveOutcome::Stored)
+				}
+				Err(e) => e.current,
+			};
+			if let Some(current_bytes) = &current {
+				let current_entry = MessageEntry::from_bytes(current_bytes)?;
+				if !current_entry.is_expired(unix_now()) {
+					let chunked = self.chunks_db.contains_key(chunk_key(address, 0))?;
+					return if current_entry.content == entry.content && !chunked {
+						Ok(SaveOutcome::Duplicate)
+					} else {
+						Err(DbError::AlreadyExists.into())
+					}
+				}
+			}
+			// The address is free again or holds an expired entry, retry against the current value.
+			expected = current;
+		}
+	}
+
+	async fn save_staged(&self, address: &[u8], upload_id: &[u8], entry: MessageEntry) -> anyhow::Result<()> {
+		let entry_bytes = entry.to_bytes()?;
+		let mut staged_keys = Vec::new();
+		let mut staged = Vec::new();
+		for e in self.staged_db.scan_prefix(upload_id) {
+			let (key, value) = e?;
+			staged_keys.push(key);
+			// Shares the buffer of the staged value instead of copying it.
+			staged.push(value.subslice(8, value.len() - 8));
+		}
+		let added = entry.content.len() as u64 + staged.iter().map(|e| e.len() as u64).sum::<u64>();
+		let replaced = (&self.message_db, &self.chunks_db)
+			.transaction(|(messages, chunks)| {
+				let mut replaced = 0;
+				if let Some(current_bytes) = messages.get(address)? {
+					let current_entry = MessageEntry::from_bytes(&current_bytes).map_err(ConflictableTransactionError::Abort)?;
+					if !current_entry.is_expired(unix_now()) {
+						return Err(ConflictableTransactionError::Abort(DbError::AlreadyExists.into()))
+					}
+					replaced += current_entry.content.len() as u64;
+				}
+				// Chunks of an expired share that is replaced.
+				let mut index = 0;
+				while let Some(chunk) = chunks.remove(chunk_key(address, index))? {
+					replaced += chunk.len() as u64;
+					index += 1;
+				}
+				for (index, chunk) in staged.iter().enumerate() {
+					chunks.insert(chunk_key(address, index as u32), chunk.clone())?;
+				}
+				messages.insert(address, entry_bytes.clone())?;
+				Ok(replaced)
+			})
+			.map_err(|e| match e {
+				TransactionError::Abort(e) => e,
+				TransactionError::Storage(e) => e.into(),
+			})?;
+		self.stored_bytes.fetch_add(added, Ordering::SeqCst);
+		self.stored_bytes.fetch_sub(replaced, Ordering::SeqCst);
+		for key in staged_keys {
+			self.staged_db.remove(key)?;
+		}
+		Ok(())
+	}
+
+	async fn get_entry(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let message = match self.message_db.get(address)? {
+			Some(d) => MessageEntry::from_bytes(&d)?,
+			None => return Ok(None),
+		};
+		if message.is_expired(unix_now()) {
+			return Ok(None)
+		}
+		Ok(Some(message))
+	}
+
+	async fn get_chunk(&self, address: &[u8], index: u32) -> anyhow::Result<Option<Vec<u8>>> {
+		Ok(self.chunks_db.get(chunk_key(address, index))?.map(|e| e.to_vec()))
+	}
+
+	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let prev_val = match self.message_db.remove(address)? {
+			Some(d) => MessageEntry::from_bytes(&d)?,
+			None => return Ok(None),
+		};
+		self.stored_bytes.fetch_sub(prev_val.content.len() as u64, Ordering::SeqCst);
+		self.remove_chunks(address)?;
+		Ok(Some(prev_val))
+	}
+
+	async fn remove_expired(&self, now: u64) -> anyhow::Result<Vec<(Vec<u8>, MessageEntry)>> {
+		let mut removed = Vec::new();
+		for e in self.message_db.iter() {
+			let (address, entry_bytes) = e?;
+			let entry = MessageEntry::from_bytes(&entry_bytes)?;
+			if !entry.is_expired(now) {
+				continue;
+			}
+			// Only remove the entry if it wasn't replaced in the meantime.
+			let swapped = self.message_db.compare_and_swap(&address, Some(&entry_bytes), None as Option<&[u8]>)?;
+			if swapped.is_ok() {
+				self.stored_bytes.fetch_sub(entry.content.len() as u64, Ordering::SeqCst);
+				self.remove_chunks(&address)?;
+				removed.push((address.to_vec(), entry));
+			}
 		}
+		Ok(removed)
 	}
 
-	async fn get_message(&self, address: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
-		let message = self.message_db.get(address)?;
-		Ok(message.map(|e| e.to_vec()))
+	async fn addresses(&self, now: u64) -> anyhow::Result<Vec<Vec<u8>>> {
+		let mut addresses = Vec::new();
+		for e in self.message_db.iter() {
+			let (address, entry_bytes) = e?;
+			if !MessageEntry::from_bytes(&entry_bytes)?.is_expired(now) {
+				addresses.push(address.to_vec());
+			}
+		}
+		Ok(addresses)
 	}
 
-	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
-		let prev_val = self.message_db.remove(address)?;
-		Ok(prev_val.map(|e| e.to_vec()))
+	fn stored_bytes(&self) -> u64 {
+		self.stored_bytes.load(Ordering::SeqCst)
+	}
+
+	async fn append_staged(&self, upload_id: &[u8], chunk: &[u8]) -> anyhow::Result<u64> {
+		// Chunks are keyed by upload id and index. Each value starts with the number of bytes staged up to and including it.
+		let (index, staged) = match self.staged_db.scan_prefix(upload_id).next_back() {
+			Some(e) => {
+				let (key, value) = e?;
+				let index = u32::from_be_bytes(key[upload_id.len()..].try_into()?);
+				let staged = u64::from_be_bytes(value[..8].try_into()?);
+				(index + 1, staged)
+			}
+			None => (0, 0),
+		};
+		let staged = staged + chunk.len() as u64;
+		let mut key = upload_id.to_vec();
+		key.extend_from_slice(&index.to_be_bytes());
+		let mut value = staged.to_be_bytes().to_vec();
+		value.extend_from_slice(chunk);
+		self.staged_db.insert(key, value)?;
+		Ok(staged)
+	}
+
+	async fn remove_staged(&self, upload_id: &[u8]) -> anyhow::Result<()> {
+		for key in self.staged_db.scan_prefix(upload_id).keys() {
+			self.staged_db.remove(key?)?;
+		}
+		Ok(())
+	}
+
+	async fn spend_token(&self, nonce: &[u8]) -> anyhow::Result<bool> {
+		let swapped = self.spent_tokens_db.compare_and_swap(nonce, None as Option<&[u8]>, Some(&[] as &[u8]))?;
+		Ok(swapped.is_ok())
+	}
+
+	async fn restore_token(&self, nonce: &[u8]) -> anyhow::Result<()> {
+		self.spent_tokens_db.remove(nonce)?;
+		Ok(())
 	}
 
 	#[cfg(test)]
@@ -63,3 +474,129 @@ impl MessageStoreDb for MessageDb {
 		self.db.flush().unwrap();
 	}
 }
+
+/// Behaviour every [MessageStoreDb] implementation has to show.
+#[cfg(test)]
+async fn behaviour_suite<T: MessageStoreDb + Sync>(test_db: &T) {
+	let entry = MessageEntry::new(b"This is just testcontent".to_vec(), 1_000)
+		.with_delete_commitment(Some(sha512_hash_bytes(b"delete secret")));
+	let outcome = test_db.save_message(b"thisisatestaddress", entry.clone()).await.unwrap();
+	assert_eq!(outcome, SaveOutcome::Stored);
+	let outcome = test_db.save_message(b"thisisatestaddress", entry.clone()).await.unwrap();
+	assert_eq!(outcome, SaveOutcome::Duplicate);
+	let collision = MessageEntry::new(b"This is other testcontent".to_vec(), 1_000);
+	let err = test_db.save_message(b"thisisatestaddress", collision).await.unwrap_err();
+	assert_eq!(err.downcast_ref::<DbError>(), Some(&DbError::AlreadyExists));
+	assert_eq!(test_db.get_message(b"thisisatestaddress").await.unwrap(), Some(entry.clone()));
+	assert!(test_db.stored_bytes() >= entry.content.len() as u64);
+	assert!(entry.may_delete(b"delete secret"));
+	assert!(!entry.may_delete(b"wrong secret"));
+
+	let removed = test_db.remove_message(b"thisisatestaddress").await.unwrap();
+	assert_eq!(removed, Some(entry));
+	assert_eq!(test_db.get_message(b"thisisatestaddress").await.unwrap(), None);
+	assert_eq!(test_db.stored_bytes(), 0);
+
+	let mut expired = MessageEntry::new(b"expired content".to_vec(), 10);
+	expired.inserted_at -= 20;
+	let alive = MessageEntry::new(b"alive content".to_vec(), 1_000);
+
+	test_db.save_message(b"expiredaddress", expired.clone()).await.unwrap();
+	test_db.save_message(b"aliveaddress", alive.clone()).await.unwrap();
+
+	assert_eq!(test_db.get_message(b"expiredaddress").await.unwrap(), None);
+
+	let removed = test_db.remove_expired(unix_now()).await.unwrap();
+	assert_eq!(removed, vec![(b"expiredaddress".to_vec(), expired.clone())]);
+	assert_eq!(test_db.get_message(b"aliveaddress").await.unwrap(), Some(alive));
+	assert_eq!(test_db.addresses(unix_now()).await.unwrap(), vec![b"aliveaddress".to_vec()]);
+
+	test_db.save_message(b"replacedaddress", expired).await.unwrap();
+	let replacing = MessageEntry::new(b"Content replacing an expired share".to_vec(), 1_000);
+	let outcome = test_db.save_message(b"replacedaddress", replacing).await.unwrap();
+	assert_eq!(outcome, SaveOutcome::Stored);
+
+	assert_eq!(test_db.append_staged(b"uploadid", b"first chunk").await.unwrap(), 11);
+	assert_eq!(test_db.append_staged(b"uploadid", b"second chunk").await.unwrap(), 23);
+	let staged_entry = MessageEntry::new(Vec::new(), 1_000);
+	test_db.save_staged(b"stagedaddress", b"uploadid", staged_entry.clone()).await.unwrap();
+	assert_eq!(test_db.get_entry(b"stagedaddress").await.unwrap(), Some(staged_entry.clone()));
+	assert_eq!(test_db.get_chunk(b"stagedaddress", 1).await.unwrap(), Some(b"second chunk".to_vec()));
+	assert_eq!(test_db.get_chunk(b"stagedaddress", 2).await.unwrap(), None);
+	let assembled = test_db.get_message(b"stagedaddress").await.unwrap().unwrap();
+	assert_eq!(assembled.content, b"first chunksecond chunk".to_vec());
+	assert_eq!(test_db.append_staged(b"uploadid", b"other chunk").await.unwrap(), 11);
+	let err = test_db.save_staged(b"stagedaddress", b"uploadid", MessageEntry::new(Vec::new(), 1_000)).await.unwrap_err();
+	assert_eq!(err.downcast_ref::<DbError>(), Some(&DbError::AlreadyExists));
+	test_db.remove_staged(b"uploadid").await.unwrap();
+	assert_eq!(test_db.append_staged(b"uploadid", b"first chunk").await.unwrap(), 11);
+	test_db.remove_staged(b"uploadid").await.unwrap();
+	assert_eq!(test_db.remove_message(b"stagedaddress").await.unwrap(), Some(staged_entry));
+	assert_eq!(test_db.get_chunk(b"stagedaddress", 0).await.unwrap(), None);
+
+	let mut expired_staged = MessageEntry::new(Vec::new(), 10);
+	expired_staged.inserted_at -= 20;
+	test_db.append_staged(b"expireduploadid", b"expired chunk").await.unwrap();
+	test_db.save_staged(b"expiredstagedaddress", b"expireduploadid", expired_staged.clone()).await.unwrap();
+	let removed = test_db.remove_expired(unix_now()).await.unwrap();
+	assert_eq!(removed, vec![(b"expiredstagedaddress".to_vec(), expired_staged)]);
+	assert_eq!(test_db.get_chunk(b"expiredstagedaddress", 0).await.unwrap(), None);
+
+	assert!(test_db.spend_token(b"tokennonce").await.unwrap());
+	assert!(!test_db.spend_token(b"tokennonce").await.unwrap());
+	assert!(test_db.spend_token(b"othertokennonce").await.unwrap());
+	test_db.restore_token(b"tokennonce").await.unwrap();
+	assert!(test_db.spend_token(b"tokennonce").await.unwrap());
+}
+
+#[tokio::test]
+async fn message_db_behaviour() {
+	let test_db_path = String::from("test_message_db_behaviour.sled");
+	let test_db = MessageDb::test_connection(&test_db_path);
+	behaviour_suite(&test_db).await;
+	MessageDb::destroy_test_connection(&test_db_path).await.unwrap();
+}
+
+#[tokio::test]
+async fn message_db_migrates_legacy_shares() {
+	let test_db_path = String::from("test_message_db_legacy.sled");
+	{
+		let legacy = sled::open(&test_db_path).unwrap();
+		let messages = legacy.open_tree("messages").unwrap();
+		messages.insert(b"legacyaddress", b"Share stored as raw content".to_vec()).unwrap();
+		legacy.flush().unwrap();
+	}
+
+	let now = unix_now();
+	let test_db = MessageDb::test_connection(&test_db_path);
+	let migrated = test_db.get_message(b"legacyaddress").await.unwrap().unwrap();
+	assert_eq!(migrated.content, b"Share stored as raw content".to_vec());
+	assert_eq!(migrated.ttl, LEGACY_TTL);
+	assert!(migrated.inserted_at >= now);
+	assert!(test_db.remove_expired(unix_now()).await.unwrap().is_empty());
+	let removed = test_db.remove_expired(migrated.expires_at()).await.unwrap();
+	assert_eq!(removed, vec![(b"legacyaddress".to_vec(), migrated)]);
+	drop(test_db);
+	MessageDb::destroy_test_connection(&test_db_path).await.unwrap();
+}
+
+#[tokio::test]
+async fn memory_db_behaviour() {
+	let test_db = MemoryDb::test_connection("");
+	behaviour_suite(&test_db).await;
+}
+
+#[tokio::test]
+async fn encrypted_db_behaviour() {
+	let test_db = EncryptedDb::<MemoryDb>::test_connection("");
+	behaviour_suite(&test_db).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_db_behaviour() {
+	let test_db_path = String::from("test_sqlite_db_behaviour.sqlite");
+	let test_db = SqliteDb::test_connection(&test_db_path);
+	behaviour_suite(&test_db).await;
+	SqliteDb::destroy_test_connection(&test_db_path).await.unwrap();
+}
@@ -0,0 +1,432 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+
+use dione_lib::hashing::{hash_bytes, HashAlgorithm, StreamHasher};
+
+use crate::db::{MessageStoreDb, MessageEntry, DbError, SaveOutcome, unix_now, chunk_key};
+use crate::message_storage::HashType;
+
+/// Name of the SQLite database file inside the database directory.
+const SQLITE_FILE: &str = "messages.sqlite3";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+	address BLOB PRIMARY KEY NOT NULL,
+	content BLOB NOT NULL,
+	content_hash BLOB NOT NULL,
+	hash_type INTEGER NOT NULL,
+	inserted_at INTEGER NOT NULL,
+	expires_at INTEGER NOT NULL,
+	delete_commitment BLOB
+);
+CREATE INDEX IF NOT EXISTS messages_expires_at ON messages (expires_at);
+CREATE TABLE IF NOT EXISTS chunks (
+	address BLOB NOT NULL,
+	seq INTEGER NOT NULL,
+	data BLOB NOT NULL,
+	PRIMARY KEY (address, seq)
+);
+CREATE TABLE IF NOT EXISTS staged (
+	upload_id BLOB NOT NULL,
+	seq INTEGER NOT NULL,
+	data BLOB NOT NULL,
+	PRIMARY KEY (upload_id, seq)
+);
+CREATE TABLE IF NOT EXISTS spent_tokens (
+	nonce BLOB PRIMARY KEY NOT NULL
+);
+";
+
+/// [MessageStoreDb] keeping shares in an SQLite database, so they can be inspected and backed up with standard tools.
+///
+/// Queries run in the blocking thread pool, as SQLite does blocking I/O.
+#[derive(Debug)]
+pub struct SqliteDb {
+	conn: Arc<Mutex<Connection>>,
+	stored_bytes: AtomicU64,
+}
+
+/// Number of content bytes of the entry at `address` and its chunks.
+fn content_len(conn: &Connection, address: &[u8]) -> rusqlite::Result<u64> {
+	let len: i64 = conn.query_row(
+		"SELECT (SELECT COALESCE(SUM(LENGTH(content)), 0) FROM messages WHERE address = ?1) + (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM chunks WHERE address = ?1)",
+		params![address],
+		|row| row.get(0)
+	)?;
+	Ok(len as u64)
+}
+
+/// Inserts `entry` at `address`, replacing any previous entry and its chunks. `hash` is the SHA-512 hash of the whole
+/// share. Returns the number of content bytes replaced.
+fn insert_entry(conn: &Connection, address: &[u8], entry: &MessageEntry, hash: &[u8]) -> rusqlite::Result<u64> {
+	let replaced = content_len(conn, address)?;
+	conn.execute("DELETE FROM chunks WHERE address = ?1", params![address])?;
+	conn.execute(
+		"INSERT OR REPLACE INTO messages (address, content, content_hash, hash_type, inserted_at, expires_at, delete_commitment) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+		params![address, &entry.content, hash, HashType::Sha512 as i32, entry.inserted_at as i64, expires_at(entry), &entry.delete_commitment]
+	)?;
+	Ok(replaced)
+}
+
+fn expires_at(entry: &MessageEntry) -> i64 {
+	entry.expires_at().min(i64::MAX as u64) as i64
+}
+
+/// Reads an entry from a row of the columns `content, inserted_at, expires_at, delete_commitment`.
+fn read_entry(row: &Row<'_>) -> rusqlite::Result<MessageEntry> {
+	let content: Vec<u8> = row.get(0)?;
+	let inserted_at: i64 = row.get(1)?;
+	let expires_at: i64 = row.get(2)?;
+	let delete_commitment: Option<Vec<u8>> = row.get(3)?;
+	Ok(MessageEntry {
+		content,
+		inserted_at: inserted_at as u64,
+		ttl: (expires_at - inserted_at) as u64,
+		delete_commitment,
+	})
+}
+
+impl SqliteDb {
+	/// Runs `f` on the connection in the blocking thread pool.
+	async fn with_conn<F, R>(&self, f: F) -> anyhow::Result<R>
+	where
+		F: FnOnce(&mut Connection) -> anyhow::Result<R> + Send + 'static,
+		R: Send + 'static,
+	{
+		let conn = self.conn.clone();
+		tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap())).await?
+	}
+
+	/// Applies a change of the stored content from `replaced` to `added` bytes to the running total.
+	fn account(&self, added: u64, replaced: u64) {
+		self.stored_bytes.fetch_add(added, Ordering::SeqCst);
+		self.stored_bytes.fetch_sub(replaced, Ordering::SeqCst);
+	}
+}
+
+#[async_trait]
+impl MessageStoreDb for SqliteDb {
+	fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+		std::fs::create_dir_all(&path)?;
+		let conn = Connection::open(path.as_ref().join(SQLITE_FILE))?;
+		conn.execute_batch(SCHEMA)?;
+		// Uploads interrupted by a shutdown can't be resumed.
+		conn.execute("DELETE FROM staged", params![])?;
+		let stored: i64 = conn.query_row(
+			"SELECT (SELECT COALESCE(SUM(LENGTH(content)), 0) FROM messages) + (SELECT COALESCE(SUM(LENGTH(data)), 0) FROM chunks)",
+			params![],
+			|row| row.get(0)
+		)?;
+		Ok(Self {
+			conn: Arc::new(Mutex::new(conn)),
+			stored_bytes: AtomicU64::new(stored as u64),
+		})
+	}
+
+	async fn save_message(&self, address: &[u8], entry: MessageEntry) -> anyhow::Result<SaveOutcome> {
+		let address = address.to_vec();
+		let added = entry.content.len() as u64;
+		let replaced = self.with_conn(move |conn| {
+			let tx = conn.transaction()?;
+			let current = tx.query_row(
+				"SELECT content, inserted_at, expires_at, delete_commitment FROM messages WHERE address = ?1 AND expires_at > ?2",
+				params![address, unix_now() as i64],
+				read_entry
+			).optional()?;
+			if let Some(current) = current {
+				let chunked: bool = tx.query_row("SELECT EXISTS (SELECT 1 FROM chunks WHERE address = ?1)", params![address], |row| row.get(0))?;
+				return if current.content == entry.content && !chunked {
+					Ok(None)
+				} else {
+					Err(DbError::AlreadyExists.into())
+				}
+			}
+			let replaced = insert_entry(&tx, &address, &entry, &hash_bytes(HashAlgorithm::Sha512, &entry.content))?;
+			tx.commit()?;
+			Ok(Some(replaced))
+		}).await?;
+		match replaced {
+			Some(replaced) => {
+				self.account(added, replaced);
+				Ok(SaveOutcome::Stored)
+			}
+			None => Ok(SaveOutcome::Duplicate),
+		}
+	}
+
+	async fn save_staged(&self, address: &[u8], upload_id: &[u8], entry: MessageEntry) -> anyhow::Result<()> {
+		let address = address.to_vec();
+		let upload_id = upload_id.to_vec();
+		let (added, replaced) = self.with_conn(move |conn| {
+			let tx = conn.transaction()?;
+			let occupied: bool = tx.query_row(
+				"SELECT EXISTS (SELECT 1 FROM messages WHERE address = ?1 AND expires_at > ?2)",
+				params![address, unix_now() as i64],
+				|row| row.get(0)
+			)?;
+			if occupied {
+				return Err(DbError::AlreadyExists.into())
+			}
+			// The hash is computed one staged chunk at a time.
+			let mut hasher = StreamHasher::new(HashAlgorithm::Sha512);
+			hasher.update(&entry.content);
+			let mut added = entry.content.len() as u64;
+			{
+				let mut stmt = tx.prepare("SELECT data FROM staged WHERE upload_id = ?1 ORDER BY seq")?;
+				let mut rows = stmt.query(params![upload_id])?;
+				while let Some(row) = rows.next()? {
+					let chunk = row.get_ref(0)?.as_blob()?;
+					hasher.update(chunk);
+					added += chunk.len() as u64;
+				}
+			}
+			let replaced = insert_entry(&tx, &address, &entry, &hasher.finalize())?;
+			tx.execute(
+				"INSERT INTO chunks (address, seq, data) SELECT ?1, seq, data FROM staged WHERE upload_id = ?2",
+				params![address, upload_id]
+			)?;
+			tx.execute("DELETE FROM staged WHERE upload_id = ?1", params![upload_id])?;
+			tx.commit()?;
+			Ok((added, replaced))
+		}).await?;
+		self.account(added, replaced);
+		Ok(())
+	}
+
+	async fn get_entry(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let address = address.to_vec();
+		self.with_conn(move |conn| {
+			let message = conn.query_row(
+				"SELECT content, inserted_at, expires_at, delete_commitment FROM messages WHERE address = ?1 AND expires_at > ?2",
+				params![address, unix_now() as i64],
+				read_entry
+			).optional()?;
+			Ok(message)
+		}).await
+	}
+
+	async fn get_chunk(&self, address: &[u8], index: u32) -> anyhow::Result<Option<Vec<u8>>> {
+		let address = address.to_vec();
+		self.with_conn(move |conn| {
+			let chunk = conn.query_row(
+				"SELECT data FROM chunks WHERE address = ?1 AND seq = ?2",
+				params![address, index],
+				|row| row.get(0)
+			).optional()?;
+			Ok(chunk)
+		}).await
+	}
+
+	async fn remove_message(&self, address: &[u8]) -> anyhow::Result<Option<MessageEntry>> {
+		let address = address.to_vec();
+		let (prev, removed) = self.with_conn(move |conn| {
+			let tx = conn.transaction()?;
+			let prev = tx.query_row(
+				"SELECT content, inserted_at, expires_at, delete_commitment FROM messages WHERE address = ?1",
+				params![address],
+				read_entry
+			).optional()?;
+			let removed = content_len(&tx, &address)?;
+			tx.execute("DELETE FROM messages WHERE address = ?1", params![address])?;
+			tx.execute("DELETE FROM chunks WHERE address = ?1", params![address])?;
+			tx.commit()?;
+			Ok((prev, removed))
+		}).await?;
+		self.account(0, removed);
+		Ok(prev)
+	}
+
+	async fn remove_expired(&self, now: u64) -> anyhow::Result<Vec<(Vec<u8>, MessageEntry)>> {
+		let (removed, chunks_len) = self.with_conn(move |conn| {
+			let tx = conn.transaction()?;
+			let removed = {
+				let mut stmt = tx.prepare("SELECT content, inserted_at, expires_at, delete_commitment, address FROM messages WHERE expires_at <= ?1")?;
+				let rows = stmt.query_map(params![now as i64], |row| {
+					let address: Vec<u8> = row.get(4)?;
+					Ok((address, read_entry(row)?))
+				})?;
+				rows.collect::<rusqlite::Result<Vec<_>>>()?
+			};
+			let chunks_len: i64 = tx.query_row(
+				"SELECT COALESCE(SUM(LENGTH(data)), 0) FROM chunks WHERE address IN (SELECT address FROM messages WHERE expires_at <= ?1)",
+				params![now as i64],
+				|row| row.get(0)
+			)?;
+			tx.execute("DELETE FROM chunks WHERE address IN (SELECT address FROM messages WHERE expires_at <= ?1)", params![now as i64])?;
+			tx.execute("DELETE FROM messages WHERE expires_at <= ?1", params![now as i64])?;
+			tx.commit()?;
+			Ok((removed, chunks_len as u64))
+		}).await?;
+		let content_len = removed.iter().map(|e| e.1.content.len() as u64).sum::<u64>();
+		self.account(0, content_len + chunks_len);
+		Ok(removed)
+	}
+
+	async fn addresses(&self, now: u64) -> anyhow::Result<Vec<Vec<u8>>> {
+		self.with_conn(move |conn| {
+			let mut stmt = conn.prepare("SELECT address FROM messages WHERE expires_at > ?1")?;
+			let rows = stmt.query_map(params![now as i64], |row| row.get(0))?;
+			let addresses = rows.collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+			Ok(addresses)
+		}).await
+	}
+
+	fn stored_bytes(&self) -> u64 {
+		self.stored_bytes.load(Ordering::SeqCst)
+	}
+
+	async fn append_staged(&self, upload_id: &[u8], chunk: &[u8]) -> anyhow::Result<u64> {
+		let upload_id = upload_id.to_vec();
+		let chunk = chunk.to_vec();
+		self.with_conn(move |conn| {
+			let tx = conn.transaction()?;
+			tx.execute(
+				"INSERT INTO staged (upload_id, seq, data) SELECT ?1, COALESCE(MAX(seq) + 1, 0), ?2 FROM staged WHERE upload_id = ?1",
+				params![upload_id, chunk]
+			)?;
+			let staged: i64 = tx.query_row(
+				"SELECT SUM(LENGTH(data)) FROM staged WHERE upload_id = ?1",
+				params![upload_id],
+				|row| row.get(0)
+			)?;
+			tx.commit()?;
+			Ok(staged as u64)
+		}).await
+	}
+
+	async fn remove_staged(&self, upload_id: &[u8]) -> anyhow::Result<()> {
+		let upload_id = upload_id.to_vec();
+		self.with_conn(move |conn| {
+			conn.execute("DELETE FROM staged WHERE upload_id = ?1", params![upload_id])?;
+			Ok(())
+		}).await
+	}
+
+	async fn spend_token(&self, nonce: &[u8]) -> anyhow::Result<bool> {
+		let nonce = nonce.to_vec();
+		self.with_conn(move |conn| {
+			let inserted = conn.execute("INSERT OR IGNORE INTO spent_tokens (nonce) VALUES (?1)", params![nonce])?;
+			Ok(inserted == 1)
+		}).await
+	}
+
+	async fn restore_token(&self, nonce: &[u8]) -> anyhow::Result<()> {
+		let nonce = nonce.to_vec();
+		self.with_conn(move |conn| {
+			conn.execute("DELETE FROM spent_tokens WHERE nonce = ?1", params![nonce])?;
+			Ok(())
+		}).await
+	}
+
+	#[cfg(test)]
+	fn test_connection<P: AsRef<Path>>(path: P) -> Self {
+		Self::new(path).expect("Error creating db")
+	}
+
+	#[cfg(test)]
+	async fn destroy_test_connection<P: AsRef<Path> + Send>(path: P) -> anyhow::Result<()> {
+		tokio::fs::remove_dir_all(path).await?;
+		Ok(())
+	}
+
+	fn flush(&self) {}
+}
+
+/// Copies every share of the `messages` tree of the sled database at `sled_path` into `target`, together with the
+/// chunks of shares uploaded in chunks.
+///
+/// Raw shares of databases written before shares had a TTL are copied as if saved now with [LEGACY_TTL](crate::db::LEGACY_TTL).
+/// Returns the number of copied shares.
+pub fn migrate_from_sled<P: AsRef<Path>>(sled_path: P, target: &SqliteDb) -> anyhow::Result<usize> {
+	let db = sled::open(sled_path)?;
+	let messages = db.open_tree("messages")?;
+	let mut conn = target.conn.lock().unwrap();
+	let tx = conn.transaction()?;
+	let now = unix_now();
+	let mut copied = 0;
+	let (mut added, mut replaced) = (0, 0);
+	let chunks = db.open_tree("chunks")?;
+	for e in messages.iter() {
+		let (address, entry_bytes) = e?;
+		let entry = MessageEntry::from_stored(&entry_bytes, now);
+		let mut hasher = StreamHasher::new(HashAlgorithm::Sha512);
+		hasher.update(&entry.content);
+		let mut seq = 0u32;
+		while let Some(chunk) = chunks.get(chunk_key(&address, seq))? {
+			hasher.update(&chunk);
+			seq += 1;
+		}
+		replaced += insert_entry(&tx, &address, &entry, &hasher.finalize())?;
+		added += entry.content.len() as u64;
+		for seq in 0..seq {
+			if let Some(chunk) = chunks.get(chunk_key(&address, seq))? {
+				tx.execute("INSERT INTO chunks (address, seq, data) VALUES (?1, ?2, ?3)", params![&address[..], seq, &chunk[..]])?;
+				added += chunk.len() as u64;
+			}
+		}
+		copied += 1;
+	}
+	tx.commit()?;
+	target.account(added, replaced);
+	Ok(copied)
+}
+
+#[tokio::test]
+async fn migrate_sled_messages() {
+	use crate::db::MessageDb;
+
+	let sled_path = String::from("test_migrate_source.sled");
+	let sqlite_path = String::from("test_migrate_target.sqlite");
+	let entry = MessageEntry::new(b"This is just testcontent".to_vec(), 1_000);
+	{
+		let source = MessageDb::test_connection(&sled_path);
+		source.save_message(b"thisisatestaddress", entry.clone()).await.unwrap();
+		source.append_staged(b"uploadid", b"first chunk").await.unwrap();
+		source.append_staged(b"uploadid", b"second chunk").await.unwrap();
+		source.save_staged(b"chunkedaddress", b"uploadid", MessageEntry::new(Vec::new(), 1_000)).await.unwrap();
+		source.flush();
+	}
+
+	let target = SqliteDb::test_connection(&sqlite_path);
+	let copied = migrate_from_sled(&sled_path, &target).unwrap();
+	let migrated = target.get_message(b"thisisatestaddress").await.unwrap();
+	let chunked = target.get_message(b"chunkedaddress").await.unwrap().unwrap();
+	let stored_bytes = target.stored_bytes();
+
+	MessageDb::destroy_test_connection(&sled_path).await.unwrap();
+	SqliteDb::destroy_test_connection(&sqlite_path).await.unwrap();
+
+	assert_eq!(copied, 2);
+	assert_eq!(stored_bytes, (entry.content.len() + chunked.content.len()) as u64);
+	assert_eq!(migrated, Some(entry));
+	assert_eq!(chunked.content, b"first chunksecond chunk".to_vec());
+}
+
+#[tokio::test]
+async fn migrate_legacy_sled_messages() {
+	let sled_path = String::from("test_migrate_legacy_source.sled");
+	let sqlite_path = String::from("test_migrate_legacy_target.sqlite");
+	{
+		let source = sled::open(&sled_path).unwrap();
+		let messages = source.open_tree("messages").unwrap();
+		messages.insert(b"legacyaddress", b"Share stored as raw content".to_vec()).unwrap();
+		source.flush().unwrap();
+	}
+
+	let now = unix_now();
+	let target = SqliteDb::test_connection(&sqlite_path);
+	let copied = migrate_from_sled(&sled_path, &target).unwrap();
+	let migrated = target.get_message(b"legacyaddress").await.unwrap();
+
+	tokio::fs::remove_dir_all(&sled_path).await.unwrap();
+	SqliteDb::destroy_test_connection(&sqlite_path).await.unwrap();
+
+	assert_eq!(copied, 1);
+	let migrated = migrated.unwrap();
+	assert_eq!(migrated.content, b"Share stored as raw content".to_vec());
+	assert_eq!(migrated.ttl, crate::db::LEGACY_TTL);
+	assert!(migrated.inserted_at >= now);
+}
@@ -0,0 +1,37 @@
+use tracing::*;
+
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::db::MessageStoreDb;
+use crate::network::{Client, FetchResponse, InboundFetch};
+
+/// Serves the shares in `db` to other nodes, which pass them on to their clients.
+pub async fn run<T: MessageStoreDb + Send + Sync>(db: Arc<T>, client: Client, mut requests: mpsc::Receiver<InboundFetch>) {
+	while let Some(inbound) = requests.recv().await {
+		let response = match db.get_message(&inbound.request.address).await {
+			Ok(Some(entry)) => FetchResponse::Found(entry.content),
+			Ok(None) => FetchResponse::NotFound,
+			Err(e) => {
+				event!(Level::ERROR, "Error loading share fetched by {}: {:?}", inbound.peer, e);
+				FetchResponse::NotFound
+			}
+		};
+		client.respond_fetch(inbound.channel, response).await;
+	}
+}
+
+/// Fetches the share at `address` from the first of its providers that still has it.
+pub async fn fetch_from_providers(client: &Client, address: &[u8]) -> Option<Vec<u8>> {
+	let local_peer_id = client.local_peer_id();
+	for peer in client.get_providers(address.to_vec()).await {
+		if peer == local_peer_id {
+			continue
+		}
+		match client.fetch(peer, address.to_vec()).await {
+			Ok(FetchResponse::Found(d)) => return Some(d),
+			Ok(FetchResponse::NotFound) => event!(Level::DEBUG, "{} no longer provides share", peer),
+			Err(e) => event!(Level::INFO, "Fetching share from {} failed: {:?}", peer, e),
+		}
+	}
+	None
+}
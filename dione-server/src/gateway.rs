@@ -0,0 +1,251 @@
+use tracing::instrument;
+
+use std::fmt::Debug;
+use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+use prost::Message;
+use serde::{Serialize, Deserialize};
+use tonic::{Code, Request, Status};
+use crate::db::MessageStoreDb;
+use crate::message_storage::{SaveMessageRequest, SaveMessageResponse, GetMessageRequest, ServerLocRequest, ServerLocResponse, RateToken};
+use crate::message_storage::message_storage_server::MessageStorage;
+use crate::message_storage::location_server::Location;
+use crate::tonic_responder::message_storer::MessageStorer;
+use crate::tonic_responder::location::LocationService;
+
+/// Largest JSON body accepted. Leaves room for base64 encoded shares up to the unary limit of the gRPC service.
+const MAX_JSON_SIZE: usize = 8 * 1024 * 1024;
+
+/// Share to save, mirroring [SaveMessageRequest]. Binary fields are base64 encoded.
+#[derive(Debug, Deserialize)]
+pub struct SaveMessageJson {
+    addr: String,
+    content: String,
+    ttl: Option<u64>,
+    delete_commitment: Option<String>,
+    pow_nonce: Option<u64>,
+    token: Option<RateTokenJson>,
+    hash_type: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateTokenJson {
+    nonce: String,
+    signature: String,
+}
+
+impl SaveMessageJson {
+    fn into_request(self) -> Result<SaveMessageRequest, base64::DecodeError> {
+        let token = match self.token {
+            Some(d) => Some(RateToken {
+                nonce: base64::decode(d.nonce)?,
+                signature: base64::decode(d.signature)?,
+            }),
+            None => None,
+        };
+        Ok(SaveMessageRequest {
+            addr: base64::decode(self.addr)?,
+            content: base64::decode(self.content)?,
+            ttl: self.ttl,
+            delete_commitment: self.delete_commitment.map(base64::decode).transpose()?,
+            pow_nonce: self.pow_nonce,
+            token,
+            hash_type: self.hash_type,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SaveMessageResponseJson {
+    code: i32,
+    hash: Option<String>,
+    hash_type: Option<i32>,
+}
+
+impl From<SaveMessageResponse> for SaveMessageResponseJson {
+    fn from(response: SaveMessageResponse) -> Self {
+        Self {
+            code: response.code,
+            hash: response.hash.map(base64::encode),
+            hash_type: response.hash_type,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageJson {
+    addr: String,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerAddressJson {
+    addr_type: i32,
+    addr: String,
+}
+
+impl From<ServerLocResponse> for ServerAddressJson {
+    fn from(response: ServerLocResponse) -> Self {
+        Self {
+            addr_type: response.addrtype,
+            addr: response.addr,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerAddressesJson {
+    addrs: Vec<ServerAddressJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorJson {
+    code: i32,
+    message: String,
+}
+
+/// Registers the JSON endpoints mirroring the gRPC services.
+///
+/// Addresses in paths are base64 encoded with the URL safe alphabet, everything else with the standard one.
+pub fn routes<T: MessageStoreDb + Send + Sync + Debug + 'static>(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(web::resource("/api/messages")
+            .app_data(web::JsonConfig::default().limit(MAX_JSON_SIZE))
+            .route(web::post().to(save_message::<T>)))
+        .service(web::resource("/api/messages/{addr}").route(web::get().to(get_message::<T>)))
+        .service(web::resource("/api/messages/{addr}/providers").route(web::get().to(message_look_up)))
+        .service(web::resource("/api/lookup/{addr}").route(web::get().to(look_up)));
+}
+
+#[allow(clippy::async_yields_async)]
+#[instrument(skip(storer, body))]
+async fn save_message<T: MessageStoreDb + Send + Sync + Debug + 'static>(
+    storer: web::Data<MessageStorer<T>>,
+    body: web::Json<SaveMessageJson>,
+) -> HttpResponse {
+    let request = match body.into_inner().into_request() {
+        Ok(d) => d,
+        Err(e) => return invalid_base64(e),
+    };
+    match storer.save_message(Request::new(request)).await {
+        Ok(d) => HttpResponse::Ok().json(SaveMessageResponseJson::from(d.into_inner())),
+        Err(e) => error_response(e),
+    }
+}
+
+#[allow(clippy::async_yields_async)]
+#[instrument(skip(storer))]
+async fn get_message<T: MessageStoreDb + Send + Sync + Debug + 'static>(
+    storer: web::Data<MessageStorer<T>>,
+    addr: web::Path<String>,
+) -> HttpResponse {
+    let addr = match base64::decode_config(addr.into_inner(), base64::URL_SAFE_NO_PAD) {
+        Ok(d) => d,
+        Err(e) => return invalid_base64(e),
+    };
+    match storer.get_message(Request::new(GetMessageRequest { addr })).await {
+        Ok(d) => {
+            let d = d.into_inner();
+            HttpResponse::Ok().json(MessageJson {
+                addr: base64::encode(d.addr),
+                content: base64::encode(d.content),
+            })
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+#[allow(clippy::async_yields_async)]
+#[instrument(skip(locer))]
+async fn look_up(locer: web::Data<LocationService>, addr: web::Path<String>) -> HttpResponse {
+    let addr = match base64::decode_config(addr.into_inner(), base64::URL_SAFE_NO_PAD) {
+        Ok(d) => d,
+        Err(e) => return invalid_base64(e),
+    };
+    match locer.look_up(Request::new(ServerLocRequest { addr })).await {
+        Ok(d) => HttpResponse::Ok().json(ServerAddressJson::from(d.into_inner())),
+        Err(e) => error_response(e),
+    }
+}
+
+#[allow(clippy::async_yields_async)]
+#[instrument(skip(locer))]
+async fn message_look_up(locer: web::Data<LocationService>, addr: web::Path<String>) -> HttpResponse {
+    let addr = match base64::decode_config(addr.into_inner(), base64::URL_SAFE_NO_PAD) {
+        Ok(d) => d,
+        Err(e) => return invalid_base64(e),
+    };
+    match locer.message_look_up(Request::new(ServerLocRequest { addr })).await {
+        Ok(d) => HttpResponse::Ok().json(ServerAddressesJson {
+            addrs: d.into_inner().addrs.into_iter().map(ServerAddressJson::from).collect(),
+        }),
+        Err(e) => error_response(e),
+    }
+}
+
+fn invalid_base64(e: base64::DecodeError) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorJson {
+        code: 400,
+        message: format!("Invalid base64: {}", e),
+    })
+}
+
+/// Response for a failed request, with the same code the gRPC service reports for items of batch requests.
+fn error_response(status: Status) -> HttpResponse {
+    let code = match SaveMessageResponse::decode(status.details()) {
+        Ok(d) if d.code != 0 => d.code,
+        _ => http_code(status.code()),
+    };
+    let status_code = StatusCode::from_u16(code as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    HttpResponse::build(status_code).json(ErrorJson {
+        code,
+        message: status.message().to_owned(),
+    })
+}
+
+fn http_code(code: Code) -> i32 {
+    match code {
+        Code::InvalidArgument => 400,
+        Code::Unauthenticated => 401,
+        Code::FailedPrecondition => 402,
+        Code::PermissionDenied => 403,
+        Code::NotFound => 404,
+        Code::AlreadyExists => 409,
+        Code::OutOfRange => 416,
+        Code::ResourceExhausted => 429,
+        Code::Unimplemented => 501,
+        Code::Unavailable => 503,
+        _ => 500,
+    }
+}
+
+#[test]
+fn decode_save_message_json() {
+    let json = SaveMessageJson {
+        addr: base64::encode(b"thisisatestaddress"),
+        content: base64::encode(b"This is just testcontent"),
+        ttl: Some(60),
+        delete_commitment: None,
+        pow_nonce: None,
+        token: Some(RateTokenJson {
+            nonce: base64::encode(b"tokennonce"),
+            signature: String::from("not base64!"),
+        }),
+        hash_type: None,
+    };
+    assert!(json.into_request().is_err());
+
+    let json = SaveMessageJson {
+        addr: base64::encode(b"thisisatestaddress"),
+        content: base64::encode(b"This is just testcontent"),
+        ttl: Some(60),
+        delete_commitment: None,
+        pow_nonce: None,
+        token: None,
+        hash_type: None,
+    };
+    let request = json.into_request().unwrap();
+    assert_eq!(request.addr, b"thisisatestaddress".to_vec());
+    assert_eq!(request.content, b"This is just testcontent".to_vec());
+    assert_eq!(request.ttl, Some(60));
+}
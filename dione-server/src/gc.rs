@@ -0,0 +1,27 @@
+use tracing::*;
+
+use std::sync::Arc;
+use std::time::Duration;
+use crate::db::{MessageStoreDb, unix_now};
+use crate::network::Client;
+
+/// Periodically removes expired shares from the database and stops providing them in the DHT.
+pub async fn run<T: MessageStoreDb + Send + Sync>(db: Arc<T>, client: Client, interval: Duration) {
+	let mut interval = tokio::time::interval(interval);
+	loop {
+		interval.tick().await;
+		let removed = match db.remove_expired(unix_now()).await {
+			Ok(d) => d,
+			Err(e) => {
+				event!(Level::ERROR, "Error removing expired shares: {:?}", e);
+				continue;
+			}
+		};
+		if !removed.is_empty() {
+			event!(Level::INFO, "Removed {} expired shares", removed.len());
+		}
+		for (address, _) in removed {
+			client.stop_providing(address).await;
+		}
+	}
+}
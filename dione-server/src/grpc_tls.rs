@@ -0,0 +1,118 @@
+use tracing::*;
+
+use std::io::BufReader;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::rustls::{ServerConfig, NoClientAuth, ResolvesServerCert, ClientHello};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Time a client gets to complete its TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of TLS handshakes in progress at once. Further connections wait until a handshake is done.
+const MAX_CONCURRENT_HANDSHAKES: usize = 128;
+
+/// Certificate of the gRPC endpoint, which can be swapped while connections stay open.
+pub struct ReloadableCert {
+	public_key: PathBuf,
+	private_key: PathBuf,
+	current: RwLock<CertifiedKey>,
+}
+
+impl ReloadableCert {
+	/// Loads the certificate keychain at `public_key` with its private key at `private_key`.
+	pub fn load(public_key: &Path, private_key: &Path) -> anyhow::Result<Self> {
+		let current = certified_key(public_key, private_key)?;
+		Ok(Self {
+			public_key: public_key.to_owned(),
+			private_key: private_key.to_owned(),
+			current: RwLock::new(current),
+		})
+	}
+
+	/// Reads the key pair again. New handshakes use it, while the previous certificate is kept if reading fails.
+	pub fn reload(&self) -> anyhow::Result<()> {
+		let reloaded = certified_key(&self.public_key, &self.private_key)?;
+		*self.current.write().unwrap() = reloaded;
+		Ok(())
+	}
+}
+
+impl ResolvesServerCert for ReloadableCert {
+	fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+		Some(self.current.read().unwrap().clone())
+	}
+}
+
+fn certified_key(public_key: &Path, private_key: &Path) -> anyhow::Result<CertifiedKey> {
+	let cert_chain = certs(&mut BufReader::new(File::open(public_key)?))
+		.map_err(|_| anyhow::Error::msg(format!("Invalid certificate in {:?}", public_key)))?;
+	let mut keys = pkcs8_private_keys(&mut BufReader::new(File::open(private_key)?))
+		.map_err(|_| anyhow::Error::msg(format!("Invalid private key in {:?}", private_key)))?;
+	if keys.is_empty() {
+		keys = rsa_private_keys(&mut BufReader::new(File::open(private_key)?))
+			.map_err(|_| anyhow::Error::msg(format!("Invalid private key in {:?}", private_key)))?;
+	}
+	let key = keys.first()
+		.ok_or_else(|| anyhow::Error::msg(format!("No private key in {:?}", private_key)))?;
+	let key = sign::any_supported_type(key)
+		.map_err(|_| anyhow::Error::msg(format!("Unsupported private key in {:?}", private_key)))?;
+	Ok(CertifiedKey::new(cert_chain, Arc::new(key)))
+}
+
+/// Accepts connections on `addr` and hands them to the gRPC server once their TLS handshake is done.
+///
+/// Handshakes run in their own tasks, so a slow client can't hold up others. They are limited in time and number, so
+/// clients that never finish theirs can't exhaust the node.
+pub async fn incoming(addr: SocketAddr, cert: Arc<ReloadableCert>) -> anyhow::Result<ReceiverStream<std::io::Result<TlsStream<TcpStream>>>> {
+	let mut config = ServerConfig::new(NoClientAuth::new());
+	config.cert_resolver = cert;
+	config.set_protocols(&[b"h2".to_vec()]);
+	let acceptor = TlsAcceptor::from(Arc::new(config));
+	let listener = TcpListener::bind(addr).await?;
+
+	let (sender, receiver) = mpsc::channel(32);
+	let handshakes = Arc::new(Semaphore::new(MAX_CONCURRENT_HANDSHAKES));
+	tokio::spawn(async move {
+		loop {
+			let permit = match handshakes.clone().acquire_owned().await {
+				Ok(d) => d,
+				Err(_) => break,
+			};
+			let stream = match listener.accept().await {
+				Ok((d, _)) => d,
+				Err(e) => {
+					event!(Level::WARN, "Error accepting connection to gRPC endpoint: {:?}", e);
+					continue;
+				}
+			};
+			if sender.is_closed() {
+				break
+			}
+			let acceptor = acceptor.clone();
+			let sender = sender.clone();
+			tokio::spawn(async move {
+				let accepted = timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await;
+				drop(permit);
+				match accepted {
+					Ok(Ok(d)) => {
+						let _ = sender.send(Ok(d)).await;
+					}
+					Ok(Err(e)) => event!(Level::DEBUG, "TLS handshake with client failed: {:?}", e),
+					Err(_) => event!(Level::DEBUG, "TLS handshake with client timed out"),
+				}
+			});
+		}
+	});
+	Ok(ReceiverStream::new(receiver))
+}
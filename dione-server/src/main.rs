@@ -2,40 +2,78 @@ use tonic::transport::Server;
 use tracing::Level;
 
 use structopt::StructOpt;
-use libp2p::{Multiaddr, PeerId};
+use structopt::clap::AppSettings;
+use libp2p::Multiaddr;
 use libp2p::multiaddr::Protocol;
+use libp2p::identity::{ed25519, Keypair};
 use crate::tonic_responder::message_storer::MessageStorer;
 use crate::message_storage::ServerAddressType;
 use crate::tonic_responder::location::LocationService;
-use crate::db::{MessageDb, MessageStoreDb};
+use crate::db::{MessageDb, MessageStoreDb, LimitedDb, Limits, MemoryDb, StorageKind, AtRestKey, EncryptedDb};
+use crate::network::{Client, InboundReplica, InboundFetch};
+use crate::grpc_tls::ReloadableCert;
+#[cfg(feature = "sqlite")]
+use crate::db::SqliteDb;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::fmt::Debug;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use tokio::sync::mpsc;
+use tokio::signal::unix::{signal, SignalKind};
 use actix_rt::{System, Arbiter};
 use rustls::{ServerConfig, NoClientAuth};
 use std::io::BufReader;
 use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::io::Write;
 use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+use dione_lib::cryptography::blind_token::IssuerKey;
+
+/// Name of the identity key file in the database directory, if no other path is given.
+const IDENTITY_KEY_FILE: &str = "identity.key";
+/// Directory in the database directory holding the Kademlia records and providers.
+const KAD_STORE_DIR: &str = "kad";
 
 pub(crate) mod message_storage {
 	include!(concat!(env!("OUT_DIR"), "/messagestorage.rs"));
 }
 
+mod bootstrap;
+mod config;
 mod db;
+mod gc;
+mod grpc_tls;
+mod notify;
+mod fetch;
+mod gateway;
+mod replication;
 mod tonic_responder;
 mod network;
 mod web_service;
 
 #[derive(Debug, StructOpt, Clone)]
-#[structopt(name = "Dione Server", about="Implementation of the server part of Dione.", version = "0.1.0-alpha")]
+#[structopt(name = "Dione Server", about="Implementation of the server part of Dione.", version = "0.1.0-alpha", setting = AppSettings::AllArgsOverrideSelf)]
 struct Opt {
+	/// Path to TOML config file
+	///
+	/// Sets any of the other options, named like the flags with underscores instead of dashes. Flags passed on the command line override values of the file.
+	/// Lists like `--bootstrap` given on the command line replace the list of the file, switches set in the file are turned off with `--no-<flag>`, e.g. `--no-proxy`.
+	/// Run the `default-config` subcommand for a commented example.
+	#[structopt(long)]
+	config: Option<PathBuf>,
+
 	/// External Address of gRPC server
 	///
 	/// The address where the gRPC server listens  for incoming connections from clients. Important are interface and port.
 	///
 	/// Example for listening on all interfaces with port 8010: 0.0.0.0:8010
 	#[structopt(long)]
-	ex: SocketAddr,
+	ex: Option<SocketAddr>,
 
 	/// External Address of libp2p part
 	///
@@ -46,6 +84,31 @@ struct Opt {
 	#[structopt(long, short)]
 	listen_address: Option<Multiaddr>,
 
+	/// Multiaddresses of bootstrap peers
+	///
+	/// The node dials these peers on startup to join the network and retries unreachable ones with backoff. Can be passed several times.
+	/// Every address has to end with the PeerId of the peer. Alternatively a comma separated list can be passed in the environment variable PEER.
+	///
+	/// Example: /ip4/127.0.0.1/tcp/4001/p2p/12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA
+	#[structopt(long)]
+	bootstrap: Vec<Multiaddr>,
+
+	/// Discover peers in the local network
+	///
+	/// Peers announced via mDNS on the same host or LAN are added to the routing table, so local test-nets need no bootstrap peers.
+	#[structopt(long, overrides_with = "no-mdns")]
+	mdns: bool,
+
+	/// Don't discover peers in the local network, even if the config file says so
+	#[structopt(long = "no-mdns", name = "no-mdns", overrides_with = "mdns")]
+	_no_mdns: bool,
+
+	/// Interval of the Kademlia bootstrap in seconds
+	///
+	/// Specifies how often the node refreshes its routing table by looking up its own PeerId.
+	#[structopt(long, default_value = "300")]
+	bootstrap_interval: u64,
+
 	/// Clear Address of gRPC server
 	///
 	/// The address where the gRPC server can be contacted. This is very important for assigned domains or if several interfaces where specified.
@@ -59,7 +122,28 @@ struct Opt {
 	///
 	/// Path to the database. If there is no database at the specified location a new one will be created. Target is a directory not a file.
 	#[structopt(short = "db", long)]
-	db_path: PathBuf,
+	db_path: Option<PathBuf>,
+
+	/// Storage backend for shares
+	///
+	/// Either `sled` for persistent storage at the database path or `memory` for volatile storage, that is lost on shutdown.
+	/// If compiled with the `sqlite` feature, `sqlite` stores shares in an SQLite database in the database directory.
+	#[structopt(long, default_value = "sled")]
+	storage: StorageKind,
+
+	/// Path to sled database to migrate
+	///
+	/// Copies all shares of the sled database at this path into the SQLite database in the database directory and exits afterwards.
+	#[cfg(feature = "sqlite")]
+	#[structopt(long)]
+	migrate_from_sled: Option<PathBuf>,
+
+	/// Path to key file for encryption at rest
+	///
+	/// If set, shares are encrypted before they are stored and addresses are replaced by a keyed hash.
+	/// The file has to contain at least 32 bytes of secret key material. Alternatively the key material can be passed in the environment variable AT_REST_KEY.
+	#[structopt(long)]
+	at_rest_key_file: Option<PathBuf>,
 
 	/// HTTP port for web server
 	///
@@ -73,32 +157,191 @@ struct Opt {
 	#[structopt(long, default_value = "8443")]
 	web_https_port: usize,
 
+	/// Default time to live of shares in seconds
+	///
+	/// Shares are deleted after this time, unless the client requested a different time to live.
+	#[structopt(long, default_value = "604800")]
+	default_ttl: u64,
+
+	/// Maximum time to live of shares in seconds
+	///
+	/// Time to live requested by clients is capped to this value.
+	#[structopt(long, default_value = "2592000")]
+	max_ttl: u64,
+
+	/// Interval of the garbage collection in seconds
+	///
+	/// Specifies how often the node looks for expired shares and deletes them.
+	#[structopt(long, default_value = "60")]
+	gc_interval: u64,
+
+	/// Number of replicas of each share
+	///
+	/// Every new share is copied to this many of the peers closest to its address, which provide it as well.
+	#[structopt(long, default_value = "2")]
+	replicas: usize,
+
+	/// Serve shares held by other nodes
+	///
+	/// Shares missing on this node are fetched from their providers, so clients only have to talk to this node.
+	#[structopt(long, overrides_with = "no-proxy")]
+	proxy: bool,
+
+	/// Don't serve shares held by other nodes, even if the config file says so
+	#[structopt(long = "no-proxy", name = "no-proxy", overrides_with = "proxy")]
+	_no_proxy: bool,
+
+	/// Maximum size of a share in bytes
+	///
+	/// Shares exceeding this size are rejected.
+	#[structopt(long, default_value = "4194304")]
+	max_share_size: usize,
+
+	/// Maximum size of a share uploaded in chunks in bytes
+	///
+	/// Chunked uploads never have to fit into a single message, so they may exceed `--max-share-size`.
+	#[structopt(long, default_value = "67108864")]
+	max_chunked_share_size: usize,
+
+	/// Maximum capacity of the node in bytes
+	///
+	/// Shares are rejected once the stored shares occupy this many bytes. Unlimited if not set.
+	#[structopt(long)]
+	max_capacity: Option<u64>,
+
+	/// Maximum number of shares per rate window
+	///
+	/// Limits how many shares the node accepts within one rate window. Unlimited if not set.
+	#[structopt(long)]
+	max_saves_per_window: Option<u64>,
+
+	/// Length of the rate window in seconds
+	#[structopt(long, default_value = "60")]
+	rate_window: u64,
+
+	/// Proof-of-work difficulty in bits
+	///
+	/// Shares are only accepted with a proof-of-work stamp with this many leading zero bits. Every additional bit doubles the work of clients. 0 disables proof of work.
+	#[structopt(long, default_value = "0")]
+	pow_difficulty: u32,
+
+	/// Path to key file for issuing rate-limit tokens
+	///
+	/// If set, the node issues anonymous tokens, that clients redeem instead of proof-of-work stamps. A new key is generated if the file doesn't exist.
+	#[structopt(long)]
+	token_key_file: Option<PathBuf>,
+
+	/// Refuse shares without a token
+	///
+	/// Only comes into effect if a token key file is set.
+	#[structopt(long, overrides_with = "no-require-tokens")]
+	require_tokens: bool,
+
+	/// Accept shares without a token, even if the config file says otherwise
+	#[structopt(long = "no-require-tokens", name = "no-require-tokens", overrides_with = "require_tokens")]
+	_no_require_tokens: bool,
+
+	/// Path to key file of the node identity
+	///
+	/// The libp2p keypair determining the PeerId of the node. A new key is generated if the file doesn't exist.
+	/// Defaults to `identity.key` in the database directory, except with memory storage, where the node gets a new
+	/// identity on every start unless a path is given.
+	#[structopt(long)]
+	identity_key: Option<PathBuf>,
+
 	#[structopt(subcommand)]
-	tls: Option<Tls>,
+	command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+enum Command {
+	#[structopt(flatten)]
+	Tls(TlsCommand),
+	/// Prints a commented config file with the default settings and exits
+	///
+	/// Can be run without any other arguments.
+	DefaultConfig,
+	/// Prints the PeerId of the node and exits
+	///
+	/// Only reads the identity key, or generates it if there is none yet, so neither `--ex` nor `--db-path` are needed
+	/// with `--identity-key`. If a listen address is given, the full multiaddr to pass in the PEER variable of other
+	/// nodes is printed.
+	PrintPeerId,
 }
 
+/// Subcommands for configuring/enabling/disabling TLS of the webserver and the gRPC endpoint
 #[derive(StructOpt, Debug, Clone)]
-#[structopt(about="Subcommand for configuring/enabling/disabling TLS (currently only for webserver)")]
-enum Tls {
+enum TlsCommand {
 	/// Disables TLS (default)
 	NoTls,
 	/// Enables TLS
+	///
+	/// The gRPC endpoint reloads its certificate on SIGHUP.
 	Tls {
 		/// Path to private key of certificate
 		#[structopt(long)]
 		private_key: PathBuf,
 		/// Path to keychain of certificate
 		#[structopt(long)]
-		public_key: PathBuf
+		public_key: PathBuf,
+		/// Path to private key of a separate certificate for the gRPC endpoint
+		#[structopt(long)]
+		grpc_private_key: Option<PathBuf>,
+		/// Path to keychain of a separate certificate for the gRPC endpoint
+		#[structopt(long)]
+		grpc_public_key: Option<PathBuf>,
+	},
+}
+
+impl TlsCommand {
+	/// Paths to keychain and private key of the certificate securing the gRPC endpoint.
+	fn grpc_key_pair(&self) -> Option<(PathBuf, PathBuf)> {
+		match self {
+			TlsCommand::NoTls => None,
+			TlsCommand::Tls { private_key, public_key, grpc_private_key, grpc_public_key } => Some((
+				grpc_public_key.clone().unwrap_or_else(|| public_key.clone()),
+				grpc_private_key.clone().unwrap_or_else(|| private_key.clone()),
+			)),
+		}
 	}
 }
 
-impl Default for Tls {
+impl Default for TlsCommand {
 	fn default() -> Self {
 		Self::NoTls
 	}
 }
 
+impl Opt {
+	/// TLS settings given by subcommand or config file, TLS is disabled without.
+	fn tls(&self) -> TlsCommand {
+		match &self.command {
+			Some(Command::Tls(d)) => d.clone(),
+			_ => TlsCommand::default(),
+		}
+	}
+
+	/// Address of the gRPC server, only optional for subcommands that don't run the node.
+	fn ex(&self) -> anyhow::Result<SocketAddr> {
+		self.ex.ok_or_else(|| anyhow::Error::msg("--ex is required to run the node"))
+	}
+
+	/// Database directory, only optional for subcommands that don't run the node.
+	fn db_path(&self) -> anyhow::Result<&Path> {
+		self.db_path.as_deref().ok_or_else(|| anyhow::Error::msg("--db-path is required to run the node"))
+	}
+
+	/// Path of the identity key, `None` if the node gets a new identity on every start.
+	fn identity_path(&self) -> anyhow::Result<Option<PathBuf>> {
+		match (&self.identity_key, &self.storage) {
+			(Some(d), _) => Ok(Some(d.clone())),
+			// Nothing else of a node with memory storage outlives the process, its identity doesn't either.
+			(None, StorageKind::Memory) => Ok(None),
+			(None, _) => Ok(Some(self.db_path()?.join(IDENTITY_KEY_FILE))),
+		}
+	}
+}
+
 fn main() -> anyhow::Result<()> {
 	let collector = tracing_subscriber::fmt()
 		.with_max_level(Level::INFO)
@@ -106,14 +349,68 @@ fn main() -> anyhow::Result<()> {
 	tracing::subscriber::set_global_default(collector)
 		.expect("Something fucked up during setting up collector");
 
-	let opt = Opt::from_args();
+	let (args, file_tls) = config::merge_args(std::env::args_os().collect())?;
+	let mut opt = Opt::from_iter(args);
+	if let (None, Some(d)) = (&opt.command, file_tls) {
+		opt.command = Some(Command::Tls(TlsCommand::Tls {
+			private_key: d.private_key,
+			public_key: d.public_key,
+			grpc_private_key: d.grpc_private_key,
+			grpc_public_key: d.grpc_public_key,
+		}));
+	}
+
+	if let Some(Command::DefaultConfig) = opt.command {
+		print!("{}", config::DEFAULT_CONFIG);
+		return Ok(())
+	}
+
+	if let Some(d) = &opt.config {
+		println!("Config File => {:?}", d);
+	}
+
+	if let Some(Command::PrintPeerId) = opt.command {
+		let path = opt.identity_path()?
+			.ok_or_else(|| anyhow::Error::msg("With memory storage the node gets a new identity on every start, pass --identity-key to keep one"))?;
+		let peer_id = load_identity(&path)?.public().to_peer_id();
+		let addr = opt.listen_address.clone().unwrap_or_else(Multiaddr::empty);
+		println!("PeerId => {}", peer_id);
+		println!("Multiaddr => {}", addr.with(Protocol::P2p(peer_id.into())));
+		return Ok(())
+	}
+
+	let db_path = opt.db_path()?.to_path_buf();
+	opt.ex()?;
+
+	#[cfg(feature = "sqlite")]
+	if let Some(sled_path) = &opt.migrate_from_sled {
+		let target = SqliteDb::new(&db_path)?;
+		let copied = db::migrate_from_sled(sled_path, &target)?;
+		println!("Migrated {} shares from {:?} to {:?}", copied, sled_path, db_path);
+		return Ok(())
+	}
+
+	let id_keys = match opt.identity_path()? {
+		Some(d) => load_identity(&d)?,
+		None => Keypair::generate_ed25519(),
+	};
 
 	let rt = tokio::runtime::Runtime::new().unwrap();
 
+	let kad_store = match opt.storage {
+		StorageKind::Memory => None,
+		_ => Some(db_path.join(KAD_STORE_DIR)),
+	};
+	let mdns = opt.mdns;
 	let (client, mut event_loop) = rt.block_on( async move {
-		network::new().await.unwrap()
+		network::with_identity(id_keys, kad_store.as_deref(), mdns).await.unwrap()
 	});
 
+	let peer_requests = PeerRequests {
+		replicas: event_loop.inbound_replicas(),
+		fetches: event_loop.inbound_fetches(),
+	};
+
 	let event_loop_handler = rt.spawn(async move {
 		event_loop.run().await
 	});
@@ -134,27 +431,26 @@ fn main() -> anyhow::Result<()> {
 		};
 	});
 
-	let mut client_dial = client.clone();
-
-	if let Some(addr) = std::env::var_os("PEER") {
-		let addr = Multiaddr::from_str(addr.to_str().unwrap()).expect("Couldn't pares multiaddr");
-		let peer_id = match addr.iter().last() {
-			Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).expect("Vaild hash."),
-			_ => panic!("Expect peer multiaddr to contain peer ID.")
-		};
-		rt.block_on(async move {
-			client_dial
-				.dial(peer_id, addr)
-				.await
-				.expect("Dial to succeed");
-		});
+	let mut bootstrap_addrs = opt.bootstrap.clone();
+	if let Some(d) = std::env::var_os("PEER") {
+		for addr in d.to_string_lossy().split(',').map(str::trim).filter(|e| !e.is_empty()) {
+			bootstrap_addrs.push(Multiaddr::from_str(addr)?);
+		}
 	}
+	let bootstrap_peers = bootstrap_addrs.into_iter()
+		.map(bootstrap::parse_peer)
+		.collect::<anyhow::Result<Vec<_>>>()?;
+	rt.spawn(bootstrap::run(client.clone(), bootstrap_peers, Duration::from_secs(opt.bootstrap_interval)));
 
-	let clear_addr: String = match opt.clear_address {
+	let clear_addr: String = match opt.clear_address.clone() {
 		Some(d) => d,
 		None => {
 			let host = std::env::var_os("CLEARADDRESS").expect("Either pass argument or env variable").to_str().unwrap().to_owned();
-			format!("http://{}:8010", host)
+			let scheme = match opt.tls().grpc_key_pair() {
+				Some(_) => "https",
+				None => "http",
+			};
+			format!("{}://{}:8010", scheme, host)
 		}
 	};
 	println!("Clear Address => {}", clear_addr);
@@ -165,71 +461,229 @@ fn main() -> anyhow::Result<()> {
 		println!("Successfully Put Clear Address");
 	});
 
-	let client_clone = client.clone();
+	let signal_handler = rt.spawn(async move {
+		tokio::signal::ctrl_c().await.unwrap();
+		put_clear_address_handler.abort();
+		event_loop_handler.abort();
+	});
 
-	let config: Option<ServerConfig> = match opt.tls.clone() {
-		None => None,
-		Some(e) => {
-			match e {
-				Tls::NoTls => {
-					None
-				}
-				Tls::Tls { public_key, private_key} => {
-					let mut config = ServerConfig::new(NoClientAuth::new());
-					let cert_file = &mut BufReader::new(File::open(public_key).unwrap());
-					let key_file = &mut BufReader::new(File::open(private_key).unwrap());
-					let cert_chain = certs(cert_file).unwrap();
-					let mut keys = pkcs8_private_keys(key_file).unwrap();
-					config.set_single_cert(cert_chain, keys.remove(0)).unwrap();
-					Some(config)
-				}
-			}
+
+
+	let at_rest_key = match &opt.at_rest_key_file {
+		Some(path) => Some(AtRestKey::from_material(&std::fs::read(path)?)?),
+		None => match std::env::var_os("AT_REST_KEY") {
+			Some(d) => Some(AtRestKey::from_material(d.as_bytes())?),
+			None => None,
 		}
 	};
 
+	match opt.storage {
+		StorageKind::Sled => serve_encrypted(&rt, &opt, client, MessageDb::new(&db_path)?, at_rest_key, peer_requests, signal_handler),
+		StorageKind::Memory => serve_encrypted(&rt, &opt, client, MemoryDb::default(), at_rest_key, peer_requests, signal_handler),
+		#[cfg(feature = "sqlite")]
+		StorageKind::Sqlite => serve_encrypted(&rt, &opt, client, SqliteDb::new(&db_path)?, at_rest_key, peer_requests, signal_handler),
+	}
+}
+
+/// Requests of other nodes, answered by the tasks spawned in [serve].
+struct PeerRequests {
+	replicas: mpsc::Receiver<InboundReplica>,
+	fetches: mpsc::Receiver<InboundFetch>,
+}
+
+/// Like [serve], but wraps `db` for encryption at rest if a key is given.
+fn serve_encrypted<T: MessageStoreDb + Send + Sync + Debug + 'static>(
+	rt: &Runtime,
+	opt: &Opt,
+	client: Client,
+	db: T,
+	at_rest_key: Option<AtRestKey>,
+	peer_requests: PeerRequests,
+	signal_handler: JoinHandle<()>
+) -> anyhow::Result<()> {
+	match at_rest_key {
+		Some(key) => serve(rt, opt, client, EncryptedDb::with_key(db, key), peer_requests, signal_handler),
+		None => serve(rt, opt, client, db, peer_requests, signal_handler),
+	}
+}
+
+/// Serves the gRPC services with shares kept in `db` until the process is interrupted.
+fn serve<T: MessageStoreDb + Send + Sync + Debug + 'static>(
+	rt: &Runtime,
+	opt: &Opt,
+	client: Client,
+	db: T,
+	peer_requests: PeerRequests,
+	signal_handler: JoinHandle<()>
+) -> anyhow::Result<()> {
+	let limits = Limits {
+		max_share_size: opt.max_share_size,
+		max_chunked_share_size: opt.max_chunked_share_size,
+		max_capacity: opt.max_capacity,
+		max_saves_per_window: opt.max_saves_per_window,
+		window: Duration::from_secs(opt.rate_window),
+	};
+	let db = Arc::new(LimitedDb::with_limits(db, limits.clone()));
+
+	let gc_handler = rt.spawn(gc::run(db.clone(), client.clone(), Duration::from_secs(opt.gc_interval)));
+
+	let addr = opt.ex()?;
+	let mut greeter = MessageStorer::new(db.clone(), client.clone(), opt.default_ttl, opt.max_ttl)
+		.with_pow_difficulty(opt.pow_difficulty)
+		.with_limits(limits)
+		.with_replicas(opt.replicas)
+		.with_proxy(opt.proxy);
+	if let Some(path) = &opt.token_key_file {
+		greeter = greeter.with_token_issuer(load_token_key(path)?, opt.require_tokens);
+	}
+
+	let replication_handler = rt.spawn(replication::run(db.clone(), client.clone(), greeter.hub(), opt.max_ttl, peer_requests.replicas));
+	let fetch_handler = rt.spawn(fetch::run(db.clone(), client.clone(), peer_requests.fetches));
+	rt.spawn(replication::announce_stored(db.clone(), client.clone()));
+
+	let locer = LocationService::new(client.clone());
+
+	let web_config = web_tls_config(opt);
 	let web_http_port = opt.web_http_port;
 	let web_https_port = opt.web_https_port;
+	let web_storer = Arc::new(greeter.clone());
+	let web_locer = Arc::new(locer.clone());
 
 	let _ = System::new();
 	let arbiter = Arbiter::new();
 	arbiter.spawn(async move {
-		web_service::make_server(client_clone, config, web_http_port, web_https_port).await.unwrap();
+		web_service::make_server(client, web_storer, web_locer, web_config, web_http_port, web_https_port).await.unwrap();
 	});
 
-	let signal_handler = rt.spawn(async move {
-		tokio::signal::ctrl_c().await.unwrap();
-		arbiter.stop();
-		put_clear_address_handler.abort();
-		event_loop_handler.abort();
-	});
-
-
-
-	let db = MessageDb::new(&opt.db_path).unwrap();
-
-	let addr = opt.ex;
-	let greeter = MessageStorer::new(db, client.clone());
-
-	let locer = LocationService::new(client);
+	let grpc_cert = match opt.tls().grpc_key_pair() {
+		Some((public_key, private_key)) => Some(Arc::new(ReloadableCert::load(&public_key, &private_key)?)),
+		None => None,
+	};
+	let incoming = match &grpc_cert {
+		Some(d) => Some(rt.block_on(grpc_tls::incoming(addr, d.clone()))?),
+		None => None,
+	};
 
 	println!("Storer listening on {}", addr);
 
-	let mut basic_server = Server::builder();
-
 	let svc = crate::message_storage::message_storage_server::MessageStorageServer::new(greeter);
 	let loc = crate::message_storage::location_server::LocationServer::new(locer);
+	let reload_handler = rt.spawn(async move {
+		let grpc_cert = match grpc_cert {
+			Some(d) => d,
+			None => return,
+		};
+		let mut hangup = signal(SignalKind::hangup()).expect("Listening for SIGHUP not to fail");
+		// Only new handshakes see the reloaded certificate, open connections are kept.
+		while hangup.recv().await.is_some() {
+			match grpc_cert.reload() {
+				Ok(()) => println!("Reloaded certificate of gRPC endpoint"),
+				Err(e) => println!("Keeping previous certificate of gRPC endpoint, reload failed => {:?}", e),
+			}
+		}
+	});
 	let server_handler = rt.spawn(async move {
-		basic_server
+		let basic_server = Server::builder()
 			.add_service(svc)
-			.add_service(loc)
-			.serve(addr)
-			.await
-			.unwrap();
+			.add_service(loc);
+		let served = match incoming {
+			Some(d) => basic_server.serve_with_incoming(d).await,
+			None => basic_server.serve(addr).await,
+		};
+		if let Err(e) = served {
+			println!("gRPC endpoint stopped => {:?}", e);
+		}
 	});
 	rt.block_on(async move {
 		tokio::signal::ctrl_c().await.unwrap();
 		server_handler.abort();
+		reload_handler.abort();
+		arbiter.stop();
+		gc_handler.abort();
+		replication_handler.abort();
+		fetch_handler.abort();
 		signal_handler.abort();
 	});
 	Ok(())
-}
\ No newline at end of file
+}
+
+/// TLS config of the web service, if a key pair is given.
+fn web_tls_config(opt: &Opt) -> Option<ServerConfig> {
+	match opt.tls() {
+		TlsCommand::NoTls => {
+			None
+		}
+		TlsCommand::Tls { public_key, private_key, .. } => {
+			let mut config = ServerConfig::new(NoClientAuth::new());
+			let cert_file = &mut BufReader::new(File::open(public_key).unwrap());
+			let key_file = &mut BufReader::new(File::open(private_key).unwrap());
+			let cert_chain = certs(cert_file).unwrap();
+			let mut keys = pkcs8_private_keys(key_file).unwrap();
+			config.set_single_cert(cert_chain, keys.remove(0)).unwrap();
+			Some(config)
+		}
+	}
+}
+
+/// Reads the ed25519 keypair of the node from `path`, or generates it there if the file doesn't exist.
+fn load_identity(path: &Path) -> anyhow::Result<Keypair> {
+	if path.exists() {
+		let mut bytes = std::fs::read(path)?;
+		let keypair = ed25519::Keypair::decode(&mut bytes)
+			.map_err(|e| anyhow::Error::msg(format!("Invalid identity key in {:?}: {:?}", path, e)))?;
+		return Ok(Keypair::Ed25519(keypair))
+	}
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+	let keypair = ed25519::Keypair::generate();
+	write_secret(path, &keypair.encode())?;
+	println!("Generated new identity key in {:?}", path);
+	Ok(Keypair::Ed25519(keypair))
+}
+
+/// Reads the key for issuing tokens from `path`, or generates it there if the file doesn't exist.
+fn load_token_key(path: &Path) -> anyhow::Result<IssuerKey> {
+	if path.exists() {
+		return IssuerKey::from_bytes(&std::fs::read(path)?)
+			.map_err(|e| anyhow::Error::msg(format!("Invalid token key in {:?}: {:?}", path, e)))
+	}
+	let key = IssuerKey::generate();
+	write_secret(path, &key.to_bytes())?;
+	println!("Generated new token key in {:?}", path);
+	Ok(key)
+}
+
+/// Writes a newly generated key to `path`, readable only by the owner.
+fn write_secret(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+	let mut file = std::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(path)?;
+	file.write_all(bytes)?;
+	Ok(())
+}
+
+#[test]
+fn switch_off_flags() {
+	let opt = Opt::from_iter(["dione-server", "--db-path", "node1", "--ex", "0.0.0.0:8010", "--proxy", "--mdns", "--require-tokens", "--no-proxy", "--no-require-tokens"]);
+	let enabled_again = Opt::from_iter(["dione-server", "--db-path", "node1", "--ex", "0.0.0.0:8010", "--no-proxy", "--proxy"]);
+
+	assert!(!opt.proxy);
+	assert!(opt.mdns);
+	assert!(!opt.require_tokens);
+	assert!(enabled_again.proxy);
+}
+
+#[test]
+fn subcommands_without_node_flags() {
+	let opt = Opt::from_iter(["dione-server", "--identity-key", "node1.key", "print-peer-id"]);
+	let ephemeral = Opt::from_iter(["dione-server", "--storage", "memory", "print-peer-id"]);
+
+	assert!(matches!(opt.command, Some(Command::PrintPeerId)));
+	assert_eq!(opt.identity_path().unwrap(), Some(PathBuf::from("node1.key")));
+	assert_eq!(ephemeral.identity_path().unwrap(), None);
+	assert!(ephemeral.ex().is_err());
+	assert!(matches!(Opt::from_iter(["dione-server", "default-config"]).command, Some(Command::DefaultConfig)));
+}
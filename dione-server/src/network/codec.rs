@@ -0,0 +1,45 @@
+use std::io;
+
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Largest message read from a peer, bounds the memory a single request can take.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Reads a length-prefixed, bincode encoded message.
+pub(super) async fn read_message<T, M>(io: &mut T) -> io::Result<M>
+where
+	T: AsyncRead + Unpin + Send,
+	M: DeserializeOwned,
+{
+	let data = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+	bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `message` length-prefixed and bincode encoded, then closes the stream.
+pub(super) async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+	T: AsyncWrite + Unpin + Send,
+	M: Serialize,
+{
+	let data = bincode::serialize(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	write_length_prefixed(io, data).await?;
+	io.close().await
+}
+
+#[cfg(test)]
+use super::fetch::FetchResponse;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn message_roundtrip() {
+	let response = FetchResponse::Found(b"This is just testcontent".to_vec());
+
+	let mut written = libp2p::futures::io::Cursor::new(Vec::new());
+	write_message(&mut written, &response).await.unwrap();
+	let mut reader = libp2p::futures::io::Cursor::new(written.into_inner());
+	let read: FetchResponse = read_message(&mut reader).await.unwrap();
+
+	assert_eq!(read, response);
+}
@@ -0,0 +1,77 @@
+use std::io;
+
+use async_trait::async_trait;
+use libp2p::PeerId;
+use libp2p::core::upgrade::ProtocolName;
+use libp2p::futures::{AsyncRead, AsyncWrite};
+use libp2p::request_response::{RequestResponseCodec, ResponseChannel};
+use serde::{Serialize, Deserialize};
+
+use super::codec::{read_message, write_message};
+
+/// Asks a provider for the share at `address`, so it can be served to a client of another node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchRequest {
+	pub address: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FetchResponse {
+	Found(Vec<u8>),
+	NotFound,
+}
+
+/// A fetch request received from `peer`, answered through `channel`.
+#[derive(Debug)]
+pub struct InboundFetch {
+	pub peer: PeerId,
+	pub request: FetchRequest,
+	pub channel: ResponseChannel<FetchResponse>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchProtocol;
+
+impl ProtocolName for FetchProtocol {
+	fn protocol_name(&self) -> &[u8] {
+		b"/dione/fetch/1.0.0"
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchCodec;
+
+#[async_trait]
+impl RequestResponseCodec for FetchCodec {
+	type Protocol = FetchProtocol;
+	type Request = FetchRequest;
+	type Response = FetchResponse;
+
+	async fn read_request<T>(&mut self, _: &FetchProtocol, io: &mut T) -> io::Result<Self::Request>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read_message(io).await
+	}
+
+	async fn read_response<T>(&mut self, _: &FetchProtocol, io: &mut T) -> io::Result<Self::Response>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read_message(io).await
+	}
+
+	async fn write_request<T>(&mut self, _: &FetchProtocol, io: &mut T, request: FetchRequest) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write_message(io, &request).await
+	}
+
+	async fn write_response<T>(&mut self, _: &FetchProtocol, io: &mut T, response: FetchResponse) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write_message(io, &response).await
+	}
+}
@@ -1,29 +1,92 @@
+mod codec;
+mod fetch;
+mod replication;
+mod store;
+
 use tracing::*;
 
 use libp2p::{NetworkBehaviour, Multiaddr, PeerId, Swarm};
-use libp2p::kad::{Kademlia, KademliaEvent, QueryId, QueryResult, GetProvidersOk, GetRecordOk, Quorum, Record, GetClosestPeersOk, PutRecordOk, AddProviderOk};
-use libp2p::kad::store::MemoryStore;
+use libp2p::kad::{Kademlia, KademliaEvent, QueryId, QueryResult, GetProvidersOk, GetRecordOk, Quorum, Record, GetClosestPeersOk, GetClosestPeersError, PutRecordOk, AddProviderOk, AddProviderError};
+use libp2p::request_response::{RequestResponse, RequestResponseConfig, RequestResponseEvent, RequestResponseMessage, ProtocolSupport, RequestId, ResponseChannel};
 use std::error::Error;
 use tokio::sync::{oneshot, mpsc};
 use std::collections::{HashSet, HashMap};
-use libp2p::swarm::{SwarmEvent, SwarmBuilder};
+use std::iter;
+use std::path::Path;
+use libp2p::swarm::{SwarmEvent, SwarmBuilder, IntoProtocolsHandler, ProtocolsHandler};
+use libp2p::swarm::toggle::Toggle;
+use libp2p::mdns::{Mdns, MdnsConfig, MdnsEvent};
+use libp2p::identify::{Identify, IdentifyConfig, IdentifyEvent};
+use libp2p::ping::{Ping, PingConfig, PingEvent, PingSuccess};
+use libp2p::swarm::AddressScore;
+use std::time::Duration;
+use std::num::NonZeroU32;
 use libp2p::multiaddr::Protocol;
 use libp2p::kad::record::Key;
 use tokio_stream::StreamExt;
 use serde::{Serialize, Deserialize};
 
+use replication::{ReplicationCodec, ReplicationProtocol};
+pub use replication::{ReplicaRequest, ReplicaResponse, InboundReplica};
+use fetch::{FetchCodec, FetchProtocol};
+pub use fetch::{FetchRequest, FetchResponse, InboundFetch};
+pub use store::SledStore;
+
 type ShareAddress = Vec<u8>;
+/// Error of the connection handlers of [ComposedBehaviour], e.g. a failed ping.
+type HandlerError = <<<ComposedBehaviour as libp2p::swarm::NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::Error;
 
+/// Protocol version announced to other peers via identify.
+const IDENTIFY_PROTOCOL: &str = "/dione/1.0.0";
+/// Consecutive failed pings after which a peer is considered dead and evicted from the routing table.
+const MAX_PING_FAILURES: u32 = 3;
+/// Number of peers that have to observe the same address of this node before it is advertised as external address.
+const OBSERVED_ADDRESS_CONFIRMATIONS: usize = 3;
+
+/// Network handles of a node with a fresh identity.
+#[cfg(test)]
 pub async fn new() -> Result<(Client, EventLoop), Box<dyn Error>> {
-	let id_keys = libp2p::identity::Keypair::generate_ed25519();
+	with_identity(libp2p::identity::Keypair::generate_ed25519(), None, false).await
+}
+
+/// Network handles of a node with the identity `id_keys`, which determines its PeerId.
+///
+/// Kademlia records and providers are kept in `kad_store`, without a path they are lost on shutdown.
+/// With `mdns` peers in the local network are discovered and added to the routing table.
+pub async fn with_identity(id_keys: libp2p::identity::Keypair, kad_store: Option<&Path>, mdns: bool) -> Result<(Client, EventLoop), Box<dyn Error>> {
 	let peer_id = id_keys.public().to_peer_id();
+	let identify_config = IdentifyConfig::new(IDENTIFY_PROTOCOL.to_owned(), id_keys.public())
+		.with_agent_version(format!("dione-server/{}", env!("CARGO_PKG_VERSION")));
+
+	let store = match kad_store {
+		Some(path) => SledStore::open(path, peer_id)?,
+		None => SledStore::temporary(peer_id)?,
+	};
+	let mdns = match mdns {
+		true => Some(Mdns::new(MdnsConfig::default()).await?),
+		false => None,
+	};
 
 	let transport = libp2p::development_transport(id_keys).await.unwrap();
 
 	let swarm = SwarmBuilder::new(
 		transport,
 		ComposedBehaviour {
-			kademlia: Kademlia::new(peer_id, MemoryStore::new(peer_id)),
+			kademlia: Kademlia::new(peer_id, store),
+			replication: RequestResponse::new(
+				ReplicationCodec,
+				iter::once((ReplicationProtocol, ProtocolSupport::Full)),
+				RequestResponseConfig::default(),
+			),
+			fetch: RequestResponse::new(
+				FetchCodec,
+				iter::once((FetchProtocol, ProtocolSupport::Full)),
+				RequestResponseConfig::default(),
+			),
+			mdns: Toggle::from(mdns),
+			identify: Identify::new(identify_config),
+			// The ping handler only reports failures before the last one, which closes the connection.
+			ping: Ping::new(PingConfig::new().with_max_failures(NonZeroU32::new(MAX_PING_FAILURES + 1).expect("Not zero"))),
 		},
 		peer_id
 	).build();
@@ -33,6 +96,7 @@ pub async fn new() -> Result<(Client, EventLoop), Box<dyn Error>> {
 	Ok((
 		Client {
 			sender: command_sender,
+			peer_id,
 		},
 		EventLoop::new(swarm, command_receiver)
 		))
@@ -41,9 +105,14 @@ pub async fn new() -> Result<(Client, EventLoop), Box<dyn Error>> {
 #[derive(Clone, Debug)]
 pub struct Client {
 	sender: mpsc::Sender<Command>,
+	peer_id: PeerId,
 }
 
 impl Client {
+	pub fn local_peer_id(&self) -> PeerId {
+		self.peer_id
+	}
+
 	#[instrument]
 	pub async fn start_listening(
 		&mut self,
@@ -75,8 +144,21 @@ impl Client {
 		receiver.await.expect("Sender not to be dropped")
 	}
 
+	/// Starts a Kademlia bootstrap, which fills the routing table with the peers closest to the local node.
+	#[instrument]
+	pub async fn bootstrap(&self) -> anyhow::Result<()> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Command::Bootstrap { sender })
+			.await
+			.expect("Command receiver not to be dropped.");
+		receiver.await.expect("Sender not to be dropped")
+	}
+
+	/// Announces this node as provider of the share at `share_addr`. Fails if the announcement didn't reach the DHT,
+	/// the node stays provider in its own records anyway.
 	#[instrument]
-	pub async fn start_providing(&self, share_addr: ShareAddress) {
+	pub async fn start_providing(&self, share_addr: ShareAddress) -> anyhow::Result<()> {
 		let (sender, receiver) = oneshot::channel();
 		self.sender
 			.send(Command::StartProviding { share_addr, sender })
@@ -134,6 +216,76 @@ impl Client {
 		receiver.await.expect("Sender not to be dropped")
 	}
 
+	/// Peers closest to `addr` in the Kademlia keyspace, nearest first, without the local node.
+	#[instrument]
+	pub async fn get_closest_peers(&self, addr: Vec<u8>) -> Vec<PeerId> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Command::GetClosestPeers { addr, sender })
+			.await
+			.expect("Command receiver not to be dropped.");
+		receiver.await.expect("Sender not to be dropped")
+	}
+
+	#[instrument(skip(self, request))]
+	pub async fn replicate(&self, peer: PeerId, request: ReplicaRequest) -> anyhow::Result<ReplicaResponse> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Command::Replicate { peer, request, sender })
+			.await
+			.expect("Command receiver not to be dropped.");
+		receiver.await.expect("Sender not to be dropped")
+	}
+
+	#[instrument(skip(self, channel))]
+	pub async fn respond_replica(&self, channel: ResponseChannel<ReplicaResponse>, response: ReplicaResponse) {
+		self.sender
+			.send(Command::RespondReplica { channel, response })
+			.await
+			.expect("Command receiver not to be dropped.");
+	}
+
+	/// Fetches the share at `address` from `peer`.
+	#[instrument]
+	pub async fn fetch(&self, peer: PeerId, address: Vec<u8>) -> anyhow::Result<FetchResponse> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Command::Fetch { peer, address, sender })
+			.await
+			.expect("Command receiver not to be dropped.");
+		receiver.await.expect("Sender not to be dropped")
+	}
+
+	#[instrument(skip(self, channel, response))]
+	pub async fn respond_fetch(&self, channel: ResponseChannel<FetchResponse>, response: FetchResponse) {
+		self.sender
+			.send(Command::RespondFetch { channel, response })
+			.await
+			.expect("Command receiver not to be dropped.");
+	}
+
+	/// Peers the node is connected to.
+	#[instrument]
+	pub async fn peers(&self) -> Vec<(PeerId, PeerInfo)> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Command::Peers { sender })
+			.await
+			.expect("Command receiver not to be dropped.");
+		receiver.await.expect("Sender not to be dropped")
+	}
+
+	/// Peers in the Kademlia routing table.
+	#[instrument]
+	pub async fn routing_table(&self) -> Vec<PeerId> {
+		let (sender, receiver) = oneshot::channel();
+		self.sender
+			.send(Command::RoutingTable { sender })
+			.await
+			.expect("Command receiver not to be dropped.");
+		receiver.await.expect("Sender not to be dropped")
+	}
+
 	#[instrument]
 	pub async fn get_listen_address(&self) -> anyhow::Result<Vec<Multiaddr>> {
 		let (sender, receiver) = oneshot::channel();
@@ -148,12 +300,22 @@ impl Client {
 #[derive(NetworkBehaviour)]
 #[behaviour(event_process = false, out_event = "ComposedEvent")]
 struct ComposedBehaviour {
-	kademlia: Kademlia<MemoryStore>,
+	kademlia: Kademlia<SledStore>,
+	replication: RequestResponse<ReplicationCodec>,
+	fetch: RequestResponse<FetchCodec>,
+	mdns: Toggle<Mdns>,
+	identify: Identify,
+	ping: Ping,
 }
 
 #[derive(Debug)]
 enum ComposedEvent {
 	Kademlia(KademliaEvent),
+	Replication(RequestResponseEvent<ReplicaRequest, ReplicaResponse>),
+	Fetch(RequestResponseEvent<FetchRequest, FetchResponse>),
+	Mdns(MdnsEvent),
+	Identify(IdentifyEvent),
+	Ping(PingEvent),
 }
 
 impl From<KademliaEvent> for ComposedEvent {
@@ -162,6 +324,36 @@ impl From<KademliaEvent> for ComposedEvent {
 	}
 }
 
+impl From<RequestResponseEvent<ReplicaRequest, ReplicaResponse>> for ComposedEvent {
+	fn from(event: RequestResponseEvent<ReplicaRequest, ReplicaResponse>) -> Self {
+		ComposedEvent::Replication(event)
+	}
+}
+
+impl From<RequestResponseEvent<FetchRequest, FetchResponse>> for ComposedEvent {
+	fn from(event: RequestResponseEvent<FetchRequest, FetchResponse>) -> Self {
+		ComposedEvent::Fetch(event)
+	}
+}
+
+impl From<MdnsEvent> for ComposedEvent {
+	fn from(event: MdnsEvent) -> Self {
+		ComposedEvent::Mdns(event)
+	}
+}
+
+impl From<IdentifyEvent> for ComposedEvent {
+	fn from(event: IdentifyEvent) -> Self {
+		ComposedEvent::Identify(event)
+	}
+}
+
+impl From<PingEvent> for ComposedEvent {
+	fn from(event: PingEvent) -> Self {
+		ComposedEvent::Ping(event)
+	}
+}
+
 #[derive(Debug)]
 enum Command {
 	StartListening {
@@ -173,9 +365,18 @@ enum Command {
 		peer_addr: Multiaddr,
 		sender: oneshot::Sender<anyhow::Result<()>>,
 	},
+	Bootstrap {
+		sender: oneshot::Sender<anyhow::Result<()>>,
+	},
+	Peers {
+		sender: oneshot::Sender<Vec<(PeerId, PeerInfo)>>,
+	},
+	RoutingTable {
+		sender: oneshot::Sender<Vec<PeerId>>,
+	},
 	StartProviding {
 		share_addr: ShareAddress,
-		sender: oneshot::Sender<()>,
+		sender: oneshot::Sender<anyhow::Result<()>>,
 	},
 	StopProviding {
 		share_addr: ShareAddress,
@@ -197,9 +398,31 @@ enum Command {
 		addr: ShareAddress,
 		sender: oneshot::Sender<anyhow::Result<PeerId>>,
 	},
+	GetClosestPeers {
+		addr: ShareAddress,
+		sender: oneshot::Sender<Vec<PeerId>>,
+	},
 	GetListenAddress {
 		sender: oneshot::Sender<anyhow::Result<Vec<Multiaddr>>>,
-	}
+	},
+	Replicate {
+		peer: PeerId,
+		request: ReplicaRequest,
+		sender: oneshot::Sender<anyhow::Result<ReplicaResponse>>,
+	},
+	RespondReplica {
+		channel: ResponseChannel<ReplicaResponse>,
+		response: ReplicaResponse,
+	},
+	Fetch {
+		peer: PeerId,
+		address: ShareAddress,
+		sender: oneshot::Sender<anyhow::Result<FetchResponse>>,
+	},
+	RespondFetch {
+		channel: ResponseChannel<FetchResponse>,
+		response: FetchResponse,
+	},
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -209,17 +432,33 @@ pub struct ServerAddrBundle {
 	pub addr_type: crate::message_storage::ServerAddressType,
 }
 
+/// Connected peer, as learned from identify and ping.
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+	/// Round-trip time of the last successful ping.
+	pub rtt: Option<Duration>,
+	pub agent_version: Option<String>,
+	failed_pings: u32,
+}
 
 pub struct EventLoop {
 	swarm: Swarm<ComposedBehaviour>,
 	command_receiver: mpsc::Receiver<Command>,
 	pending_dial: HashMap<PeerId, oneshot::Sender<anyhow::Result<()>>>,
-	pending_start_providing: HashMap<QueryId, oneshot::Sender<()>>,
+	pending_start_providing: HashMap<QueryId, oneshot::Sender<anyhow::Result<()>>>,
 	pending_get_providers: HashMap<QueryId, oneshot::Sender<HashSet<PeerId>>>,
 	pending_put_clear_addr: HashMap<QueryId, oneshot::Sender<()>>,
 	pending_get_clear_addr: HashMap<QueryId, oneshot::Sender<anyhow::Result<ServerAddrBundle>>>,
 	pending_get_closest_peer: HashMap<QueryId, oneshot::Sender<anyhow::Result<PeerId>>>,
+	pending_get_closest_peers: HashMap<QueryId, oneshot::Sender<Vec<PeerId>>>,
+	pending_replicate: HashMap<RequestId, oneshot::Sender<anyhow::Result<ReplicaResponse>>>,
+	inbound_replicas: Option<mpsc::Sender<InboundReplica>>,
+	pending_fetch: HashMap<RequestId, oneshot::Sender<anyhow::Result<FetchResponse>>>,
+	inbound_fetches: Option<mpsc::Sender<InboundFetch>>,
 	providing: HashSet<Key>,
+	peers: HashMap<PeerId, PeerInfo>,
+	/// Address of this node as last observed by each connected peer.
+	observed_addrs: HashMap<PeerId, Multiaddr>,
 }
 
 impl EventLoop {
@@ -236,10 +475,62 @@ impl EventLoop {
 			pending_get_clear_addr: Default::default(),
 			pending_put_clear_addr: Default::default(),
 			pending_get_closest_peer: Default::default(),
-			providing: Default::default()
+			pending_get_closest_peers: Default::default(),
+			pending_replicate: Default::default(),
+			inbound_replicas: None,
+			pending_fetch: Default::default(),
+			inbound_fetches: None,
+			providing: Default::default(),
+			peers: Default::default(),
+			observed_addrs: Default::default(),
+		}
+	}
+
+	/// Records the address `peer` observed for this node. It is only advertised once enough peers agree on it, so a
+	/// single peer can't make the node announce an arbitrary address.
+	fn observe_address(&mut self, peer: PeerId, address: Multiaddr) {
+		self.observed_addrs.insert(peer, address.clone());
+		let confirmations = self.observed_addrs.values().filter(|e| **e == address).count();
+		if confirmations == OBSERVED_ADDRESS_CONFIRMATIONS {
+			self.swarm.add_external_address(address, AddressScore::Finite(1));
+		}
+	}
+
+	/// Counts a failed ping of `peer` and evicts it once [MAX_PING_FAILURES] pings in a row failed.
+	fn ping_failed(&mut self, peer: PeerId) {
+		let info = self.peers.entry(peer).or_default();
+		info.failed_pings += 1;
+		if info.failed_pings >= MAX_PING_FAILURES {
+			tracing::info!("Evicting {} after {} failed pings", peer, info.failed_pings);
+			self.peers.remove(&peer);
+			self.observed_addrs.remove(&peer);
+			self.swarm.behaviour_mut().kademlia.remove_peer(&peer);
+			let _ = self.swarm.disconnect_peer_id(peer);
 		}
 	}
 
+	/// Replication requests of other nodes. Without a receiver taken, they are all refused.
+	pub fn inbound_replicas(&mut self) -> mpsc::Receiver<InboundReplica> {
+		let (sender, receiver) = mpsc::channel(32);
+		self.inbound_replicas = Some(sender);
+		receiver
+	}
+
+	/// Fetch requests of other nodes. Without a receiver taken, every share is reported as not found.
+	pub fn inbound_fetches(&mut self) -> mpsc::Receiver<InboundFetch> {
+		let (sender, receiver) = mpsc::channel(32);
+		self.inbound_fetches = Some(sender);
+		receiver
+	}
+
+	fn refuse_fetch(&mut self, channel: ResponseChannel<FetchResponse>) {
+		let _ = self.swarm.behaviour_mut().fetch.send_response(channel, FetchResponse::NotFound);
+	}
+
+	fn refuse_replica(&mut self, channel: ResponseChannel<ReplicaResponse>) {
+		let _ = self.swarm.behaviour_mut().replication.send_response(channel, ReplicaResponse::Refused);
+	}
+
 	pub async fn run(&mut self) {
 		loop {
 			tokio::select! {
@@ -259,7 +550,7 @@ impl EventLoop {
 		&mut self,
 		event: SwarmEvent<
 			ComposedEvent,
-			std::io::Error
+			HandlerError
 		>
 	) {
 		match event {
@@ -270,11 +561,23 @@ impl EventLoop {
 				..
 			}, )) => {
 				self.providing.insert(key);
-				let sender: oneshot::Sender<()> = self
+				let sender = self
 					.pending_start_providing
 					.remove(&id)
 					.expect("Completed query to be previously pending");
-				let _ = sender.send(());
+				let _ = sender.send(Ok(()));
+			}
+			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+			KademliaEvent::OutboundQueryCompleted {
+				id,
+				result: QueryResult::StartProviding(Err(AddProviderError::Timeout { key })),
+				..
+			})) => {
+				// The provider record was stored locally before the query started.
+				self.providing.insert(key);
+				if let Some(sender) = self.pending_start_providing.remove(&id) {
+					let _ = sender.send(Err(anyhow::Error::msg("Announcing provider record timed out")));
+				}
 			}
 			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
 			KademliaEvent::OutboundQueryCompleted {
@@ -293,6 +596,17 @@ impl EventLoop {
 					.send(providers);
 			}
 			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+			KademliaEvent::OutboundQueryCompleted {
+				id,
+				result: QueryResult::GetProviders(Err(_)),
+				..
+			})) => {
+				// A failed lookup is answered like one without providers.
+				if let Some(sender) = self.pending_get_providers.remove(&id) {
+					let _ = sender.send(HashSet::new());
+				}
+			}
+			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
 			KademliaEvent::OutboundQueryCompleted {
 				id,
 				result: QueryResult::GetRecord(Ok(GetRecordOk{ records, .. })), ..
@@ -317,6 +631,21 @@ impl EventLoop {
 					.send(bundle);
 			}
 			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+			KademliaEvent::OutboundQueryCompleted {
+				id,
+				result: QueryResult::GetClosestPeers(Ok(GetClosestPeersOk { mut peers, key })), ..
+			})) if self.pending_get_closest_peers.contains_key(&id) => {
+				let key = libp2p::kad::kbucket::Key::from(key);
+				let local_peer_id = *self.swarm.local_peer_id();
+				peers.retain(|p| *p != local_peer_id);
+				peers.sort_by_key(|p| libp2p::kad::kbucket::Key::from(*p).distance(&key));
+				let _ = self
+					.pending_get_closest_peers
+					.remove(&id)
+					.expect("Completed query to previously pending")
+					.send(peers);
+			},
+			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
 			KademliaEvent::OutboundQueryCompleted {
 				id,
 				result: QueryResult::GetClosestPeers(Ok(GetClosestPeersOk { peers, key })), ..
@@ -349,7 +678,111 @@ impl EventLoop {
 					.expect("Completed query to previously pending")
 					.send(());
 			},
+			SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+			KademliaEvent::OutboundQueryCompleted {
+				id,
+				result: QueryResult::GetClosestPeers(Err(GetClosestPeersError::Timeout { peers, .. })), ..
+			})) => {
+				// Peers found before the timeout are still good candidates for replicas.
+				if let Some(sender) = self.pending_get_closest_peers.remove(&id) {
+					let local_peer_id = *self.swarm.local_peer_id();
+					let _ = sender.send(peers.into_iter().filter(|p| *p != local_peer_id).collect());
+				} else if let Some(sender) = self.pending_get_closest_peer.remove(&id) {
+					let _ = sender.send(Err(anyhow::Error::msg("Closest peer lookup timed out")));
+				}
+			},
 			SwarmEvent::Behaviour(ComposedEvent::Kademlia( .. )) => {}
+			SwarmEvent::Behaviour(ComposedEvent::Replication(
+			RequestResponseEvent::Message { peer, message }
+			)) => match message {
+				RequestResponseMessage::Request { request, channel, .. } => {
+					let inbound = InboundReplica { peer, request, channel };
+					match &self.inbound_replicas {
+						Some(inbound_replicas) => {
+							if let Err(e) = inbound_replicas.try_send(inbound) {
+								let inbound = match e {
+									mpsc::error::TrySendError::Full(d) | mpsc::error::TrySendError::Closed(d) => d,
+								};
+								tracing::warn!("Refusing replica from {}, no capacity to handle it", inbound.peer);
+								self.refuse_replica(inbound.channel);
+							}
+						}
+						None => self.refuse_replica(inbound.channel),
+					}
+				}
+				RequestResponseMessage::Response { request_id, response } => {
+					if let Some(sender) = self.pending_replicate.remove(&request_id) {
+						let _ = sender.send(Ok(response));
+					}
+				}
+			},
+			SwarmEvent::Behaviour(ComposedEvent::Replication(
+			RequestResponseEvent::OutboundFailure { request_id, error, .. }
+			)) => {
+				if let Some(sender) = self.pending_replicate.remove(&request_id) {
+					let _ = sender.send(Err(anyhow::Error::from(error)));
+				}
+			}
+			SwarmEvent::Behaviour(ComposedEvent::Replication( .. )) => {}
+			SwarmEvent::Behaviour(ComposedEvent::Fetch(
+			RequestResponseEvent::Message { peer, message }
+			)) => match message {
+				RequestResponseMessage::Request { request, channel, .. } => {
+					let inbound = InboundFetch { peer, request, channel };
+					match &self.inbound_fetches {
+						Some(inbound_fetches) => {
+							if let Err(e) = inbound_fetches.try_send(inbound) {
+								let inbound = match e {
+									mpsc::error::TrySendError::Full(d) | mpsc::error::TrySendError::Closed(d) => d,
+								};
+								tracing::warn!("Refusing fetch of {}, no capacity to handle it", inbound.peer);
+								self.refuse_fetch(inbound.channel);
+							}
+						}
+						None => self.refuse_fetch(inbound.channel),
+					}
+				}
+				RequestResponseMessage::Response { request_id, response } => {
+					if let Some(sender) = self.pending_fetch.remove(&request_id) {
+						let _ = sender.send(Ok(response));
+					}
+				}
+			},
+			SwarmEvent::Behaviour(ComposedEvent::Fetch(
+			RequestResponseEvent::OutboundFailure { request_id, error, .. }
+			)) => {
+				if let Some(sender) = self.pending_fetch.remove(&request_id) {
+					let _ = sender.send(Err(anyhow::Error::from(error)));
+				}
+			}
+			SwarmEvent::Behaviour(ComposedEvent::Fetch( .. )) => {}
+			SwarmEvent::Behaviour(ComposedEvent::Mdns(MdnsEvent::Discovered(list))) => {
+				for (peer_id, address) in list {
+					tracing::debug!("Discovered peer {} at {} in local network", peer_id, address);
+					self.swarm.behaviour_mut().kademlia.add_address(&peer_id, address);
+				}
+			}
+			SwarmEvent::Behaviour(ComposedEvent::Mdns(MdnsEvent::Expired(..))) => {}
+			SwarmEvent::Behaviour(ComposedEvent::Identify(IdentifyEvent::Received { peer_id, info })) => {
+				for address in info.listen_addrs {
+					self.swarm.behaviour_mut().kademlia.add_address(&peer_id, address);
+				}
+				self.observe_address(peer_id, info.observed_addr);
+				self.peers.entry(peer_id).or_default().agent_version = Some(info.agent_version);
+			}
+			SwarmEvent::Behaviour(ComposedEvent::Identify( .. )) => {}
+			SwarmEvent::Behaviour(ComposedEvent::Ping(PingEvent { peer, result })) => match result {
+				Ok(PingSuccess::Ping { rtt }) => {
+					let info = self.peers.entry(peer).or_default();
+					info.rtt = Some(rtt);
+					info.failed_pings = 0;
+				}
+				Ok(PingSuccess::Pong) => {}
+				Err(e) => {
+					tracing::debug!("Ping of {} failed: {:?}", peer, e);
+					self.ping_failed(peer);
+				}
+			},
 			SwarmEvent::NewListenAddr { address, .. } => {
 				let local_peer_id = *self.swarm.local_peer_id();
 				println!("Local node is listening on {:?}",
@@ -378,6 +811,7 @@ impl EventLoop {
 			} => {
 				println!("Adding peer {} with address {}", peer_id, endpoint.get_remote_address().clone());
 				self.swarm.behaviour_mut().kademlia.add_address(&peer_id, endpoint.get_remote_address().clone());
+				self.peers.entry(peer_id).or_default();
 				if endpoint.is_dialer() {
 					if let Some(sender) = self.pending_dial.remove(&peer_id) {
 						let _ = sender.send(Ok(()));
@@ -385,23 +819,21 @@ impl EventLoop {
 				}
 			}
 			SwarmEvent::OutgoingConnectionError {
+				peer_id,
 				error,
-				..
 			} => {
 				tracing::error!("Had outgoing connection error {:?}", &error);
-				/*
-				match peer_id {
-					Some(d) => {
-					if let Some(sender) = self.pending_dial.remove(&d) {
-						let _ = sender.send(Err(anyhow::Error::from(error)));
-					}},
-					None => {}
+				if let Some(sender) = peer_id.and_then(|d| self.pending_dial.remove(&d)) {
+					let _ = sender.send(Err(anyhow::Error::from(error)));
 				}
-				 */
-
 			}
 
-			SwarmEvent::ConnectionClosed { .. } => {},
+			SwarmEvent::ConnectionClosed { peer_id, num_established, .. } => {
+				if num_established == 0 {
+					self.peers.remove(&peer_id);
+					self.observed_addrs.remove(&peer_id);
+				}
+			},
 			SwarmEvent::Dialing( .. ) => {},
 			e => panic!("{:?}", e),
 		}
@@ -435,17 +867,33 @@ impl EventLoop {
 							let _ = sender.send(Err(anyhow::Error::from(e)));
 						}
 					}
+				} else {
+					let _ = sender.send(Err(anyhow::Error::msg(format!("Already dialing {}", peer_id))));
 				}
 			}
+			Command::Peers { sender } => {
+				let _ = sender.send(self.peers.iter().map(|(k, v)| (*k, v.clone())).collect());
+			}
+			Command::RoutingTable { sender } => {
+				let peers = self.swarm.behaviour_mut().kademlia.kbuckets()
+					.flat_map(|b| b.iter().map(|e| *e.node.key.preimage()).collect::<Vec<_>>())
+					.collect();
+				let _ = sender.send(peers);
+			}
+			Command::Bootstrap { sender } => {
+				let result = self.swarm.behaviour_mut().kademlia.bootstrap();
+				let _ = sender.send(result.map(|_| ()).map_err(anyhow::Error::from));
+			}
 			Command::StartProviding { share_addr, sender } => {
 				let key: Key = share_addr.to_vec().into();
-				let query_id = self
-					.swarm
-					.behaviour_mut()
-					.kademlia
-					.start_providing(key)
-					.expect("No store error.");
-				self.pending_start_providing.insert(query_id, sender	);
+				match self.swarm.behaviour_mut().kademlia.start_providing(key) {
+					Ok(query_id) => {
+						self.pending_start_providing.insert(query_id, sender);
+					}
+					Err(e) => {
+						let _ = sender.send(Err(anyhow::Error::msg(format!("Storing provider record failed: {:?}", e))));
+					}
+				}
 			}
 			Command::StopProviding { share_addr } => {
 				let key: Key = share_addr.to_vec().into();
@@ -497,6 +945,36 @@ impl EventLoop {
 					.get_closest_peers(addr);
 				self.pending_get_closest_peer.insert(query_id, sender);
 			}
+			Command::GetClosestPeers { addr, sender } => {
+				let query_id = self
+					.swarm
+					.behaviour_mut()
+					.kademlia
+					.get_closest_peers(addr);
+				self.pending_get_closest_peers.insert(query_id, sender);
+			}
+			Command::Replicate { peer, request, sender } => {
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.replication
+					.send_request(&peer, request);
+				self.pending_replicate.insert(request_id, sender);
+			}
+			Command::RespondReplica { channel, response } => {
+				let _ = self.swarm.behaviour_mut().replication.send_response(channel, response);
+			}
+			Command::Fetch { peer, address, sender } => {
+				let request_id = self
+					.swarm
+					.behaviour_mut()
+					.fetch
+					.send_request(&peer, FetchRequest { address });
+				self.pending_fetch.insert(request_id, sender);
+			}
+			Command::RespondFetch { channel, response } => {
+				let _ = self.swarm.behaviour_mut().fetch.send_response(channel, response);
+			}
 			Command::GetListenAddress { sender } => {
 				let local_peer_id = self.swarm.local_peer_id().to_owned().into();
 				let listen_address: Vec<Multiaddr> = self.swarm.listeners().map(|e| e.to_owned().with(Protocol::P2p(local_peer_id))).collect();
@@ -505,3 +983,38 @@ impl EventLoop {
 		}
 	}
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn evict_after_failed_pings() {
+	let (_, mut event_loop) = new().await.unwrap();
+	let peer = PeerId::random();
+	event_loop.swarm.behaviour_mut().kademlia.add_address(&peer, "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+
+	for _ in 1..MAX_PING_FAILURES {
+		event_loop.ping_failed(peer);
+	}
+	let kept = event_loop.swarm.behaviour_mut().kademlia.kbuckets().count();
+	event_loop.ping_failed(peer);
+	let evicted = event_loop.swarm.behaviour_mut().kademlia.kbuckets().count();
+
+	assert_eq!(kept, 1);
+	assert_eq!(evicted, 0);
+	assert!(!event_loop.peers.contains_key(&peer));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn advertise_only_confirmed_addresses() {
+	let (_, mut event_loop) = new().await.unwrap();
+	let observed: Multiaddr = "/ip4/203.0.113.7/tcp/4001".parse().unwrap();
+
+	event_loop.observe_address(PeerId::random(), "/ip4/198.51.100.1/tcp/4001".parse().unwrap());
+	for _ in 1..OBSERVED_ADDRESS_CONFIRMATIONS {
+		event_loop.observe_address(PeerId::random(), observed.clone());
+	}
+	let unconfirmed = event_loop.swarm.external_addresses().count();
+	event_loop.observe_address(PeerId::random(), observed.clone());
+	let confirmed: Vec<_> = event_loop.swarm.external_addresses().map(|e| e.addr.clone()).collect();
+
+	assert_eq!(unconfirmed, 0);
+	assert_eq!(confirmed, vec![observed]);
+}
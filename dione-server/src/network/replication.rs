@@ -0,0 +1,87 @@
+use std::io;
+
+use async_trait::async_trait;
+use libp2p::PeerId;
+use libp2p::core::upgrade::ProtocolName;
+use libp2p::futures::{AsyncRead, AsyncWrite};
+use libp2p::request_response::{RequestResponseCodec, ResponseChannel};
+use serde::{Serialize, Deserialize};
+
+use crate::db::MessageEntry;
+use super::codec::{read_message, write_message};
+
+/// Requests a node sends to the peers holding replicas of its shares.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicaRequest {
+	/// Store a copy of the share at `address`.
+	Store {
+		address: Vec<u8>,
+		entry: MessageEntry,
+	},
+	/// Delete the copy of the share at `address`, authorized by the secret of its delete commitment.
+	Delete {
+		address: Vec<u8>,
+		secret: Vec<u8>,
+	},
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicaResponse {
+	Done,
+	Refused,
+}
+
+/// A replication request received from `peer`, answered through `channel`.
+#[derive(Debug)]
+pub struct InboundReplica {
+	pub peer: PeerId,
+	pub request: ReplicaRequest,
+	pub channel: ResponseChannel<ReplicaResponse>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplicationProtocol;
+
+impl ProtocolName for ReplicationProtocol {
+	fn protocol_name(&self) -> &[u8] {
+		b"/dione/replicate/1.0.0"
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplicationCodec;
+
+#[async_trait]
+impl RequestResponseCodec for ReplicationCodec {
+	type Protocol = ReplicationProtocol;
+	type Request = ReplicaRequest;
+	type Response = ReplicaResponse;
+
+	async fn read_request<T>(&mut self, _: &ReplicationProtocol, io: &mut T) -> io::Result<Self::Request>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read_message(io).await
+	}
+
+	async fn read_response<T>(&mut self, _: &ReplicationProtocol, io: &mut T) -> io::Result<Self::Response>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read_message(io).await
+	}
+
+	async fn write_request<T>(&mut self, _: &ReplicationProtocol, io: &mut T, request: ReplicaRequest) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write_message(io, &request).await
+	}
+
+	async fn write_response<T>(&mut self, _: &ReplicationProtocol, io: &mut T, response: ReplicaResponse) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write_message(io, &response).await
+	}
+}
@@ -0,0 +1,331 @@
+use tracing::*;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use libp2p::{Multiaddr, PeerId};
+use libp2p::kad::kbucket;
+use libp2p::kad::record::{Key, ProviderRecord, Record};
+use libp2p::kad::store::{Error, MemoryStoreConfig, RecordStore, Result};
+use serde::{Serialize, Deserialize};
+
+use crate::db::unix_now;
+
+/// Record as kept on disk. Instants don't survive a restart, so expiry is kept as unix timestamp.
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+	value: Vec<u8>,
+	publisher: Option<Vec<u8>>,
+	expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+	provider: Vec<u8>,
+	expires: Option<u64>,
+	addresses: Vec<Vec<u8>>,
+}
+
+/// Kademlia record store on sled, so records and providers outlive a restart of the node.
+///
+/// Follows the limits and eviction rules of the [MemoryStore](libp2p::kad::store::MemoryStore), except for the
+/// provider records of this node. They would give away the addresses of the stored shares, even if the shares are
+/// encrypted at rest, so they are only kept in memory and announced again on startup. Their number is bounded by
+/// the capacity of the node instead of `max_provided_keys`.
+pub struct SledStore {
+	local_key: kbucket::Key<PeerId>,
+	config: MemoryStoreConfig,
+	records: sled::Tree,
+	providers: sled::Tree,
+	provided: HashMap<Key, ProviderRecord>,
+	/// Entries of `records` and `providers`, counted on the side as `len` walks the whole tree.
+	record_count: usize,
+	provider_count: usize,
+}
+
+impl SledStore {
+	/// Store in the sled database at `path`.
+	pub fn open<P: AsRef<Path>>(path: P, local_id: PeerId) -> anyhow::Result<Self> {
+		Self::with_db(sled::open(path)?, local_id)
+	}
+
+	/// Store that is lost on shutdown.
+	pub fn temporary(local_id: PeerId) -> anyhow::Result<Self> {
+		Self::with_db(sled::Config::new().temporary(true).open()?, local_id)
+	}
+
+	fn with_db(db: sled::Db, local_id: PeerId) -> anyhow::Result<Self> {
+		let records = db.open_tree("records")?;
+		let mut store = Self {
+			local_key: kbucket::Key::from(local_id),
+			config: MemoryStoreConfig::default(),
+			record_count: records.len(),
+			records,
+			providers: db.open_tree("providers")?,
+			provided: HashMap::new(),
+			provider_count: 0,
+		};
+		store.drop_stored_local_providers()?;
+		Ok(store)
+	}
+
+	/// Removes provider records of this node written by earlier versions and counts the remaining keys.
+	fn drop_stored_local_providers(&mut self) -> anyhow::Result<()> {
+		let local_id = *self.local_key.preimage();
+		for key in self.providers.iter().keys() {
+			let key = Key::from(key?.to_vec());
+			self.provider_count += 1;
+			let mut providers = self.load_providers(&key);
+			if providers.iter().any(|e| e.provider == local_id) {
+				providers.retain(|e| e.provider != local_id);
+				self.save_providers(&key, &providers);
+			}
+		}
+		Ok(())
+	}
+
+	/// Providers of `key`, sorted by their distance to the key.
+	fn load_providers(&self, key: &Key) -> Vec<ProviderRecord> {
+		let stored: Vec<StoredProvider> = match self.providers.get(key.as_ref()) {
+			Ok(Some(d)) => match bincode::deserialize(&d) {
+				Ok(d) => d,
+				Err(e) => {
+					event!(Level::ERROR, "Dropping undecodable provider records: {:?}", e);
+					return Vec::new()
+				}
+			},
+			Ok(None) => return Vec::new(),
+			Err(e) => {
+				event!(Level::ERROR, "Error loading provider records: {:?}", e);
+				return Vec::new()
+			}
+		};
+		stored.into_iter()
+			.filter_map(|e| Some(ProviderRecord {
+				key: key.clone(),
+				provider: PeerId::from_bytes(&e.provider).ok()?,
+				expires: e.expires.map(to_instant),
+				addresses: e.addresses.into_iter().filter_map(|e| Multiaddr::try_from(e).ok()).collect(),
+			}))
+			.collect()
+	}
+
+	fn save_providers(&mut self, key: &Key, providers: &[ProviderRecord]) {
+		let result = if providers.is_empty() {
+			self.providers.remove(key.as_ref()).map(|e| {
+				if e.is_some() {
+					self.provider_count -= 1;
+				}
+			})
+		} else {
+			let stored: Vec<StoredProvider> = providers.iter()
+				.map(|e| StoredProvider {
+					provider: e.provider.to_bytes(),
+					expires: e.expires.map(to_timestamp),
+					addresses: e.addresses.iter().map(|e| e.to_vec()).collect(),
+				})
+				.collect();
+			let stored_bytes = bincode::serialize(&stored).expect("Provider records to be serializable");
+			self.providers.insert(key.as_ref(), stored_bytes).map(|e| {
+				if e.is_none() {
+					self.provider_count += 1;
+				}
+			})
+		};
+		if let Err(e) = result {
+			event!(Level::ERROR, "Error saving provider records: {:?}", e);
+		}
+	}
+}
+
+impl<'a> RecordStore<'a> for SledStore {
+	type RecordsIter = std::vec::IntoIter<Cow<'a, Record>>;
+	type ProvidedIter = std::vec::IntoIter<Cow<'a, ProviderRecord>>;
+
+	fn get(&'a self, k: &Key) -> Option<Cow<'a, Record>> {
+		let stored = match self.records.get(k.as_ref()) {
+			Ok(d) => d?,
+			Err(e) => {
+				event!(Level::ERROR, "Error loading record: {:?}", e);
+				return None
+			}
+		};
+		let stored: StoredRecord = bincode::deserialize(&stored).ok()?;
+		Some(Cow::Owned(to_record(k.clone(), stored)))
+	}
+
+	fn put(&'a mut self, r: Record) -> Result<()> {
+		if r.value.len() >= self.config.max_value_bytes {
+			return Err(Error::ValueTooLarge)
+		}
+		if !self.records.contains_key(r.key.as_ref()).unwrap_or_default() && self.record_count >= self.config.max_records {
+			return Err(Error::MaxRecords)
+		}
+		let stored = StoredRecord {
+			value: r.value,
+			publisher: r.publisher.map(|e| e.to_bytes()),
+			expires: r.expires.map(to_timestamp),
+		};
+		let stored_bytes = bincode::serialize(&stored).expect("Record to be serializable");
+		match self.records.insert(r.key.as_ref(), stored_bytes) {
+			Ok(None) => self.record_count += 1,
+			Ok(Some(_)) => {}
+			Err(e) => event!(Level::ERROR, "Error saving record: {:?}", e),
+		}
+		Ok(())
+	}
+
+	fn remove(&'a mut self, k: &Key) {
+		match self.records.remove(k.as_ref()) {
+			Ok(Some(_)) => self.record_count -= 1,
+			Ok(None) => {}
+			Err(e) => event!(Level::ERROR, "Error removing record: {:?}", e),
+		}
+	}
+
+	fn records(&'a self) -> Self::RecordsIter {
+		self.records.iter()
+			.filter_map(|e| e.ok())
+			.filter_map(|(key, value)| {
+				let stored: StoredRecord = bincode::deserialize(&value).ok()?;
+				Some(Cow::Owned(to_record(Key::from(key.to_vec()), stored)))
+			})
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+
+	fn add_provider(&'a mut self, record: ProviderRecord) -> Result<()> {
+		if record.provider == *self.local_key.preimage() {
+			self.provided.insert(record.key.clone(), record);
+			return Ok(())
+		}
+		let mut providers = self.load_providers(&record.key);
+		if providers.is_empty() && self.provider_count >= self.config.max_provided_keys {
+			return Err(Error::MaxProvidedKeys)
+		}
+		if let Some(i) = providers.iter().position(|p| p.provider == record.provider) {
+			providers[i] = record.clone();
+		} else {
+			let key = kbucket::Key::new(record.key.clone());
+			let provider = kbucket::Key::from(record.provider);
+			match providers.iter().position(|p| provider.distance(&key) < kbucket::Key::from(p.provider).distance(&key)) {
+				Some(i) => {
+					providers.insert(i, record.clone());
+					providers.truncate(self.config.max_providers_per_key);
+				}
+				// Farther away than every known provider, only kept if there is room.
+				None if providers.len() < self.config.max_providers_per_key => providers.push(record.clone()),
+				None => return Ok(()),
+			}
+		}
+		self.save_providers(&record.key, &providers);
+		Ok(())
+	}
+
+	fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+		self.provided.get(key)
+			.cloned()
+			.into_iter()
+			.chain(self.load_providers(key))
+			.collect()
+	}
+
+	fn provided(&'a self) -> Self::ProvidedIter {
+		self.provided.values()
+			.map(Cow::Borrowed)
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+
+	fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+		if *p == *self.local_key.preimage() {
+			self.provided.remove(k);
+			return
+		}
+		let mut providers = self.load_providers(k);
+		providers.retain(|e| e.provider != *p);
+		self.save_providers(k, &providers);
+	}
+}
+
+fn to_record(key: Key, stored: StoredRecord) -> Record {
+	Record {
+		key,
+		value: stored.value,
+		publisher: stored.publisher.and_then(|e| PeerId::from_bytes(&e).ok()),
+		expires: stored.expires.map(to_instant),
+	}
+}
+
+fn to_timestamp(instant: Instant) -> u64 {
+	unix_now() + instant.saturating_duration_since(Instant::now()).as_secs()
+}
+
+fn to_instant(timestamp: u64) -> Instant {
+	Instant::now() + Duration::from_secs(timestamp.saturating_sub(unix_now()))
+}
+
+#[test]
+fn records_and_providers_survive_reopening() {
+	let db = sled::Config::new().temporary(true).open().unwrap();
+	let local_id = PeerId::random();
+	let remote_id = PeerId::random();
+	let key = Key::new(b"thisisatestaddress");
+
+	let mut store = SledStore::with_db(db.clone(), local_id).unwrap();
+	store.put(Record::new(key.clone(), b"clear address".to_vec())).unwrap();
+	store.add_provider(ProviderRecord::new(key.clone(), local_id, Vec::new())).unwrap();
+	store.add_provider(ProviderRecord::new(key.clone(), remote_id, Vec::new())).unwrap();
+	assert_eq!(store.providers(&key).len(), 2);
+	assert_eq!(store.provided().map(|e| e.provider).collect::<Vec<_>>(), vec![local_id]);
+
+	store.remove_provider(&key, &local_id);
+	assert_eq!(store.provided().count(), 0);
+	assert_eq!(store.providers(&key).len(), 1);
+
+	store.add_provider(ProviderRecord::new(key.clone(), local_id, Vec::new())).unwrap();
+	drop(store);
+
+	// Only records of other nodes reach the disk, this node announces its shares again on startup.
+	let mut store = SledStore::with_db(db, local_id).unwrap();
+	assert_eq!(store.get(&key).unwrap().value, b"clear address".to_vec());
+	assert_eq!(store.providers(&key).iter().map(|e| e.provider).collect::<Vec<_>>(), vec![remote_id]);
+	assert_eq!(store.provided().count(), 0);
+	assert_eq!((store.record_count, store.provider_count), (1, 1));
+
+	store.remove(&key);
+	store.remove_provider(&key, &remote_id);
+	assert_eq!((store.record_count, store.provider_count), (0, 0));
+}
+
+#[test]
+fn own_providers_are_not_capped() {
+	let local_id = PeerId::random();
+	let mut store = SledStore::temporary(local_id).unwrap();
+
+	for i in 0..store.config.max_provided_keys + 1 {
+		store.add_provider(ProviderRecord::new(Key::new(&i.to_be_bytes()), local_id, Vec::new())).unwrap();
+	}
+
+	assert_eq!(store.provided().count(), store.config.max_provided_keys + 1);
+	assert_eq!(store.providers.len(), 0);
+}
+
+#[test]
+fn stored_own_providers_are_dropped() {
+	let db = sled::Config::new().temporary(true).open().unwrap();
+	let local_id = PeerId::random();
+	let key = Key::new(b"thisisatestaddress");
+
+	// Written like by earlier versions, which kept the records of this node on disk.
+	let mut store = SledStore::with_db(db.clone(), PeerId::random()).unwrap();
+	store.add_provider(ProviderRecord::new(key.clone(), local_id, Vec::new())).unwrap();
+	drop(store);
+
+	let store = SledStore::with_db(db, local_id).unwrap();
+	assert!(store.providers(&key).is_empty());
+	assert_eq!(store.provider_count, 0);
+	assert!(!store.providers.contains_key(key.as_ref()).unwrap());
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+
+/// Number of saved addresses buffered for watchers that fall behind.
+const NOTIFICATION_CAPACITY: usize = 1024;
+
+/// Maximum number of watchers at the same time.
+const MAX_WATCHERS: usize = 1024;
+
+/// Subscription to the addresses of saved shares. Holds one of the watcher slots of the hub until dropped.
+#[derive(Debug)]
+pub struct Watch {
+	pub receiver: broadcast::Receiver<Vec<u8>>,
+	_permit: OwnedSemaphorePermit,
+}
+
+/// Broadcasts the address of every share saved on this node to everyone watching for new shares.
+///
+/// Only addresses are sent, watchers read the shares from the database.
+#[derive(Debug, Clone)]
+pub struct NotificationHub {
+	sender: broadcast::Sender<Vec<u8>>,
+	watchers: Arc<Semaphore>,
+}
+
+impl NotificationHub {
+	pub fn new(capacity: usize, max_watchers: usize) -> Self {
+		let (sender, _) = broadcast::channel(capacity);
+		Self {
+			sender,
+			watchers: Arc::new(Semaphore::new(max_watchers)),
+		}
+	}
+
+	pub fn notify(&self, address: &[u8]) {
+		if self.sender.receiver_count() == 0 {
+			return
+		}
+		// Sending only fails if every watcher left in the meantime.
+		let _ = self.sender.send(address.to_vec());
+	}
+
+	/// Subscribes to saved addresses. Returns `None` if the maximum number of watchers is reached.
+	pub fn subscribe(&self) -> Option<Watch> {
+		let permit = self.watchers.clone().try_acquire_owned().ok()?;
+		Some(Watch {
+			receiver: self.sender.subscribe(),
+			_permit: permit,
+		})
+	}
+}
+
+impl Default for NotificationHub {
+	fn default() -> Self {
+		Self::new(NOTIFICATION_CAPACITY, MAX_WATCHERS)
+	}
+}
+
+#[test]
+fn limit_watchers() {
+	let hub = NotificationHub::new(4, 1);
+	let first = hub.subscribe();
+	let second = hub.subscribe();
+	drop(first);
+	let third = hub.subscribe();
+
+	assert!(second.is_none());
+	assert!(third.is_some());
+}
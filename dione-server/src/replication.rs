@@ -0,0 +1,238 @@
+use tracing::*;
+
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use libp2p::PeerId;
+use libp2p::kad::{kbucket, K_VALUE};
+use crate::db::{MessageStoreDb, MessageEntry, SaveOutcome, unix_now};
+use crate::network::{Client, InboundReplica, ReplicaRequest, ReplicaResponse};
+use crate::notify::NotificationHub;
+
+/// Answers the replication requests of other nodes, keeping their replicas in `db` for at most `max_ttl` seconds.
+pub async fn run<T: MessageStoreDb + Send + Sync>(db: Arc<T>, client: Client, hub: NotificationHub, max_ttl: u64, mut requests: mpsc::Receiver<InboundReplica>) {
+	while let Some(inbound) = requests.recv().await {
+		let result = match &inbound.request {
+			// Replicas skip proof-of-work and tokens, so only nodes that would pick this one as replica may push them.
+			ReplicaRequest::Store { address, .. } => {
+				let routing_table = client.routing_table().await;
+				match accepts_replica(client.local_peer_id(), &routing_table, inbound.peer, address) {
+					true => handle(&db, &client, &hub, max_ttl, inbound.request).await,
+					false => Err(anyhow::Error::msg("Peer isn't in the routing table or the share is too far from this node")),
+				}
+			}
+			ReplicaRequest::Delete { .. } => handle(&db, &client, &hub, max_ttl, inbound.request).await,
+		};
+		let response = match result {
+			Ok(()) => ReplicaResponse::Done,
+			Err(e) => {
+				event!(Level::INFO, "Refused replication request of {}: {:?}", inbound.peer, e);
+				ReplicaResponse::Refused
+			}
+		};
+		client.respond_replica(inbound.channel, response).await;
+	}
+}
+
+/// Whether `sender` may push a replica of the share at `address`. The sender has to be in the routing table and this
+/// node among the [K_VALUE] nodes closest to the share it knows of, as only those are picked for replicas.
+fn accepts_replica(local_peer_id: PeerId, routing_table: &[PeerId], sender: PeerId, address: &[u8]) -> bool {
+	if !routing_table.contains(&sender) {
+		return false
+	}
+	let key = kbucket::Key::new(address.to_vec());
+	let local_distance = kbucket::Key::from(local_peer_id).distance(&key);
+	let closer = routing_table.iter()
+		.filter(|e| kbucket::Key::from(**e).distance(&key) < local_distance)
+		.count();
+	closer < K_VALUE.get()
+}
+
+async fn handle<T: MessageStoreDb + Send + Sync>(db: &Arc<T>, client: &Client, hub: &NotificationHub, max_ttl: u64, request: ReplicaRequest) -> anyhow::Result<()> {
+	match request {
+		ReplicaRequest::Store { address, entry } => {
+			let now = unix_now();
+			if entry.is_expired(now) {
+				return Err(anyhow::Error::msg("Replica already expired"))
+			}
+			// The clock and limits of the sender aren't trusted, the replica is kept no longer than a share saved here.
+			let entry = MessageEntry {
+				inserted_at: now,
+				ttl: (entry.expires_at() - now).min(max_ttl),
+				..entry
+			};
+			if db.save_message(&address, entry).await? == SaveOutcome::Stored {
+				hub.notify(&address);
+			}
+			// The announcement can take until the DHT query times out, which mustn't hold up further requests.
+			let client = client.clone();
+			tokio::spawn(async move {
+				if let Err(e) = client.start_providing(address).await {
+					event!(Level::INFO, "Error announcing replica: {:?}", e);
+				}
+			});
+		}
+		ReplicaRequest::Delete { address, secret } => {
+			let entry = db.get_message(&address)
+				.await?
+				.ok_or_else(|| anyhow::Error::msg("No replica of the share"))?;
+			if !entry.may_delete(&secret) {
+				return Err(anyhow::Error::msg("Secret doesn't match the delete commitment"))
+			}
+			db.remove_message(&address).await?;
+			client.stop_providing(address).await;
+		}
+	}
+	Ok(())
+}
+
+/// Copies the share at `address` to the `replicas` peers closest to it that accept it, trying further peers until
+/// enough accepted or none are left. The share stays on this node even if replication fails.
+pub async fn replicate(client: Client, address: Vec<u8>, entry: MessageEntry, replicas: usize) {
+	let peers = client.get_closest_peers(address.clone()).await;
+	let mut replicated = 0;
+	for peer in peers {
+		if replicated == replicas {
+			break
+		}
+		let request = ReplicaRequest::Store {
+			address: address.clone(),
+			entry: entry.clone(),
+		};
+		match client.replicate(peer, request).await {
+			Ok(ReplicaResponse::Done) => {
+				event!(Level::DEBUG, "Replicated share to {}", peer);
+				replicated += 1;
+			}
+			Ok(ReplicaResponse::Refused) => event!(Level::INFO, "{} refused replica of share", peer),
+			Err(e) => event!(Level::INFO, "Replicating share to {} failed: {:?}", peer, e),
+		}
+	}
+	if replicated < replicas {
+		event!(Level::DEBUG, "Replicated share to {} of {} peers", replicated, replicas);
+	}
+}
+
+/// Announces this node as provider of every share still in `db`, so they are found again after a restart.
+pub async fn announce_stored<T: MessageStoreDb + Send + Sync>(db: Arc<T>, client: Client) {
+	let addresses = match db.addresses(unix_now()).await {
+		Ok(d) => d,
+		Err(e) => {
+			event!(Level::ERROR, "Error listing stored shares: {:?}", e);
+			return
+		}
+	};
+	event!(Level::INFO, "Announcing {} stored shares", addresses.len());
+	for address in addresses {
+		if let Err(e) = client.start_providing(address).await {
+			event!(Level::INFO, "Error announcing stored share: {:?}", e);
+		}
+	}
+}
+
+/// Asks every other provider of the share at `address` to delete its replica. Returns the number of deleted replicas.
+pub async fn propagate_delete(client: Client, address: Vec<u8>, secret: Vec<u8>) -> usize {
+	let local_peer_id = client.local_peer_id();
+	let mut deleted = 0;
+	for peer in client.get_providers(address.clone()).await {
+		if peer == local_peer_id {
+			continue
+		}
+		let request = ReplicaRequest::Delete {
+			address: address.clone(),
+			secret: secret.clone(),
+		};
+		match client.replicate(peer, request).await {
+			Ok(ReplicaResponse::Done) => {
+				event!(Level::DEBUG, "Deleted replica on {}", peer);
+				deleted += 1;
+			}
+			Ok(ReplicaResponse::Refused) => event!(Level::INFO, "{} refused deleting replica", peer),
+			Err(e) => event!(Level::INFO, "Deleting replica on {} failed: {:?}", peer, e),
+		}
+	}
+	deleted
+}
+
+#[cfg(test)]
+use crate::db::MemoryDb;
+
+#[cfg(test)]
+use crate::network;
+
+#[cfg(test)]
+use dione_lib::hashing::cryptographic::sha512_hash_bytes;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn store_and_delete_replica() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let db = Arc::new(MemoryDb::test_connection(""));
+	let hub = NotificationHub::default();
+	let address = b"thisisatestaddress".to_vec();
+	let entry = MessageEntry::new(b"This is just testcontent".to_vec(), 60)
+		.with_delete_commitment(Some(sha512_hash_bytes(b"delete secret")));
+
+	let stored = handle(&db, &client, &hub, 60, ReplicaRequest::Store {
+		address: address.clone(),
+		entry: entry.clone(),
+	}).await;
+	let denied = handle(&db, &client, &hub, 60, ReplicaRequest::Delete {
+		address: address.clone(),
+		secret: b"wrong secret".to_vec(),
+	}).await;
+	let still_there = db.get_message(&address).await.unwrap();
+	let deleted = handle(&db, &client, &hub, 60, ReplicaRequest::Delete {
+		address: address.clone(),
+		secret: b"delete secret".to_vec(),
+	}).await;
+	let gone = db.get_message(&address).await.unwrap();
+
+	assert!(stored.is_ok());
+	assert!(denied.is_err());
+	assert_eq!(still_there, Some(entry));
+	assert!(deleted.is_ok());
+	assert_eq!(gone, None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn clamp_replica_ttl() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let db = Arc::new(MemoryDb::test_connection(""));
+	let hub = NotificationHub::default();
+	let mut entry = MessageEntry::new(b"This is just testcontent".to_vec(), u64::MAX);
+	entry.inserted_at = 0;
+
+	handle(&db, &client, &hub, 60, ReplicaRequest::Store {
+		address: b"thisisatestaddress".to_vec(),
+		entry,
+	}).await.unwrap();
+	let stored = db.get_message(b"thisisatestaddress").await.unwrap().unwrap();
+
+	assert!(stored.inserted_at >= unix_now() - 1);
+	assert_eq!(stored.ttl, 60);
+}
+
+#[test]
+fn accept_replicas_only_from_routing_table() {
+	let address = b"thisisatestaddress";
+	let key = kbucket::Key::new(address.to_vec());
+	let mut peers: Vec<PeerId> = (0..K_VALUE.get() + 2).map(|_| PeerId::random()).collect();
+	peers.sort_by_key(|e| kbucket::Key::from(*e).distance(&key));
+	let sender = peers[0];
+	let stranger = PeerId::random();
+
+	let close = accepts_replica(peers[1], &peers[..4], sender, address);
+	let unknown_sender = accepts_replica(peers[1], &peers[..4], stranger, address);
+	let too_far = accepts_replica(peers[K_VALUE.get() + 1], &peers[..K_VALUE.get() + 1], sender, address);
+
+	assert!(close);
+	assert!(!unknown_sender);
+	assert!(!too_far);
+}
@@ -0,0 +1,81 @@
+use tonic::{Status, Code};
+use prost::Message;
+use thiserror::Error;
+
+use crate::db::DbError;
+use crate::message_storage::SaveMessageResponse;
+
+/// Errors while handling requests of the [MessageStorage](crate::message_storage::message_storage_server::MessageStorage) service.
+#[derive(Debug, Error)]
+pub enum StorerError {
+	#[error("No share stored at the requested address")]
+	NotFound,
+	#[error("Secret doesn't match the delete commitment of the share")]
+	PermissionDenied,
+	#[error("Share of {size} bytes is too large for a single message, use the chunked variant")]
+	TooLarge { size: usize },
+	#[error("Share needs a proof-of-work stamp of {difficulty} bits")]
+	PowRequired { difficulty: u32 },
+	#[error("Share needs an unspent token issued by this node")]
+	TokenRequired,
+	#[error("Invalid request: {0}")]
+	InvalidRequest(&'static str),
+	#[error(transparent)]
+	Rejected(#[from] DbError),
+	#[error("Error in database: {0}")]
+	Db(anyhow::Error),
+}
+
+impl StorerError {
+	/// Code reported for the item in responses of batch requests.
+	pub fn response_code(&self) -> i32 {
+		match self {
+			StorerError::NotFound => 404,
+			StorerError::PermissionDenied => 403,
+			StorerError::TooLarge { .. } => 416,
+			StorerError::PowRequired { .. } => 402,
+			StorerError::TokenRequired => 401,
+			StorerError::InvalidRequest(_) => 400,
+			StorerError::Rejected(d) => d.response_code(),
+			StorerError::Db(_) => 500,
+		}
+	}
+}
+
+impl From<anyhow::Error> for StorerError {
+	fn from(e: anyhow::Error) -> Self {
+		match e.downcast::<DbError>() {
+			Ok(d) => StorerError::Rejected(d),
+			Err(e) => StorerError::Db(e),
+		}
+	}
+}
+
+impl From<StorerError> for Status {
+	fn from(err: StorerError) -> Self {
+		match err {
+			StorerError::NotFound => Status::not_found(err.to_string()),
+			StorerError::PermissionDenied => Status::permission_denied(err.to_string()),
+			StorerError::TooLarge { .. } => Status::out_of_range(err.to_string()),
+			StorerError::PowRequired { .. } => Status::failed_precondition(err.to_string()),
+			StorerError::TokenRequired => Status::unauthenticated(err.to_string()),
+			StorerError::InvalidRequest(_) => Status::invalid_argument(err.to_string()),
+			StorerError::Rejected(d) => rejection_status(&d),
+			StorerError::Db(e) => Status::internal(e.to_string()),
+		}
+	}
+}
+
+/// Status for a share refused by the database. The details carry a [SaveMessageResponse] with the matching code.
+fn rejection_status(err: &DbError) -> Status {
+	let response = SaveMessageResponse {
+		code: err.response_code(),
+		hash: None,
+		hash_type: None,
+	};
+	let code = match err {
+		DbError::AlreadyExists => Code::AlreadyExists,
+		_ => Code::ResourceExhausted,
+	};
+	Status::with_details(code, err.to_string(), response.encode_to_vec().into())
+}
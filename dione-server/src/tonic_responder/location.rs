@@ -6,7 +6,7 @@ use crate::message_storage::{ServerLocRequest, ServerLocResponse, MessageLocResp
 use crate::network::Client;
 use std::collections::{HashMap, VecDeque};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LocationService {
 	client: Client,
 }
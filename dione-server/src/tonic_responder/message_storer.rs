@@ -1,62 +1,221 @@
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 use tracing::*;
 
-use crate::message_storage::{SaveMessageRequest, SaveMessageResponse, GetMessageResponse, GetMessageRequest};
+use crate::message_storage::{SaveMessageRequest, SaveMessageResponse, GetMessageResponse, GetMessageRequest, DeleteMessageRequest, DeleteMessageResponse};
+use crate::message_storage::{SaveMessagesRequest, SaveMessagesResponse, GetMessagesRequest, GetMessagesResponse, GetMessagesItem, WatchAddressesRequest, SaveChunk, GetChunk, PowParamsRequest, PowParamsResponse, IssueTokensRequest, IssueTokensResponse, InfoRequest, InfoResponse, ConnectedPeer, RateToken};
 use crate::message_storage::message_storage_server::MessageStorage;
+use crate::message_storage::HashType;
 use crate::network::Client;
+use crate::notify::NotificationHub;
+use crate::replication;
+use crate::fetch;
+use dione_lib::hashing::cryptographic::sha512_hash_bytes;
+use dione_lib::hashing::{pow, hash_bytes, HashAlgorithm, StreamHasher};
+use dione_lib::cryptography::blind_token::{IssuerKey, Token, ISSUE_TOKENS_ADDRESS};
+use rand::RngCore;
+use rand::rngs::OsRng;
 
 #[cfg(test)]
 use crate::network;
 
-use crate::db::MessageStoreDb;
+#[cfg(test)]
+use tonic::Code;
+
+#[cfg(test)]
+use dione_lib::cryptography::blind_token::TokenRequest;
+
+
+
+
+use crate::db::{MessageStoreDb, MessageEntry, SaveOutcome, Limits, DbError};
+use crate::tonic_responder::error::StorerError;
+use crate::tonic_responder::message_storer_request_handle::{parse_hash_type, SUPPORTED_HASH_TYPES};
 
 #[cfg(test)]
-use crate::db::MessageDb;
+use crate::db::MemoryDb;
 
+use std::collections::HashSet;
 use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Version of the protocol reported by Info. Raised on changes clients have to know about.
+///
+/// Version 2 hashes receipts with the hash type requested in `SaveMessageRequest.hash_type`. Version 3 streams
+/// `GetMessagesItem`s from WatchAddresses, which announce shares too large for a single message with code 416.
+const PROTOCOL_VERSION: u32 = 3;
+
+/// Maximum number of addresses a single WatchAddresses request may watch.
+const MAX_WATCHED_ADDRESSES: usize = 256;
+
+/// Maximum number of shares a single SaveMessages or GetMessages request may carry.
+const MAX_BATCH_SIZE: usize = 256;
+
+/// Largest share returned by the unary get requests. Leaves room for the rest of the message below the 4 MiB gRPC default.
+const MAX_UNARY_CONTENT: usize = 4 * 1024 * 1024 - 64 * 1024;
+
+/// Size of the chunks sent by GetMessageChunked.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const UPLOAD_ID_LENGTH: usize = 16;
+
+/// Maximum number of tokens a single IssueTokens request may ask for.
+const MAX_TOKENS_PER_ISSUE: usize = 64;
 
 #[derive(Debug)]
 pub struct MessageStorer<T: MessageStoreDb> {
-	db_conn: T,
+	db_conn: Arc<T>,
 	client: Client,
+	default_ttl: u64,
+	max_ttl: u64,
+	hub: NotificationHub,
+	pow_difficulty: u32,
+	token_key: Option<IssuerKey>,
+	require_tokens: bool,
+	limits: Limits,
+	replicas: usize,
+	proxy: bool,
+}
+
+// Manual impl, as deriving would require `T: Clone` although only the `Arc` is cloned.
+impl<T: MessageStoreDb> Clone for MessageStorer<T> {
+	fn clone(&self) -> Self {
+		Self {
+			db_conn: self.db_conn.clone(),
+			client: self.client.clone(),
+			default_ttl: self.default_ttl,
+			max_ttl: self.max_ttl,
+			hub: self.hub.clone(),
+			pow_difficulty: self.pow_difficulty,
+			token_key: self.token_key.clone(),
+			require_tokens: self.require_tokens,
+			limits: self.limits.clone(),
+			replicas: self.replicas,
+			proxy: self.proxy,
+		}
+	}
 }
 
 impl<T: MessageStoreDb> MessageStorer<T> {
-	pub(crate) fn new(conn: T, client: Client) -> Self {
+	pub(crate) fn new(conn: Arc<T>, client: Client, default_ttl: u64, max_ttl: u64) -> Self {
 		Self {
 			db_conn: conn,
-			client
+			client,
+			default_ttl,
+			max_ttl,
+			hub: NotificationHub::default(),
+			pow_difficulty: 0,
+			token_key: None,
+			require_tokens: false,
+			limits: Limits::default(),
+			replicas: 0,
+			proxy: false,
 		}
 	}
-}
 
-#[tonic::async_trait]
-impl<T: MessageStoreDb + Sync + Send + Debug + 'static> MessageStorage for MessageStorer<T> {
-	#[instrument(skip(self, request))]
-	async fn save_message(
-		&self,
-		request: Request<SaveMessageRequest>,
-	) -> Result<Response<SaveMessageResponse>, Status> {
-		event!(Level::INFO, "Processing Request");
+	/// Copies every new share saved in one piece to the `replicas` peers closest to its address.
+	pub(crate) fn with_replicas(mut self, replicas: usize) -> Self {
+		self.replicas = replicas;
+		self
+	}
 
-		let client_clone = self.client.clone();
+	/// Serves shares this node doesn't hold by fetching them from their providers.
+	pub(crate) fn with_proxy(mut self, proxy: bool) -> Self {
+		self.proxy = proxy;
+		self
+	}
 
-		let request_data = request.into_inner();
+	/// Hub notified of every share saved through this storer.
+	pub(crate) fn hub(&self) -> NotificationHub {
+		self.hub.clone()
+	}
 
+	/// Limits of the database, as reported by Info. They are enforced by the database itself.
+	pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+		self.limits = limits;
+		self
+	}
 
-		let addr = request_data.addr.clone();
+	/// Demands a proof-of-work stamp of `pow_difficulty` bits for every share. 0 disables proof of work.
+	pub(crate) fn with_pow_difficulty(mut self, pow_difficulty: u32) -> Self {
+		self.pow_difficulty = pow_difficulty;
+		self
+	}
+
+	/// Issues tokens signed with `key`. Redeeming a token replaces the proof-of-work stamp of a share.
+	/// With `require_tokens`, shares without a token are refused.
+	pub(crate) fn with_token_issuer(mut self, key: IssuerKey, require_tokens: bool) -> Self {
+		self.token_key = Some(key);
+		self.require_tokens = require_tokens;
+		self
+	}
+
+	/// Checks the proof-of-work stamp over `addr` and the SHA-512 digest of the content.
+	fn check_pow(&self, addr: &[u8], content_digest: &[u8], pow_nonce: Option<u64>) -> Result<(), StorerError> {
+		if self.pow_difficulty == 0 {
+			return Ok(())
+		}
+		let difficulty = self.pow_difficulty;
+		let nonce = pow_nonce.ok_or(StorerError::PowRequired { difficulty })?;
+		if !pow::verify(addr, content_digest, nonce, difficulty) {
+			return Err(StorerError::PowRequired { difficulty })
+		}
+		Ok(())
+	}
 
-		let dht = tokio::spawn(async move {
-			println!("Propagating {:?}", addr);
+	/// TTL for a new share. Requested TTLs are clamped to the maximum of the node.
+	fn ttl_for(&self, requested: Option<u64>) -> u64 {
+		match requested {
+			Some(d) => d.min(self.max_ttl),
+			None => self.default_ttl,
+		}
+	}
+}
 
-			event!(Level::DEBUG, "Propagating to DHT");
+impl<T: MessageStoreDb + Sync + Send> MessageStorer<T> {
+	/// Admits a share at `addr` either by redeeming its token or by checking its proof-of-work stamp.
+	///
+	/// Returns the nonce of the redeemed token, which has to be given back with [MessageStorer::restore_token] if the
+	/// share isn't stored.
+	async fn admit(&self, addr: &[u8], content_digest: &[u8], pow_nonce: Option<u64>, token: Option<&RateToken>) -> Result<Option<Vec<u8>>, StorerError> {
+		if let (Some(key), Some(token)) = (&self.token_key, token) {
+			let token = Token {
+				nonce: token.nonce.clone(),
+				signature: token.signature.clone(),
+			};
+			if !key.verify_token(&token) || !self.db_conn.spend_token(&token.nonce).await? {
+				event!(Level::INFO, "Refused invalid or spent token");
+				return Err(StorerError::TokenRequired)
+			}
+			return Ok(Some(token.nonce))
+		}
+		if self.require_tokens {
+			return Err(StorerError::TokenRequired)
+		}
+		self.check_pow(addr, content_digest, pow_nonce)?;
+		Ok(None)
+	}
+
+	/// Gives back the token spent by [MessageStorer::admit] on a share that wasn't stored.
+	async fn restore_token(&self, spent: Option<Vec<u8>>) {
+		if let Some(nonce) = spent {
+			if let Err(e) = self.db_conn.restore_token(&nonce).await {
+				event!(Level::ERROR, "Error restoring token: {:?}", e);
+			}
+		}
+	}
 
-			client_clone.start_providing(addr).await;
+	/// Saves a single share and announces it in the DHT.
+	async fn store(&self, request_data: SaveMessageRequest) -> Result<SaveMessageResponse, StorerError> {
+		let hash_type = request_data.requested_hash_type()
+			.ok_or(StorerError::InvalidRequest("Unknown hash type"))?;
 
-			event!(Level::DEBUG, "Propagated to DHT");
-		});
+		let content_digest = sha512_hash_bytes(&request_data.content);
+		let spent = self.admit(&request_data.addr, &content_digest, request_data.pow_nonce, request_data.token.as_ref()).await?;
 
-		let (hash_type, hash) = request_data.hash_content();
+		let hash = hash_bytes(hash_type.into(), &request_data.content);
 
 		event!(Level::DEBUG, "Calculated Hash");
 
@@ -68,12 +227,232 @@ impl<T: MessageStoreDb + Sync + Send + Debug + 'static> MessageStorage for Messa
 
 		event!(Level::DEBUG, "Formulated Response");
 
-		dht.await.unwrap();
+		let ttl = self.ttl_for(request_data.ttl);
+		let entry = MessageEntry::new(request_data.content.clone(), ttl)
+			.with_delete_commitment(request_data.delete_commitment);
+		let replica = match self.replicas {
+			0 => None,
+			_ => Some(entry.clone()),
+		};
+
+		match self.db_conn.save_message(&request_data.addr, entry).await.map_err(StorerError::from) {
+			Ok(SaveOutcome::Stored) => {
+				event!(Level::DEBUG, "Saved to DB");
+				self.hub.notify(&request_data.addr);
+				if let Some(replica) = replica {
+					tokio::spawn(replication::replicate(self.client.clone(), request_data.addr.clone(), replica, self.replicas));
+				}
+			}
+			Ok(SaveOutcome::Duplicate) => {
+				event!(Level::DEBUG, "Identical share already in DB");
+				self.restore_token(spent).await;
+			}
+			Err(e) => {
+				event!(Level::INFO, "Rejected share: {}", e);
+				self.restore_token(spent).await;
+				return Err(e)
+			}
+		}
+
+		let addr = request_data.addr.clone();
+
+		event!(Level::DEBUG, "Propagating to DHT");
+
+		// The share is stored either way, it is announced again with the next restart.
+		match self.client.start_providing(addr).await {
+			Ok(()) => event!(Level::DEBUG, "Propagated to DHT"),
+			Err(e) => event!(Level::INFO, "Error propagating to DHT: {:?}", e),
+		}
+
+		Ok(reply)
+	}
+
+	async fn load(&self, addr: &[u8]) -> Result<Vec<u8>, StorerError> {
+		if let Some(entry) = self.db_conn.get_message(addr).await? {
+			return Ok(entry.content)
+		}
+		if !self.proxy {
+			return Err(StorerError::NotFound)
+		}
+		event!(Level::DEBUG, "Fetching share from providers");
+		fetch::fetch_from_providers(&self.client, addr)
+			.await
+			.ok_or(StorerError::NotFound)
+	}
+
+	/// Like [MessageStorer::load], but refuses shares too large for a single message.
+	async fn load_unary(&self, addr: &[u8]) -> Result<Vec<u8>, StorerError> {
+		let content = self.load(addr).await?;
+		if content.len() > MAX_UNARY_CONTENT {
+			return Err(StorerError::TooLarge { size: content.len() })
+		}
+		Ok(content)
+	}
+
+	/// Saves a share uploaded in chunks under `upload_id` and announces it in the DHT.
+	///
+	/// The upload is admitted on its first chunk and staged chunk by chunk, so it is never held in memory as a whole.
+	/// Shares uploaded in chunks aren't replicated, as replicas travel in a single message.
+	async fn store_chunked(&self, upload_id: &[u8], stream: &mut Streaming<SaveChunk>) -> Result<SaveMessageResponse, Status> {
+		let first = stream.message()
+			.await?
+			.ok_or(StorerError::InvalidRequest("Upload carries no chunk"))?;
+		let addr = first.addr.ok_or(StorerError::InvalidRequest("First chunk carries no address"))?;
+		let digest = first.digest.ok_or(StorerError::InvalidRequest("First chunk carries no digest"))?;
+		let hash_type = parse_hash_type(first.hash_type)
+			.ok_or(StorerError::InvalidRequest("Unknown hash type"))?;
+
+		let spent = self.admit(&addr, &digest, first.pow_nonce, first.token.as_ref()).await?;
+
+		let entry = MessageEntry::new(Vec::new(), self.ttl_for(first.ttl))
+			.with_delete_commitment(first.delete_commitment);
+		let saved = self.save_chunks(upload_id, stream, &addr, &digest, hash_type, first.data, entry).await;
+		let receipt = match saved {
+			Ok((SaveOutcome::Stored, d)) => d,
+			Ok((SaveOutcome::Duplicate, d)) => {
+				self.restore_token(spent).await;
+				d
+			}
+			Err(e) => {
+				self.restore_token(spent).await;
+				return Err(e)
+			}
+		};
+
+		if let Err(e) = self.client.start_providing(addr).await {
+			event!(Level::INFO, "Error propagating to DHT: {:?}", e);
+		}
+
+		Ok(SaveMessageResponse {
+			code: 200,
+			hash: Some(receipt),
+			hash_type: Some(hash_type.into()),
+		})
+	}
+
+	/// Stages the upload starting with `data` chunk by chunk and saves it as `entry` at `addr`, if it matches the
+	/// SHA-512 digest `digest`. Returns the hash of type `hash_type` of the share.
+	#[allow(clippy::too_many_arguments)]
+	async fn save_chunks(&self, upload_id: &[u8], stream: &mut Streaming<SaveChunk>, addr: &[u8], digest: &[u8], hash_type: HashType, mut data: Vec<u8>, entry: MessageEntry) -> Result<(SaveOutcome, Vec<u8>), Status> {
+		let mut hasher = StreamHasher::new(HashAlgorithm::Sha512);
+		let mut receipt = StreamHasher::new(hash_type.into());
+		loop {
+			hasher.update(&data);
+			receipt.update(&data);
+			self.db_conn.append_staged(upload_id, &data).await.map_err(StorerError::from)?;
+			data = match stream.message().await? {
+				Some(d) => d.data,
+				None => break,
+			};
+		}
+		if hasher.finalize() != digest {
+			return Err(StorerError::InvalidRequest("Content doesn't match the digest").into())
+		}
+
+		match self.db_conn.save_staged(addr, upload_id, entry).await {
+			Ok(()) => {
+				event!(Level::DEBUG, "Saved to DB");
+				self.hub.notify(addr);
+				Ok((SaveOutcome::Stored, receipt.finalize()))
+			}
+			Err(e) if e.downcast_ref::<DbError>() == Some(&DbError::AlreadyExists) && self.holds(addr, digest).await? => {
+				event!(Level::DEBUG, "Identical share already in DB");
+				Ok((SaveOutcome::Duplicate, receipt.finalize()))
+			}
+			Err(e) => {
+				let e = StorerError::from(e);
+				event!(Level::INFO, "Rejected share: {}", e);
+				Err(e.into())
+			}
+		}
+	}
+
+	/// Checks whether the share stored at `addr` has the SHA-512 digest `digest`, reading it one chunk at a time.
+	async fn holds(&self, addr: &[u8], digest: &[u8]) -> Result<bool, StorerError> {
+		let entry = match self.db_conn.get_entry(addr).await? {
+			Some(d) => d,
+			None => return Ok(false),
+		};
+		let mut hasher = StreamHasher::new(HashAlgorithm::Sha512);
+		hasher.update(&entry.content);
+		let mut index = 0;
+		while let Some(chunk) = self.db_conn.get_chunk(addr, index).await? {
+			hasher.update(&chunk);
+			index += 1;
+		}
+		Ok(hasher.finalize() == digest)
+	}
+}
+
+/// Sends `head` followed by the chunks stored at `addr` in `db_conn` in pieces of [CHUNK_SIZE], followed by the digest.
+///
+/// Chunks are only read from the database if `chunked`, i.e. if `head` is the content of an entry of `db_conn`.
+async fn send_chunks<T: MessageStoreDb>(db_conn: Arc<T>, addr: Vec<u8>, head: Vec<u8>, chunked: bool, tx: mpsc::Sender<Result<GetChunk, Status>>) {
+	let mut hasher = StreamHasher::new(HashAlgorithm::Sha512);
+	let mut index = 0;
+	let mut next = Some(head);
+	while let Some(data) = next {
+		for piece in data.chunks(CHUNK_SIZE) {
+			hasher.update(piece);
+			let chunk = GetChunk {
+				data: piece.to_vec(),
+				digest: None,
+			};
+			if tx.send(Ok(chunk)).await.is_err() {
+				return
+			}
+		}
+		next = match chunked {
+			true => match db_conn.get_chunk(&addr, index).await {
+				Ok(d) => d,
+				Err(e) => {
+					let _ = tx.send(Err(StorerError::from(e).into())).await;
+					return
+				}
+			},
+			false => None,
+		};
+		index += 1;
+	}
+	let _ = tx.send(Ok(GetChunk {
+		data: Vec::new(),
+		digest: Some(hasher.finalize()),
+	})).await;
+}
 
-		self.db_conn.save_message(&request_data.addr, &request_data.content).await.expect("Error saving message");
+/// Item of WatchAddresses for the share at `addr` in `db_conn`, if it is stored there.
+///
+/// Shares too large for a single message are only announced with code 416, the client fetches them in chunks.
+async fn watched_item<T: MessageStoreDb>(db_conn: &T, addr: &[u8]) -> anyhow::Result<Option<GetMessagesItem>> {
+	let entry = match db_conn.get_entry(addr).await? {
+		Some(d) => d,
+		None => return Ok(None),
+	};
+	let item = match db_conn.get_chunk(addr, 0).await? {
+		None if entry.content.len() <= MAX_UNARY_CONTENT => GetMessagesItem {
+			addr: addr.to_vec(),
+			code: 200,
+			content: Some(entry.content),
+		},
+		_ => GetMessagesItem {
+			addr: addr.to_vec(),
+			code: StorerError::TooLarge { size: entry.content.len() }.response_code(),
+			content: None,
+		},
+	};
+	Ok(Some(item))
+}
 
-		event!(Level::DEBUG, "Saved to DB");
+#[tonic::async_trait]
+impl<T: MessageStoreDb + Sync + Send + Debug + 'static> MessageStorage for MessageStorer<T> {
+	#[instrument(skip(self, request))]
+	async fn save_message(
+		&self,
+		request: Request<SaveMessageRequest>,
+	) -> Result<Response<SaveMessageResponse>, Status> {
+		event!(Level::INFO, "Processing Request");
 
+		let reply = self.store(request.into_inner()).await?;
 
 		Ok(Response::new(reply))
 	}
@@ -85,19 +464,289 @@ impl<T: MessageStoreDb + Sync + Send + Debug + 'static> MessageStorage for Messa
 
 		let request_data = request.into_inner();
 
-		let content = self.db_conn.remove_message(&request_data.addr)
-			.await
-			.expect("Didn't get message")
-			.expect("Empty");
+		let content = self.load_unary(&request_data.addr).await?;
 
 		let response = GetMessageResponse {
 			addr: request_data.addr.clone(),
-			content: content.clone()
+			content
+		};
+
+		Ok(Response::new(response))
+	}
+
+	#[instrument(skip(self, request))]
+	async fn delete_message(&self, request: Request<DeleteMessageRequest>) -> Result<Response<DeleteMessageResponse>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let request_data = request.into_inner();
+
+		let entry = match self.db_conn.get_entry(&request_data.addr).await.map_err(StorerError::from)? {
+			Some(d) => d,
+			// The providers check the secret themselves.
+			None if self.proxy => {
+				let deleted = replication::propagate_delete(self.client.clone(), request_data.addr, request_data.secret).await;
+				if deleted == 0 {
+					return Err(StorerError::NotFound.into())
+				}
+				return Ok(Response::new(DeleteMessageResponse {
+					code: 200,
+				}))
+			}
+			None => return Err(StorerError::NotFound.into()),
 		};
+		if !entry.may_delete(&request_data.secret) {
+			event!(Level::INFO, "Refused deletion with wrong secret");
+			return Err(StorerError::PermissionDenied.into())
+		}
+
+		self.db_conn.remove_message(&request_data.addr)
+			.await
+			.map_err(StorerError::from)?;
+
+		event!(Level::DEBUG, "Deleted from DB");
 
 		self.client.stop_providing(request_data.addr.clone()).await;
 
-		Ok(Response::new(response))
+		tokio::spawn(replication::propagate_delete(self.client.clone(), request_data.addr, request_data.secret));
+
+		Ok(Response::new(DeleteMessageResponse {
+			code: 200,
+		}))
+	}
+
+	#[instrument(skip(self, request))]
+	async fn save_messages(&self, request: Request<SaveMessagesRequest>) -> Result<Response<SaveMessagesResponse>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let request_data = request.into_inner();
+		if request_data.messages.len() > MAX_BATCH_SIZE {
+			return Err(Status::invalid_argument(format!("At most {} shares can be saved at once", MAX_BATCH_SIZE)))
+		}
+
+		let mut responses = Vec::with_capacity(request_data.messages.len());
+		for message in request_data.messages {
+			let response = match self.store(message).await {
+				Ok(d) => d,
+				Err(e) => SaveMessageResponse {
+					code: e.response_code(),
+					hash: None,
+					hash_type: None,
+				},
+			};
+			responses.push(response);
+		}
+
+		Ok(Response::new(SaveMessagesResponse {
+			responses,
+		}))
+	}
+
+	#[instrument(skip(self, request))]
+	async fn get_messages(&self, request: Request<GetMessagesRequest>) -> Result<Response<GetMessagesResponse>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let request_data = request.into_inner();
+		if request_data.addrs.len() > MAX_BATCH_SIZE {
+			return Err(Status::invalid_argument(format!("At most {} shares can be fetched at once", MAX_BATCH_SIZE)))
+		}
+
+		let mut items = Vec::with_capacity(request_data.addrs.len());
+		for addr in request_data.addrs {
+			let item = match self.load_unary(&addr).await {
+				Ok(d) => GetMessagesItem {
+					addr,
+					code: 200,
+					content: Some(d),
+				},
+				Err(e) => GetMessagesItem {
+					addr,
+					code: e.response_code(),
+					content: None,
+				},
+			};
+			items.push(item);
+		}
+
+		Ok(Response::new(GetMessagesResponse {
+			items,
+		}))
+	}
+
+	type WatchAddressesStream = ReceiverStream<Result<GetMessagesItem, Status>>;
+
+	#[instrument(skip(self, request))]
+	async fn watch_addresses(&self, request: Request<WatchAddressesRequest>) -> Result<Response<Self::WatchAddressesStream>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let request_data = request.into_inner();
+		if request_data.addrs.len() > MAX_WATCHED_ADDRESSES {
+			return Err(Status::invalid_argument(format!("At most {} addresses can be watched at once", MAX_WATCHED_ADDRESSES)))
+		}
+
+		// Subscribe before looking into the database, so shares saved in between aren't missed.
+		let mut watch = self.hub.subscribe()
+			.ok_or_else(|| Status::resource_exhausted("Too many watchers, try again later"))?;
+		let mut pending: HashSet<Vec<u8>> = request_data.addrs.into_iter().collect();
+		let (tx, rx) = mpsc::channel(pending.len().max(1));
+		let db_conn = self.db_conn.clone();
+
+		tokio::spawn(async move {
+			let mut rescan = true;
+			while !pending.is_empty() {
+				if rescan {
+					rescan = false;
+					for addr in pending.clone() {
+						let message = match watched_item(db_conn.as_ref(), &addr).await {
+							Ok(Some(d)) => Ok(d),
+							Ok(None) => continue,
+							Err(e) => Err(Status::from(StorerError::from(e))),
+						};
+						let failed = message.is_err();
+						pending.remove(&addr);
+						if tx.send(message).await.is_err() || failed {
+							return
+						}
+					}
+					continue;
+				}
+				let notification = tokio::select! {
+					d = watch.receiver.recv() => d,
+					_ = tx.closed() => return,
+				};
+				match notification {
+					// Notifications only carry the address, the share is read from the database.
+					Ok(address) => rescan = pending.contains(&address),
+					// Notifications were dropped, the shares might be in the database by now.
+					Err(RecvError::Lagged(_)) => rescan = true,
+					Err(RecvError::Closed) => return,
+				}
+			}
+		});
+
+		Ok(Response::new(ReceiverStream::new(rx)))
+	}
+
+	#[instrument(skip(self, request))]
+	async fn save_message_chunked(&self, request: Request<Streaming<SaveChunk>>) -> Result<Response<SaveMessageResponse>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let mut stream = request.into_inner();
+		let mut upload_id = [0u8; UPLOAD_ID_LENGTH];
+		OsRng.fill_bytes(&mut upload_id);
+
+		let reply = self.store_chunked(&upload_id, &mut stream).await;
+		// Drop whatever is still staged, because the upload failed or the share was stored before.
+		if let Err(e) = self.db_conn.remove_staged(&upload_id).await {
+			event!(Level::ERROR, "Error dropping staged upload: {:?}", e);
+		}
+
+		Ok(Response::new(reply?))
+	}
+
+	type GetMessageChunkedStream = ReceiverStream<Result<GetChunk, Status>>;
+
+	#[instrument(skip(self, request))]
+	async fn get_message_chunked(&self, request: Request<GetMessageRequest>) -> Result<Response<Self::GetMessageChunkedStream>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let request_data = request.into_inner();
+
+		// Only the entry is loaded up front, its chunks are read while the stream is consumed.
+		let (head, chunked) = match self.db_conn.get_entry(&request_data.addr).await.map_err(StorerError::from)? {
+			Some(d) => (d.content, true),
+			None => (self.load(&request_data.addr).await?, false),
+		};
+		let (tx, rx) = mpsc::channel(4);
+		tokio::spawn(send_chunks(self.db_conn.clone(), request_data.addr, head, chunked, tx));
+
+		Ok(Response::new(ReceiverStream::new(rx)))
+	}
+
+	async fn pow_params(&self, _request: Request<PowParamsRequest>) -> Result<Response<PowParamsResponse>, Status> {
+		Ok(Response::new(PowParamsResponse {
+			difficulty: self.pow_difficulty,
+		}))
+	}
+
+	#[instrument(skip(self, request))]
+	async fn issue_tokens(&self, request: Request<IssueTokensRequest>) -> Result<Response<IssueTokensResponse>, Status> {
+		event!(Level::INFO, "Processing Request");
+
+		let key = self.token_key.as_ref().ok_or_else(|| Status::unimplemented("Node doesn't issue tokens"))?;
+		let request_data = request.into_inner();
+		if request_data.blinded.len() > MAX_TOKENS_PER_ISSUE {
+			return Err(Status::invalid_argument(format!("At most {} tokens can be issued at once", MAX_TOKENS_PER_ISSUE)))
+		}
+
+		if self.pow_difficulty > 0 {
+			let difficulty = self.pow_difficulty;
+			let nonce = request_data.pow_nonce.ok_or(StorerError::PowRequired { difficulty })?;
+			let digest = sha512_hash_bytes(&request_data.blinded.concat());
+			if !pow::verify(ISSUE_TOKENS_ADDRESS, &digest, nonce, difficulty) {
+				return Err(StorerError::PowRequired { difficulty }.into())
+			}
+		}
+
+		let mut evaluated = Vec::with_capacity(request_data.blinded.len());
+		let mut proofs = Vec::with_capacity(request_data.blinded.len());
+		for blinded in &request_data.blinded {
+			let (element, proof) = key.sign(blinded)
+				.map_err(|_| StorerError::InvalidRequest("Blinded element is no valid point"))?;
+			evaluated.push(element);
+			proofs.push(proof);
+		}
+
+		event!(Level::DEBUG, "Issued {} tokens", evaluated.len());
+
+		Ok(Response::new(IssueTokensResponse {
+			public_key: key.public_key(),
+			evaluated,
+			proofs,
+		}))
+	}
+
+	async fn info(&self, _request: Request<InfoRequest>) -> Result<Response<InfoResponse>, Status> {
+		let listen_addresses = self.client.get_listen_address()
+			.await
+			.map_err(|e| Status::internal(e.to_string()))?;
+		let free_capacity = self.limits.max_capacity.map(|e| e.saturating_sub(self.db_conn.stored_bytes()));
+		let peers = self.client.peers()
+			.await
+			.into_iter()
+			.map(|(peer_id, info)| ConnectedPeer {
+				peer_id: peer_id.to_base58(),
+				rtt_millis: info.rtt.map(|e| e.as_millis() as u64),
+				agent_version: info.agent_version,
+			})
+			.collect();
+
+		Ok(Response::new(InfoResponse {
+			protocol_version: PROTOCOL_VERSION,
+			server_version: env!("CARGO_PKG_VERSION").to_owned(),
+			peer_id: self.client.local_peer_id().to_base58(),
+			listen_addresses: listen_addresses.iter().map(|e| e.to_string()).collect(),
+			hash_types: SUPPORTED_HASH_TYPES.iter().map(|e| (*e).into()).collect(),
+			max_share_size: self.limits.max_share_size as u64,
+			max_chunked_share_size: self.limits.max_chunked_share_size as u64,
+			default_ttl: self.default_ttl,
+			max_ttl: self.max_ttl,
+			free_capacity,
+			pow_difficulty: self.pow_difficulty,
+			issues_tokens: self.token_key.is_some(),
+			requires_tokens: self.token_key.is_some() && self.require_tokens,
+			proxies: self.proxy,
+			peers,
+		}))
+	}
+}
+
+/// Request saving `content` at `addr` with neither TTL, delete commitment nor admission.
+#[cfg(test)]
+fn save_request(addr: &[u8], content: &[u8]) -> SaveMessageRequest {
+	SaveMessageRequest {
+		addr: addr.to_vec(),
+		content: content.to_vec(),
+		..Default::default()
 	}
 }
 
@@ -108,15 +757,10 @@ async fn save_message() {
 	tokio::spawn(async move {
 		event_loop.run().await
 	});
-	let test_db_path = String::from("test_save_message_net.sled");
-	let test_db = MessageDb::test_connection(&test_db_path);
-	test_db.flush();
-	let message_storer = MessageStorer::new(test_db, client);
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
 	let save_msg_request = Request::new(
-		SaveMessageRequest {
-			addr: b"thisisatestaddress".to_vec(),
-			content: b"This is just testcontent".to_vec()
-		}
+		save_request(b"thisisatestaddress", b"This is just testcontent")
 	);
 	let response = message_storer.save_message(save_msg_request)
 		.await
@@ -128,7 +772,6 @@ async fn save_message() {
 			hash_type: Some(1)
 		}
 	);
-	MessageDb::destroy_test_connection(&test_db_path).await.unwrap();
 	assert_eq!(test_response.into_inner(), response.into_inner())
 }
 
@@ -139,15 +782,10 @@ async fn get_message() {
 	tokio::spawn(async move {
 		event_loop.run().await
 	});
-	let test_db_path = String::from("test_get_message_net.sled");
-	let test_db = MessageDb::test_connection(&test_db_path);
-	test_db.flush();
-	let message_storer = MessageStorer::new(test_db, client);
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
 	let save_msg_request = Request::new(
-		SaveMessageRequest {
-			addr: b"thisisatestaddress".to_vec(),
-			content: b"This is just testcontent".to_vec()
-		}
+		save_request(b"thisisatestaddress", b"This is just testcontent")
 	);
 	let _ = message_storer.save_message(save_msg_request)
 		.await
@@ -166,6 +804,508 @@ async fn get_message() {
 		addr: b"thisisatestaddress".to_vec(),
 		content: b"This is just testcontent".to_vec()
 	};
-	MessageDb::destroy_test_connection(&test_db_path).await.unwrap();
 	assert_eq!(response, test_response)
-}
\ No newline at end of file
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn error_codes() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+
+	let get_msg_request = Request::new(
+		GetMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+		}
+	);
+	let not_found = message_storer.get_message(get_msg_request)
+		.await
+		.unwrap_err();
+
+	let _ = message_storer.save_message(Request::new(
+		save_request(b"thisisatestaddress", b"This is just testcontent")
+	))
+		.await
+		.expect("Error during processing");
+	let collision = message_storer.save_message(Request::new(
+		save_request(b"thisisatestaddress", b"This is other testcontent")
+	))
+		.await
+		.unwrap_err();
+
+	assert_eq!(not_found.code(), Code::NotFound);
+	assert_eq!(collision.code(), Code::AlreadyExists);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn delete_message() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+
+	let _ = message_storer.save_message(Request::new(
+		SaveMessageRequest {
+			delete_commitment: Some(sha512_hash_bytes(b"delete secret")),
+			..save_request(b"thisisatestaddress", b"This is just testcontent")
+		}
+	))
+		.await
+		.expect("Error during processing");
+
+	let denied = message_storer.delete_message(Request::new(
+		DeleteMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+			secret: b"wrong secret".to_vec(),
+		}
+	))
+		.await
+		.unwrap_err();
+	let still_there = message_storer.get_message(Request::new(
+		GetMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+		}
+	))
+		.await;
+	let deleted = message_storer.delete_message(Request::new(
+		DeleteMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+			secret: b"delete secret".to_vec(),
+		}
+	))
+		.await;
+	let gone = message_storer.get_message(Request::new(
+		GetMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+		}
+	))
+		.await
+		.unwrap_err();
+
+	assert_eq!(denied.code(), Code::PermissionDenied);
+	assert!(still_there.is_ok());
+	assert!(deleted.is_ok());
+	assert_eq!(gone.code(), Code::NotFound);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn batch_messages() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+
+	let save_msgs_request = Request::new(
+		SaveMessagesRequest {
+			messages: vec![
+				save_request(b"thisisatestaddress", b"This is just testcontent"),
+				save_request(b"thisisatestaddress", b"This is other testcontent"),
+			],
+		}
+	);
+	let save_codes: Vec<i32> = message_storer.save_messages(save_msgs_request)
+		.await
+		.expect("Error during processing")
+		.into_inner()
+		.responses
+		.iter()
+		.map(|e| e.code)
+		.collect();
+
+	let get_msgs_request = Request::new(
+		GetMessagesRequest {
+			addrs: vec![b"thisisatestaddress".to_vec(), b"thisisanothertestaddress".to_vec()],
+		}
+	);
+	let items = message_storer.get_messages(get_msgs_request)
+		.await
+		.expect("Error during processing get request")
+		.into_inner()
+		.items;
+
+	assert_eq!(save_codes, vec![200, 409]);
+	assert_eq!(items, vec![
+		GetMessagesItem {
+			addr: b"thisisatestaddress".to_vec(),
+			code: 200,
+			content: Some(b"This is just testcontent".to_vec()),
+		},
+		GetMessagesItem {
+			addr: b"thisisanothertestaddress".to_vec(),
+			code: 404,
+			content: None,
+		},
+	]);
+}
+
+#[tokio::test]
+async fn reject_oversized_batches() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+
+	let messages = (0..=MAX_BATCH_SIZE)
+		.map(|i| save_request(format!("thisisatestaddress{}", i).as_bytes(), b"This is just testcontent"))
+		.collect();
+	let save_err = message_storer.save_messages(Request::new(SaveMessagesRequest { messages }))
+		.await
+		.unwrap_err();
+	let addrs = (0..=MAX_BATCH_SIZE).map(|i| format!("thisisatestaddress{}", i).into_bytes()).collect();
+	let get_err = message_storer.get_messages(Request::new(GetMessagesRequest { addrs }))
+		.await
+		.unwrap_err();
+
+	assert_eq!(save_err.code(), Code::InvalidArgument);
+	assert_eq!(get_err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn watch_addresses() {
+	use tokio_stream::StreamExt;
+
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+
+	let _ = message_storer.save_message(Request::new(
+		save_request(b"thisisatestaddress", b"This is just testcontent")
+	))
+		.await
+		.expect("Error during processing");
+
+	let watch_request = Request::new(
+		WatchAddressesRequest {
+			addrs: vec![b"thisisatestaddress".to_vec(), b"thisisanothertestaddress".to_vec()],
+		}
+	);
+	let mut stream = message_storer.watch_addresses(watch_request)
+		.await
+		.expect("Error during processing watch request")
+		.into_inner();
+	let stored = stream.next().await.unwrap().unwrap();
+
+	let _ = message_storer.save_message(Request::new(
+		save_request(b"thisisanothertestaddress", b"This is other testcontent")
+	))
+		.await
+		.expect("Error during processing");
+	let arrived = stream.next().await.unwrap().unwrap();
+
+	assert_eq!(stored.content, Some(b"This is just testcontent".to_vec()));
+	assert_eq!(arrived.content, Some(b"This is other testcontent".to_vec()));
+	assert!(stream.next().await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn large_shares_in_chunks() {
+	use tokio_stream::StreamExt;
+
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+	let content = vec![42u8; MAX_UNARY_CONTENT + 1];
+
+	let _ = message_storer.save_message(Request::new(
+		save_request(b"thisisatestaddress", &content)
+	))
+		.await
+		.expect("Error during processing");
+
+	let too_large = message_storer.get_message(Request::new(
+		GetMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+		}
+	))
+		.await
+		.unwrap_err();
+
+	let chunks: Vec<GetChunk> = message_storer.get_message_chunked(Request::new(
+		GetMessageRequest {
+			addr: b"thisisatestaddress".to_vec(),
+		}
+	))
+		.await
+		.expect("Error during processing chunked get request")
+		.into_inner()
+		.map(|e| e.unwrap())
+		.collect()
+		.await;
+	let received: Vec<u8> = chunks.iter().flat_map(|e| e.data.clone()).collect();
+	let watched = message_storer.watch_addresses(Request::new(
+		WatchAddressesRequest {
+			addrs: vec![b"thisisatestaddress".to_vec()],
+		}
+	))
+		.await
+		.expect("Error during processing watch request")
+		.into_inner()
+		.next()
+		.await
+		.unwrap()
+		.unwrap();
+
+	assert_eq!(too_large.code(), Code::OutOfRange);
+	assert_eq!(watched.code, 416);
+	assert_eq!(watched.content, None);
+	assert!(chunks.len() > 1);
+	assert_eq!(received, content);
+	assert_eq!(chunks.last().unwrap().digest, Some(sha512_hash_bytes(&content)));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn upload_in_chunks() {
+	use tokio_stream::StreamExt;
+	use tokio_stream::wrappers::TcpListenerStream;
+	use tonic::transport::Server;
+	use crate::db::LimitedDb;
+	use crate::message_storage::message_storage_client::MessageStorageClient;
+	use crate::message_storage::message_storage_server::MessageStorageServer;
+
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = LimitedDb::with_limits(MemoryDb::test_connection(""), Limits::default());
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120)
+		.with_pow_difficulty(4);
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(Server::builder()
+		.add_service(MessageStorageServer::new(message_storer))
+		.serve_with_incoming(TcpListenerStream::new(listener)));
+	let mut storage_client = MessageStorageClient::connect(format!("http://{}", address)).await.unwrap();
+
+	// Too large for a single message and for a share saved in one piece.
+	let content: Vec<u8> = (0..Limits::default().max_share_size + 1).map(|e| e as u8).collect();
+	let digest = sha512_hash_bytes(&content);
+	let upload = |pow_nonce: Option<u64>| {
+		let mut chunks: Vec<SaveChunk> = content.chunks(CHUNK_SIZE)
+			.map(|e| SaveChunk {
+				addr: None,
+				ttl: None,
+				delete_commitment: None,
+				data: e.to_vec(),
+				digest: None,
+				pow_nonce: None,
+				token: None,
+				hash_type: None,
+			})
+			.collect();
+		chunks[0].addr = Some(b"thisisatestaddress".to_vec());
+		chunks[0].digest = Some(digest.clone());
+		chunks[0].pow_nonce = pow_nonce;
+		tokio_stream::iter(chunks)
+	};
+	let pow_nonce = Some(pow::mint(b"thisisatestaddress", &digest, 4));
+
+	let unstamped = storage_client.save_message_chunked(upload(None))
+		.await
+		.unwrap_err();
+	let stamped = storage_client.save_message_chunked(upload(pow_nonce))
+		.await
+		.expect("Error during chunked upload")
+		.into_inner();
+	let retried = storage_client.save_message_chunked(upload(pow_nonce))
+		.await
+		.expect("Error during repeated chunked upload")
+		.into_inner();
+	let chunks: Vec<GetChunk> = storage_client.get_message_chunked(GetMessageRequest {
+		addr: b"thisisatestaddress".to_vec(),
+	})
+		.await
+		.expect("Error during chunked download")
+		.into_inner()
+		.map(|e| e.unwrap())
+		.collect()
+		.await;
+	let received: Vec<u8> = chunks.iter().flat_map(|e| e.data.clone()).collect();
+
+	assert_eq!(unstamped.code(), Code::FailedPrecondition);
+	assert_eq!(stamped.hash, Some(digest.clone()));
+	assert_eq!(retried, stamped);
+	assert_eq!(received, content);
+	assert_eq!(chunks.last().unwrap().digest, Some(digest));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn demand_pow() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120)
+		.with_pow_difficulty(8);
+	let content_digest = sha512_hash_bytes(b"This is just testcontent");
+
+	let unstamped = message_storer.save_message(Request::new(
+		save_request(b"thisisatestaddress", b"This is just testcontent")
+	))
+		.await
+		.unwrap_err();
+	let stamped = message_storer.save_message(Request::new(
+		SaveMessageRequest {
+			pow_nonce: Some(pow::mint(b"thisisatestaddress", &content_digest, 8)),
+			..save_request(b"thisisatestaddress", b"This is just testcontent")
+		}
+	))
+		.await;
+	let params = message_storer.pow_params(Request::new(PowParamsRequest {}))
+		.await
+		.unwrap()
+		.into_inner();
+
+	assert_eq!(unstamped.code(), Code::FailedPrecondition);
+	assert!(stamped.is_ok());
+	assert_eq!(params.difficulty, 8);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn redeem_tokens() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120)
+		.with_token_issuer(IssuerKey::generate(), true);
+
+	let token_requests = vec![TokenRequest::default(), TokenRequest::default()];
+	let issued = message_storer.issue_tokens(Request::new(
+		IssueTokensRequest {
+			blinded: token_requests.iter().map(|e| e.blinded()).collect(),
+			pow_nonce: None,
+		}
+	))
+		.await
+		.unwrap()
+		.into_inner();
+	let rate_tokens: Vec<RateToken> = token_requests.into_iter()
+		.enumerate()
+		.map(|(i, e)| {
+			let token = e.finalize(&issued.public_key, &issued.evaluated[i], &issued.proofs[i]).unwrap();
+			RateToken {
+				nonce: token.nonce,
+				signature: token.signature,
+			}
+		})
+		.collect();
+
+	let with_token = |addr: &[u8], content: &[u8], token: Option<RateToken>| Request::new(
+		SaveMessageRequest {
+			token,
+			..save_request(addr, content)
+		}
+	);
+	let without_token = message_storer.save_message(with_token(b"thisisatestaddress", b"This is just testcontent", None))
+		.await
+		.unwrap_err();
+	let redeemed = message_storer.save_message(with_token(b"thisisatestaddress", b"This is just testcontent", Some(rate_tokens[0].clone())))
+		.await;
+	let double_spent = message_storer.save_message(with_token(b"otheraddress", b"This is just testcontent", Some(rate_tokens[0].clone())))
+		.await
+		.unwrap_err();
+	let collision = message_storer.save_message(with_token(b"thisisatestaddress", b"This is other testcontent", Some(rate_tokens[1].clone())))
+		.await
+		.unwrap_err();
+	let restored = message_storer.save_message(with_token(b"otheraddress", b"This is just testcontent", Some(rate_tokens[1].clone())))
+		.await;
+
+	assert_eq!(without_token.code(), Code::Unauthenticated);
+	assert!(redeemed.is_ok());
+	assert_eq!(double_spent.code(), Code::Unauthenticated);
+	assert_eq!(collision.code(), Code::AlreadyExists);
+	assert!(restored.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn node_info() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let peer_id = client.local_peer_id();
+	let test_db = MemoryDb::test_connection("");
+	let limits = Limits {
+		max_capacity: Some(1_000),
+		..Limits::default()
+	};
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120)
+		.with_limits(limits)
+		.with_pow_difficulty(8);
+	let _ = message_storer.store(SaveMessageRequest {
+		pow_nonce: Some(pow::mint(b"thisisatestaddress", &sha512_hash_bytes(b"This is just testcontent"), 8)),
+		..save_request(b"thisisatestaddress", b"This is just testcontent")
+	})
+		.await
+		.unwrap();
+
+	let info = message_storer.info(Request::new(InfoRequest {}))
+		.await
+		.unwrap()
+		.into_inner();
+
+	assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+	assert_eq!(info.peer_id, peer_id.to_base58());
+	assert_eq!(info.hash_types.len(), 4);
+	assert_eq!(info.default_ttl, 60);
+	assert_eq!(info.max_ttl, 120);
+	assert_eq!(info.free_capacity, Some(1_000 - 24));
+	assert_eq!(info.pow_difficulty, 8);
+	assert!(!info.issues_tokens);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn requested_hash_type() {
+	let (client, mut event_loop) = network::new().await.unwrap();
+
+	tokio::spawn(async move {
+		event_loop.run().await
+	});
+	let test_db = MemoryDb::test_connection("");
+	let message_storer = MessageStorer::new(Arc::new(test_db), client, 60, 120);
+
+	let save_request = |hash_type: Option<i32>| Request::new(
+		SaveMessageRequest {
+			hash_type,
+			..save_request(b"thisisatestaddress", b"This is just testcontent")
+		}
+	);
+	let receipt = message_storer.save_message(save_request(Some(HashType::Blake3.into())))
+		.await
+		.unwrap()
+		.into_inner();
+	let unknown = message_storer.save_message(save_request(Some(9)))
+		.await
+		.unwrap_err();
+
+	assert_eq!(receipt.hash_type, Some(HashType::Blake3.into()));
+	assert_eq!(receipt.hash, Some(dione_lib::hashing::cryptographic::blake3_hash_bytes(b"This is just testcontent")));
+	assert_eq!(unknown.code(), Code::InvalidArgument);
+}
@@ -1,28 +1,36 @@
 use tracing::*;
 
-use dione_lib::hashing::cryptographic::sha512_hash_bytes;
-use dione_lib::hashing::non_cryptographic::{adler_hash_bytes, seahash_hash_bytes};
+use dione_lib::hashing::HashAlgorithm;
 
 use crate::message_storage::HashType;
 use crate::message_storage::SaveMessageRequest;
 
+/// Hash types clients may request for the receipt of a saved share.
+pub(crate) const SUPPORTED_HASH_TYPES: [HashType; 4] = [HashType::Sha512, HashType::Adler32, HashType::Seahash, HashType::Blake3];
+
 impl SaveMessageRequest {
+	/// Hash type requested by the client, SHA-512 if it requested none. None if the requested type is unknown.
 	#[instrument(skip(self))]
-	pub(crate) fn hash_content(&self) -> (HashType, Vec<u8>) {
-		trace!("Extracting content");
-		let content = &self.content;
-		let content_length = content.len();
-		let hash_type = match content_length {
-			l if (0..=1_000).contains(&l) => HashType::Sha512,
-			l if (1_000..=10_000).contains(&l) => HashType::Adler32,
-			_ => HashType::Lz4,
-		};
-		trace!("Hashing");
-		let hash = match hash_type {
-			HashType::Sha512 => sha512_hash_bytes,
-			HashType::Adler32 => adler_hash_bytes,
-			HashType::Lz4 => seahash_hash_bytes,
-		}(content);
-		(hash_type, hash)
+	pub(crate) fn requested_hash_type(&self) -> Option<HashType> {
+		parse_hash_type(self.hash_type)
+	}
+}
+
+/// Hash type for the `hash_type` field of a request, SHA-512 if it is unset. None if the type is unknown.
+pub(crate) fn parse_hash_type(hash_type: Option<i32>) -> Option<HashType> {
+	match hash_type {
+		Some(d) => HashType::from_i32(d),
+		None => Some(HashType::Sha512),
+	}
+}
+
+impl From<HashType> for HashAlgorithm {
+	fn from(hash_type: HashType) -> Self {
+		match hash_type {
+			HashType::Sha512 => HashAlgorithm::Sha512,
+			HashType::Adler32 => HashAlgorithm::Adler32,
+			HashType::Seahash => HashAlgorithm::Seahash,
+			HashType::Blake3 => HashAlgorithm::Blake3,
+		}
 	}
 }
@@ -1,3 +1,4 @@
+pub(crate) mod error;
 pub(crate) mod message_storer;
-mod message_storer_request_handle;
+pub(crate) mod message_storer_request_handle;
 pub(crate) mod location;
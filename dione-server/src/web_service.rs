@@ -1,11 +1,25 @@
 use tracing::instrument;
 
 use tera::{Tera, Context};
-use std::sync::Mutex;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use crate::db::MessageStoreDb;
+use crate::gateway;
 use crate::network::Client;
+use crate::tonic_responder::message_storer::MessageStorer;
+use crate::tonic_responder::location::LocationService;
 use actix_web::{web, HttpRequest, HttpResponse, HttpServer, App};
 use actix_web::dev::Server;
 use actix_web::web::Data;
+use serde::Serialize;
+
+/// Connected peer as shown on the node interface.
+#[derive(Serialize)]
+struct PeerRow {
+    peer_id: String,
+    rtt: String,
+    agent_version: String,
+}
 
 #[allow(clippy::async_yields_async)]
 #[instrument]
@@ -14,16 +28,34 @@ async fn index(
     client: web::Data<Mutex<Client>>,
     _: HttpRequest
 ) -> HttpResponse {
-    let multiaddresses = client.lock().unwrap().get_listen_address().await.unwrap();
+    let client = client.lock().unwrap().clone();
+    let multiaddresses = client.get_listen_address().await.unwrap();
+    let peers: Vec<PeerRow> = client.peers().await
+        .into_iter()
+        .map(|(peer_id, info)| PeerRow {
+            peer_id: peer_id.to_base58(),
+            rtt: info.rtt.map(|e| format!("{} ms", e.as_millis())).unwrap_or_else(|| String::from("-")),
+            agent_version: info.agent_version.unwrap_or_else(|| String::from("unknown")),
+        })
+        .collect();
     let mut ctx = Context::new();
     ctx.insert("multiaddresses", &multiaddresses);
+    ctx.insert("peers", &peers);
     ctx.insert("bytes_send", &999);
     ctx.insert("bytes_received", &888);
     let s = template.render("node_interface.html", &ctx).unwrap();
     HttpResponse::Ok().content_type("text/html").body(s)
 }
 
-pub async fn make_server(client: Client, config: Option<rustls::ServerConfig>, web_http_port: usize, web_https_port: usize) -> Result<Server, std::io::Error> {
+/// Serves the node interface and the JSON gateway, which shares `storer` and `locer` with the gRPC services.
+pub async fn make_server<T: MessageStoreDb + Send + Sync + Debug + 'static>(
+    client: Client,
+    storer: Arc<MessageStorer<T>>,
+    locer: Arc<LocationService>,
+    config: Option<rustls::ServerConfig>,
+    web_http_port: usize,
+    web_https_port: usize
+) -> Result<Server, std::io::Error> {
     println!("Starting server");
     let mut tera = Tera::default();
     tera.add_raw_template("node_interface.html", include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/node_interface.html"))).unwrap();
@@ -31,7 +63,10 @@ pub async fn make_server(client: Client, config: Option<rustls::ServerConfig>, w
         App::new()
             .app_data(Data::new(tera.clone()))
             .app_data(Data::new(Mutex::new(client.clone())))
+            .app_data(Data::from(storer.clone()))
+            .app_data(Data::from(locer.clone()))
             .service(web::resource("/").route(web::get().to(index)))
+            .configure(gateway::routes::<T>)
     });
     let http_address = format!("0.0.0.0:{}", web_http_port);
     let https_address = format!("0.0.0.0:{}", web_https_port);
@@ -13,12 +13,18 @@ struct Opt {
 	#[structopt(parse(from_os_str))]
 	db_path: PathBuf,
 	#[structopt(long, default_value = "3")]
-	share_number: usize
+	share_number: usize,
+	/// CA certificate (PEM) to verify servers with an https address against
+	#[structopt(long, parse(from_os_str))]
+	ca_cert: Option<PathBuf>,
 }
 
 fn main() -> anyhow::Result<()> {
 	let opt = Opt::from_args();
 	let mut client = Client::new(opt.db_path, opt.share_number)?;
+	if let Some(path) = opt.ca_cert {
+		client.trust_ca(path)?;
+	}
 	client.connect(opt.server)?;
 
 	let host_uuid = client.get_uuid();